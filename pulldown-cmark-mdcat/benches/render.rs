@@ -0,0 +1,85 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Benchmarks for rendering.
+//!
+//! Render a large document without any resource access, to measure only the cost of rendering,
+//! in particular of wrapping and highlighting.
+
+#![deny(warnings, clippy::all)]
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use pulldown_cmark::Parser;
+use syntect::parsing::SyntaxSet;
+use url::Url;
+
+use pulldown_cmark_mdcat::resources::NoopResourceHandler;
+use pulldown_cmark_mdcat::terminal::{TerminalProgram, TerminalSize};
+use pulldown_cmark_mdcat::{default_parser_options, push_tty, Environment, Settings};
+
+/// How often to repeat the sample document, to get a large document.
+const REPETITIONS: usize = 50;
+
+fn document() -> String {
+    include_str!("document.md").repeat(REPETITIONS)
+}
+
+fn settings(program: TerminalProgram, syntax_set: &SyntaxSet) -> Settings<'_> {
+    Settings::new(
+        program.capabilities(),
+        TerminalSize::new(80, 24),
+        syntax_set,
+    )
+}
+
+fn render(settings: &Settings, environment: &Environment, markdown: &str) -> Vec<u8> {
+    let parser = Parser::new_ext(markdown, default_parser_options());
+    let mut sink = Vec::with_capacity(markdown.len() * 2);
+    push_tty(
+        settings,
+        environment,
+        &NoopResourceHandler,
+        &mut sink,
+        parser,
+    )
+    .unwrap();
+    sink
+}
+
+fn bench_render(c: &mut Criterion) {
+    let markdown = document();
+    let syntax_set = SyntaxSet::load_defaults_newlines();
+    // A fixed environment, to avoid looking up the hostname
+    let environment = Environment {
+        base_url: Url::parse("file:///").unwrap(),
+        hostname: "localhost".to_owned(),
+    };
+
+    let mut group = c.benchmark_group("render");
+    group.throughput(Throughput::Bytes(markdown.len() as u64));
+    // Without styles rendering mostly measures wrapping
+    let dumb = settings(TerminalProgram::Dumb, &syntax_set);
+    group.bench_function("dumb", |b| {
+        b.iter(|| render(&dumb, &environment, black_box(&markdown)))
+    });
+    // With styles, rendering also highlights code blocks
+    let ansi = settings(TerminalProgram::Ansi, &syntax_set);
+    group.bench_function("ansi", |b| {
+        b.iter(|| render(&ansi, &environment, black_box(&markdown)))
+    });
+    // Narrow terminals wrap more often
+    let mut narrow = settings(TerminalProgram::Ansi, &syntax_set);
+    narrow.terminal_size = TerminalSize::new(40, 24);
+    group.bench_function("ansi-narrow", |b| {
+        b.iter(|| render(&narrow, &environment, black_box(&markdown)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_render);
+criterion_main!(benches);
@@ -0,0 +1,226 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Turn bare URLs in text into links.
+//!
+//! See [`autolink_literals`].
+
+use std::collections::VecDeque;
+
+use pulldown_cmark::{CowStr, Event, LinkType, Tag, TagEnd, TextMergeStream};
+
+/// Turn bare URLs in text into autolinks, like GFM autolink literals.
+///
+/// pulldown-cmark does not support the GFM extension for autolink literals, so this adapter finds
+/// URLs starting with `http://`, `https://`, or `www.` in text events, and wraps them in links of
+/// type [`LinkType::Autolink`].  mdcat renders these like `<https://example.com>`, i.e. as inline
+/// links on terminals which support links, and without a link reference otherwise.
+///
+/// Like GFM this adapter strips trailing punctuation and unbalanced closing parentheses from URLs,
+/// and leaves text in code blocks, links, and images alone.
+///
+/// ```
+/// # use pulldown_cmark::{Event, LinkType, Parser, Tag, TagEnd};
+/// # use pulldown_cmark_mdcat::autolinks::autolink_literals;
+/// let events = autolink_literals(Parser::new("Visit https://example.com.")).collect::<Vec<_>>();
+/// assert_eq!(
+///     events,
+///     vec![
+///         Event::Start(Tag::Paragraph),
+///         Event::Text("Visit ".into()),
+///         Event::Start(Tag::Link {
+///             link_type: LinkType::Autolink,
+///             dest_url: "https://example.com".into(),
+///             title: "".into(),
+///             id: "".into(),
+///         }),
+///         Event::Text("https://example.com".into()),
+///         Event::End(TagEnd::Link),
+///         Event::Text(".".into()),
+///         Event::End(TagEnd::Paragraph),
+///     ]
+/// );
+/// ```
+pub fn autolink_literals<'a, I>(events: I) -> AutolinkLiterals<'a, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    AutolinkLiterals {
+        events: TextMergeStream::new(events),
+        pending: VecDeque::new(),
+        literal_depth: 0,
+    }
+}
+
+/// An iterator which turns bare URLs in text into links.
+///
+/// See [`autolink_literals`].
+pub struct AutolinkLiterals<'a, I: Iterator<Item = Event<'a>>> {
+    events: TextMergeStream<'a, I>,
+    /// Events to emit before reading further events.
+    pending: VecDeque<Event<'a>>,
+    /// How many blocks we're in whose text must not contain links, e.g. code blocks or links.
+    literal_depth: usize,
+}
+
+impl<'a, I: Iterator<Item = Event<'a>>> Iterator for AutolinkLiterals<'a, I> {
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.pending.pop_front() {
+            return Some(event);
+        }
+        let event = self.events.next()?;
+        match &event {
+            Event::Start(
+                Tag::CodeBlock(_) | Tag::Link { .. } | Tag::Image { .. } | Tag::MetadataBlock(_),
+            ) => self.literal_depth += 1,
+            Event::End(
+                TagEnd::CodeBlock | TagEnd::Link | TagEnd::Image | TagEnd::MetadataBlock(_),
+            ) => self.literal_depth = self.literal_depth.saturating_sub(1),
+            Event::Text(text) if self.literal_depth == 0 && find_url(text).is_some() => {
+                let mut rest = text.as_ref();
+                while let Some((start, end)) = find_url(rest) {
+                    let url = &rest[start..end];
+                    let dest_url = if url.starts_with("www.") {
+                        format!("http://{url}")
+                    } else {
+                        url.to_owned()
+                    };
+                    if 0 < start {
+                        self.pending
+                            .push_back(Event::Text(CowStr::from(rest[..start].to_owned())));
+                    }
+                    self.pending.push_back(Event::Start(Tag::Link {
+                        link_type: LinkType::Autolink,
+                        dest_url: dest_url.into(),
+                        title: CowStr::Borrowed(""),
+                        id: CowStr::Borrowed(""),
+                    }));
+                    self.pending
+                        .push_back(Event::Text(CowStr::from(url.to_owned())));
+                    self.pending.push_back(Event::End(TagEnd::Link));
+                    rest = &rest[end..];
+                }
+                if !rest.is_empty() {
+                    self.pending
+                        .push_back(Event::Text(CowStr::from(rest.to_owned())));
+                }
+                return self.pending.pop_front();
+            }
+            _ => {}
+        }
+        Some(event)
+    }
+}
+
+/// Whether `c` may precede an autolink literal.
+fn is_url_boundary(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '*' | '_' | '~' | '(')
+}
+
+/// Whether `host` is a valid domain for an autolink literal.
+///
+/// Require at least two segments of alphanumeric characters, dashes or underscores, and no
+/// underscores in the last two segments, like GFM does.
+fn is_valid_domain(host: &str) -> bool {
+    let segments = host.split('.').collect::<Vec<_>>();
+    2 <= segments.len()
+        && segments.iter().all(|segment| {
+            !segment.is_empty()
+                && segment
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        })
+        && segments[segments.len() - 2..]
+            .iter()
+            .all(|segment| !segment.contains('_'))
+}
+
+/// Strip trailing punctuation and unbalanced closing parentheses from `url`.
+fn trim_url_end(url: &str) -> &str {
+    let mut url = url;
+    loop {
+        if let Some(trimmed) = url.strip_suffix(['?', '!', '.', ',', ':', '*', '_', '~', '\'', '"'])
+        {
+            url = trimmed;
+        } else if url.ends_with(')') && url.matches('(').count() < url.matches(')').count() {
+            url = &url[..url.len() - 1];
+        } else {
+            return url;
+        }
+    }
+}
+
+/// Find the first URL in `text`.
+///
+/// Return the start and end index of the URL in `text`, or `None` if `text` contains no URL.
+fn find_url(text: &str) -> Option<(usize, usize)> {
+    let mut previous = None;
+    for (start, c) in text.char_indices() {
+        if previous.is_none_or(is_url_boundary) {
+            let candidate = &text[start..];
+            let host_start = ["https://", "http://", "www."]
+                .iter()
+                .find(|prefix| candidate.starts_with(*prefix))
+                .map(|prefix| if *prefix == "www." { 0 } else { prefix.len() });
+            if let Some(host_start) = host_start {
+                let length = candidate
+                    .find(|c: char| c.is_whitespace() || c == '<')
+                    .unwrap_or(candidate.len());
+                let url = trim_url_end(&candidate[..length]);
+                let host = url[host_start..]
+                    .split(['/', '?', '#'])
+                    .next()
+                    .unwrap_or_default();
+                if is_valid_domain(host) {
+                    return Some((start, start + url.len()));
+                }
+            }
+        }
+        previous = Some(c);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_url;
+
+    fn url(text: &str) -> Option<&str> {
+        find_url(text).map(|(start, end)| &text[start..end])
+    }
+
+    #[test]
+    fn find_urls() {
+        assert_eq!(
+            url("visit https://example.com"),
+            Some("https://example.com")
+        );
+        assert_eq!(
+            url("http://example.com/foo?bar#baz"),
+            Some("http://example.com/foo?bar#baz")
+        );
+        assert_eq!(url("see www.example.com."), Some("www.example.com"));
+        assert_eq!(
+            url("(see https://en.wikipedia.org/wiki/Foo_(bar)), too"),
+            Some("https://en.wikipedia.org/wiki/Foo_(bar)")
+        );
+        assert_eq!(
+            url("at https://example.com/foo)."),
+            Some("https://example.com/foo")
+        );
+    }
+
+    #[test]
+    fn ignore_invalid_urls() {
+        assert_eq!(url("no link here"), None);
+        assert_eq!(url("https://localhost"), None);
+        assert_eq!(url("https://"), None);
+        assert_eq!(url("www.example_foo.com"), None);
+        assert_eq!(url("foohttps://example.com"), None);
+    }
+}
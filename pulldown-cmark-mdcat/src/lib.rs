@@ -14,7 +14,7 @@
 //!
 //! ## Features
 //!
-//! - `default` enables `svg` and `image-processing`.
+//! - `default` enables `svg`, `image-processing`, `half-blocks`, and `regex-fancy`.
 //!
 //! - `svg` includes support for rendering SVG images to PNG for terminals which do not support SVG
 //!   images natively.  This feature adds a dependency on `resvg`.
@@ -33,33 +33,66 @@
 //!
 //!   Please **do not report bugs** about inline image rendering with this feature disabled, unless
 //!   the issue can also be reproduced if the feature is enabled.
+//!
+//! - `half-blocks` draws images from Unicode half blocks for terminals with 24-bit colours but
+//!   without an image protocol, see [`terminal::capabilities::halfblock`].  This feature adds
+//!   dependencies on `png`, `zune-jpeg`, and `gif` to decode these image formats even without
+//!   `image-processing`.
+//!
+//! - `regex-fancy` highlights code with the pure Rust fancy-regex engine of syntect.
+//!
+//! - `regex-onig` highlights code with the oniguruma engine of syntect instead.  Disable default
+//!   features to build only oniguruma; if both features are enabled syntect uses oniguruma.
+//!
+//! syntect needs a regex engine, so enable at least one of `regex-fancy` and `regex-onig`.
 
 #![deny(warnings, missing_docs, clippy::all)]
 #![forbid(unsafe_code)]
 
+use std::ffi::OsString;
 use std::io::{Error, ErrorKind, Result, Write};
 use std::path::Path;
 
 use gethostname::gethostname;
-use pulldown_cmark::Event;
+use pulldown_cmark::{Event, Options};
 use syntect::parsing::SyntaxSet;
-use tracing::instrument;
+use tracing::{event, instrument, Level};
 use url::Url;
 
+use crate::references::UrlBase;
+use crate::sink::{AnsiSink, StyleSink};
+
 pub use crate::resources::ResourceUrlHandler;
 pub use crate::terminal::capabilities::TerminalCapabilities;
 pub use crate::terminal::{TerminalProgram, TerminalSize};
-pub use crate::theme::Theme;
+pub use crate::theme::{CodeBlockBorderWidth, Glyphs, Theme};
 
+pub mod autolinks;
 mod references;
 pub mod resources;
+pub mod segments;
+pub mod sink;
 pub mod terminal;
 mod theme;
 
 mod render;
 
+/// The name of the regex engine syntect uses for syntax highlighting.
+///
+/// This is `onig` if this crate was built with the `regex-onig` feature, and `fancy-regex`
+/// otherwise.  Highlighting results and performance differ between both engines.
+pub const SYNTECT_REGEX_ENGINE: &str = if cfg!(feature = "regex-onig") {
+    "onig"
+} else {
+    "fancy-regex"
+};
+
 /// Settings for markdown rendering.
+///
+/// Create settings with [`Settings::new`], and change optional settings with the `with_`
+/// methods.  mdcat adds new settings over time, so this struct is not exhaustive.
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct Settings<'a> {
     /// Capabilities of the terminal mdcat writes to.
     pub terminal_capabilities: TerminalCapabilities,
@@ -69,6 +102,293 @@ pub struct Settings<'a> {
     pub syntax_set: &'a SyntaxSet,
     /// Colour theme for mdcat
     pub theme: Theme,
+    /// Whether to infer the language of indented code blocks from their first line.
+    ///
+    /// Indented code blocks have no language token; if enabled, mdcat tries to detect their
+    /// language with [`SyntaxSet::find_syntax_by_first_line`], e.g. from a shebang line, and
+    /// highlights the block if it finds a syntax.  Disabled by default, because this may highlight
+    /// blocks with the wrong syntax.
+    pub infer_indented_code_syntax: bool,
+    /// Expand tabs in code blocks to spaces, up to the next multiple of this width.
+    ///
+    /// If `None` write tabs as they are, and leave their width up to the tab stops of the
+    /// terminal.
+    pub tab_width: Option<u16>,
+    /// Where to write link references, for links which mdcat cannot show inline.
+    pub link_reference_placement: LinkReferencePlacement,
+    /// Whether to write the dimensions and format of rendered images as a caption below the image.
+    ///
+    /// The caption looks like `(640×480, PNG)`.  mdcat needs to decode the image header to
+    /// obtain the dimensions, so this has no effect without the `image-processing` feature.
+    pub image_dimensions: bool,
+    /// Whether to render HTML `<img>` and `<a>` tags like markdown images and links.
+    ///
+    /// mdcat normally writes HTML literally.  If enabled, mdcat extracts the source and
+    /// description of `<img>` tags and the target of `<a>` tags, and renders them like markdown
+    /// images and links, and writes all other HTML literally.  This doesn't really parse HTML, so it
+    /// only works for simple tags.
+    ///
+    /// mdcat skips `<img>` tags without alt text, and only renders `<a>` tags as links if the
+    /// closing `</a>` tag follows at the same level of inline nesting.
+    pub render_html_images_and_links: bool,
+    /// Whether to log expected fallbacks for images only at debug level.
+    ///
+    /// If mdcat cannot render an image it writes a link to the image, or the image description,
+    /// and logs this at info or warning level.  If enabled, mdcat logs these fallbacks only at
+    /// debug level if the terminal doesn't support images at all, and reserves higher levels for
+    /// images which mdcat failed to render.
+    pub quiet_image_fallbacks: bool,
+    /// Whether to keep the line breaks of paragraphs.
+    ///
+    /// mdcat normally reflows paragraphs, i.e. joins the lines of a paragraph, and wraps the text at
+    /// the width of the terminal.  If enabled, mdcat treats soft breaks like hard breaks, to keep
+    /// the line structure of the source text, e.g. for documents with semantic line breaks.  mdcat
+    /// still wraps lines which do not fit into the terminal.
+    pub preserve_line_breaks: bool,
+    /// The syntax theme for highlighting code blocks, or `None` for the built-in theme.
+    ///
+    /// mdcat highlights code with a built-in Solarized theme by default, and maps its colours to
+    /// the basic ANSI colours, to fit the colour theme of the terminal.  Other themes use their
+    /// exact colours, so they require a terminal with 24-bit colours, and must fit the background
+    /// of the terminal.
+    pub syntax_theme: Option<&'a syntect::highlighting::Theme>,
+    /// The maximum width of the description of an image which mdcat cannot show, in columns.
+    ///
+    /// If mdcat cannot show an image inline, it writes its description, i.e. its alt text,
+    /// instead.  If set, mdcat truncates longer descriptions with an ellipsis, to keep long alt
+    /// texts from taking over the text around the image.  If `None`, mdcat writes the whole
+    /// description.
+    pub max_image_description_width: Option<u16>,
+    /// Whether to render the title of front matter as a top-level heading.
+    ///
+    /// mdcat never shows front matter itself; with this setting it extracts a simple `title`
+    /// from YAML or TOML front matter and writes it as a heading at the start of the document.
+    /// pulldown-cmark only emits front matter if enabled with
+    /// [`pulldown_cmark::Options::ENABLE_YAML_STYLE_METADATA_BLOCKS`] or
+    /// [`pulldown_cmark::Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS`].
+    pub render_front_matter_title: bool,
+    /// Languages of code blocks which contain diagrams, e.g. `mermaid` or `plantuml`.
+    ///
+    /// mdcat cannot render diagrams, and highlighting their source is rarely useful.  mdcat
+    /// writes code blocks in these languages as plain text, with a notice that it did not render
+    /// the diagram.  Compare languages case-insensitively.
+    pub diagram_languages: &'a [&'a str],
+    /// How to show the URLs of links.
+    ///
+    /// Autolinks, e.g. `<https://example.com>`, always show their URL as link text, and never get
+    /// a reference or another URL after the link text.
+    pub link_style: LinkStyle,
+    /// Whether to write the description of rendered images as caption below the image.
+    ///
+    /// mdcat only writes the description of images it cannot render, but the description helps
+    /// to understand an image, e.g. for users of screen readers, or when scrolling past an image.
+    pub image_alt_captions: bool,
+    /// Indent every line of output by this number of columns.
+    ///
+    /// Use this to embed rendered documents into other text.  mdcat wraps text within the columns
+    /// of the terminal which remain after the indent, and leaves empty lines empty.
+    pub indent: u16,
+}
+
+impl<'a> Settings<'a> {
+    /// Create settings for a terminal with the given capabilities and size.
+    ///
+    /// Highlight code with `syntax_set`, use the default theme, and disable all optional
+    /// features.  Use the `with_` methods to change further settings, e.g.
+    /// `Settings::new(capabilities, size, syntax_set).with_preserve_line_breaks(true)`.
+    pub fn new(
+        terminal_capabilities: TerminalCapabilities,
+        terminal_size: TerminalSize,
+        syntax_set: &'a SyntaxSet,
+    ) -> Self {
+        Self {
+            terminal_capabilities,
+            terminal_size,
+            syntax_set,
+            theme: Theme::default(),
+            infer_indented_code_syntax: false,
+            tab_width: None,
+            link_reference_placement: LinkReferencePlacement::default(),
+            image_dimensions: false,
+            render_html_images_and_links: false,
+            quiet_image_fallbacks: false,
+            preserve_line_breaks: false,
+            syntax_theme: None,
+            max_image_description_width: None,
+            render_front_matter_title: false,
+            diagram_languages: &[],
+            link_style: LinkStyle::Reference,
+            image_alt_captions: false,
+            indent: 0,
+        }
+    }
+
+    /// Use the given colour `theme`.
+    pub fn with_theme(self, theme: Theme) -> Self {
+        Self { theme, ..self }
+    }
+
+    /// Set whether to infer the language of indented code blocks.
+    ///
+    /// See [`Settings::infer_indented_code_syntax`].
+    pub fn with_infer_indented_code_syntax(self, infer: bool) -> Self {
+        Self {
+            infer_indented_code_syntax: infer,
+            ..self
+        }
+    }
+
+    /// Set the width to expand tabs in code blocks to.
+    ///
+    /// See [`Settings::tab_width`].
+    pub fn with_tab_width(self, tab_width: Option<u16>) -> Self {
+        Self { tab_width, ..self }
+    }
+
+    /// Set where to write link references.
+    pub fn with_link_reference_placement(self, placement: LinkReferencePlacement) -> Self {
+        Self {
+            link_reference_placement: placement,
+            ..self
+        }
+    }
+
+    /// Set whether to write the dimensions of rendered images as caption.
+    ///
+    /// See [`Settings::image_dimensions`].
+    pub fn with_image_dimensions(self, enabled: bool) -> Self {
+        Self {
+            image_dimensions: enabled,
+            ..self
+        }
+    }
+
+    /// Set whether to render HTML `<img>` and `<a>` tags like images and links.
+    ///
+    /// See [`Settings::render_html_images_and_links`].
+    pub fn with_render_html_images_and_links(self, enabled: bool) -> Self {
+        Self {
+            render_html_images_and_links: enabled,
+            ..self
+        }
+    }
+
+    /// Set whether to log expected image fallbacks only at debug level.
+    ///
+    /// See [`Settings::quiet_image_fallbacks`].
+    pub fn with_quiet_image_fallbacks(self, quiet: bool) -> Self {
+        Self {
+            quiet_image_fallbacks: quiet,
+            ..self
+        }
+    }
+
+    /// Set whether to keep the line breaks of paragraphs.
+    ///
+    /// See [`Settings::preserve_line_breaks`].
+    pub fn with_preserve_line_breaks(self, preserve: bool) -> Self {
+        Self {
+            preserve_line_breaks: preserve,
+            ..self
+        }
+    }
+
+    /// Highlight code with the given syntax `theme` instead of the built-in theme.
+    ///
+    /// See [`Settings::syntax_theme`].
+    pub fn with_syntax_theme(self, theme: Option<&'a syntect::highlighting::Theme>) -> Self {
+        Self {
+            syntax_theme: theme,
+            ..self
+        }
+    }
+
+    /// Set the maximum width of descriptions of images mdcat cannot show.
+    ///
+    /// See [`Settings::max_image_description_width`].
+    pub fn with_max_image_description_width(self, width: Option<u16>) -> Self {
+        Self {
+            max_image_description_width: width,
+            ..self
+        }
+    }
+
+    /// Set whether to render the title of front matter as heading.
+    ///
+    /// See [`Settings::render_front_matter_title`].
+    pub fn with_render_front_matter_title(self, enabled: bool) -> Self {
+        Self {
+            render_front_matter_title: enabled,
+            ..self
+        }
+    }
+
+    /// Set the languages of code blocks which contain diagrams.
+    ///
+    /// See [`Settings::diagram_languages`].
+    pub fn with_diagram_languages(self, languages: &'a [&'a str]) -> Self {
+        Self {
+            diagram_languages: languages,
+            ..self
+        }
+    }
+
+    /// Set how to show the URLs of links.
+    pub fn with_link_style(self, style: LinkStyle) -> Self {
+        Self {
+            link_style: style,
+            ..self
+        }
+    }
+
+    /// Set whether to write the description of rendered images as caption.
+    ///
+    /// See [`Settings::image_alt_captions`].
+    pub fn with_image_alt_captions(self, enabled: bool) -> Self {
+        Self {
+            image_alt_captions: enabled,
+            ..self
+        }
+    }
+
+    /// Indent every line of output by `indent` columns.
+    ///
+    /// See [`Settings::indent`].
+    pub fn with_indent(self, indent: u16) -> Self {
+        Self { indent, ..self }
+    }
+}
+
+/// Where to write the list of link references.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum LinkReferencePlacement {
+    /// Write link references at the end of each section, i.e. before every top-level heading,
+    /// and at the end of the document.
+    #[default]
+    PerSection,
+    /// Write all link references at the end of the document, like footnotes.
+    DocumentEnd,
+}
+
+/// How to show the URLs of links.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum LinkStyle {
+    /// Refer to the URL of a link with a reference number after the link text, and list the URLs
+    /// of all references, see [`LinkReferencePlacement`].
+    ///
+    /// Terminals which support hyperlinks show no reference, because the link text itself links
+    /// to the URL.
+    #[default]
+    Reference,
+    /// Show the URL in parentheses right after the link text.
+    ///
+    /// Unlike references this shows the URL even on terminals which support hyperlinks.
+    Inline,
+    /// Show only the link text, and never the URL.
+    ///
+    /// On terminals which support hyperlinks the link text still links to the URL, but on other
+    /// terminals the URL is lost.
+    Hidden,
 }
 
 /// The environment to render markdown in.
@@ -83,17 +403,33 @@ pub struct Environment {
 impl Environment {
     /// Create an environment for the local host with the given `base_url`.
     ///
-    /// Take the local hostname from `gethostname`.
+    /// Take the local hostname from `gethostname`, or use `localhost` if the hostname is empty or
+    /// not a valid host.
     pub fn for_localhost(base_url: Url) -> Result<Self> {
-        gethostname()
-            .into_string()
-            .map_err(|raw| {
-                Error::new(
-                    ErrorKind::InvalidData,
-                    format!("gethostname() returned invalid unicode data: {raw:?}"),
-                )
-            })
-            .map(|hostname| Environment { base_url, hostname })
+        Self::for_raw_hostname(base_url, gethostname())
+    }
+
+    /// Create an environment with the given `base_url` and the `raw` hostname of the local host.
+    fn for_raw_hostname(base_url: Url, raw: OsString) -> Result<Self> {
+        let hostname = raw.into_string().map_err(|raw| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("gethostname() returned invalid unicode data: {raw:?}"),
+            )
+        })?;
+        let hostname = match url::Host::parse(&hostname) {
+            Ok(_) => hostname,
+            Err(error) => {
+                event!(
+                    Level::WARN,
+                    ?hostname,
+                    %error,
+                    "gethostname() returned an invalid hostname, using localhost instead"
+                );
+                "localhost".to_owned()
+            }
+        };
+        Ok(Environment { base_url, hostname })
     }
 
     /// Create an environment for a local directory.
@@ -115,6 +451,64 @@ impl Environment {
             })
             .and_then(Self::for_localhost)
     }
+
+    /// Use the given `hostname` for `file://` links instead of the hostname from `gethostname`.
+    ///
+    /// mdcat adds the hostname to all `file://` URLs in OSC 8 links.  Terminals compare this
+    /// hostname with the name of the machine they run on, and only open the file if both match;
+    /// some terminals hand over links to other hosts to a URL handler.  Over SSH the terminal runs
+    /// on the client, so set the hostname under which the client knows the remote machine to
+    /// make file links resolve correctly.
+    ///
+    /// Return an IO error with `ErrorKind::InvalidInput` if `hostname` is not a valid host, e.g.
+    /// empty.
+    pub fn with_hostname(self, hostname: &str) -> Result<Self> {
+        let host = url::Host::parse(hostname).map_err(|error| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid hostname {hostname:?}: {error}"),
+            )
+        })?;
+        Ok(Self {
+            hostname: host.to_string(),
+            ..self
+        })
+    }
+
+    /// Resolve a `reference` from a document against the `base_url` of this environment.
+    ///
+    /// Return `reference` as URL if it's absolute, and otherwise join it to the base URL.  Return
+    /// `None` if `reference` is neither an absolute nor a relative URL.
+    pub fn resolve_reference(&self, reference: &str) -> Option<Url> {
+        self.base_url.resolve_reference(reference)
+    }
+}
+
+/// The parser options which mdcat enables by default.
+///
+/// Enable GFM tables, task lists, and strikethrough.  Parse markdown with these options to render
+/// documents like `mdcat` does by default.
+///
+/// [`push_tty`] also supports math, definition lists, and front matter, i.e.
+/// [`Options::ENABLE_MATH`], [`Options::ENABLE_DEFINITION_LIST`],
+/// [`Options::ENABLE_YAML_STYLE_METADATA_BLOCKS`], and
+/// [`Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS`], but these options change the meaning
+/// of common markdown, e.g. dollar signs, so mdcat does not enable them by default.
+pub fn default_parser_options() -> Options {
+    Options::ENABLE_TABLES | Options::ENABLE_TASKLISTS | Options::ENABLE_STRIKETHROUGH
+}
+
+/// What mdcat found while rendering a document.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RenderSummary {
+    /// Languages of fenced code blocks without a syntax in [`Settings::syntax_set`], in order of
+    /// first appearance.
+    ///
+    /// mdcat writes code blocks with unknown languages without highlighting; list these
+    /// languages to help authors find typos in language tokens.  This does not include diagram
+    /// languages, see [`Settings::diagram_languages`].
+    pub unknown_languages: Vec<String>,
 }
 
 /// Write markdown to a TTY.
@@ -125,38 +519,206 @@ impl Environment {
 ///
 /// `push_tty` tries to limit output to the given number of TTY `columns` but
 /// does not guarantee that output stays within the column limit.
+///
+/// `push_tty` always writes ordered list items as `1.`, even if the source uses
+/// `1)`, because `events` do not tell the delimiter of list items.
+///
+/// `push_tty` never leaves a style active at the end of output, even if
+/// rendering fails.  It returns an error if `events` end before all blocks are
+/// closed.
+///
+/// `push_tty` returns all IO errors of `writer` as they are, including
+/// [`ErrorKind::BrokenPipe`] if `writer` is a pipe whose reader exited early,
+/// e.g. `head`.  Use [`ignore_broken_pipe`] to treat these as success.
+///
+/// On success `push_tty` returns a [`RenderSummary`] of the document.
 #[instrument(level = "debug", skip_all, fields(environment.hostname = environment.hostname.as_str(), environment.base_url = &environment.base_url.as_str()))]
 pub fn push_tty<'a, 'e, W, I>(
     settings: &Settings,
     environment: &Environment,
     resource_handler: &dyn ResourceUrlHandler,
     writer: &'a mut W,
-    mut events: I,
-) -> Result<()>
+    events: I,
+) -> Result<RenderSummary>
 where
     I: Iterator<Item = Event<'e>>,
     W: Write,
 {
-    use render::*;
-    let StateAndData(final_state, final_data) = events.try_fold(
-        StateAndData(State::default(), StateData::default()),
-        |StateAndData(state, data), event| {
-            write_event(
-                writer,
-                settings,
-                environment,
-                &resource_handler,
-                state,
-                data,
-                event,
-            )
-        },
-    )?;
-    finish(writer, settings, environment, final_state, final_data)
+    push_styled(
+        settings,
+        environment,
+        resource_handler,
+        &mut AnsiSink::new(writer),
+        events,
+    )
+}
+
+/// Write markdown to a style sink.
+///
+/// Like [`push_tty`], but write styled text to `sink` instead of styling it with ANSI escape
+/// sequences, e.g. to capture styled text for a TUI library with
+/// [`segments::SegmentWriter`].  [`push_tty`] writes to an [`AnsiSink`].
+///
+/// `sink` only receives styled text if the terminal capabilities of `settings` support styles.
+pub fn push_styled<'e, S, I>(
+    settings: &Settings,
+    environment: &Environment,
+    resource_handler: &dyn ResourceUrlHandler,
+    sink: &mut S,
+    events: I,
+) -> Result<RenderSummary>
+where
+    I: Iterator<Item = Event<'e>>,
+    S: StyleSink,
+{
+    let mut renderer = Renderer::new(settings, environment, resource_handler);
+    for (event, lookahead) in render::LookaheadEvents::new(events, settings.tab_width) {
+        renderer.render_with_lookahead(sink, event, lookahead)?;
+    }
+    renderer.finish_styled(sink)
+}
+
+/// Treat a broken pipe as success.
+///
+/// Return `Ok(())` if `result` failed with [`ErrorKind::BrokenPipe`], and `result` otherwise.
+///
+/// Writing to a pipe fails with a broken pipe if the reader exited early, e.g. `head` after
+/// reading enough lines.  This is usually not an error for command line tools, so mdcat uses
+/// this function to silently ignore these errors from [`push_tty`].
+///
+/// ```
+/// # use std::io::{Error, ErrorKind};
+/// # use pulldown_cmark_mdcat::ignore_broken_pipe;
+/// assert!(ignore_broken_pipe(Err(Error::from(ErrorKind::BrokenPipe))).is_ok());
+/// assert!(ignore_broken_pipe(Err(Error::from(ErrorKind::NotFound))).is_err());
+/// ```
+pub fn ignore_broken_pipe(result: Result<()>) -> Result<()> {
+    match result {
+        Err(error) if error.kind() == ErrorKind::BrokenPipe => {
+            event!(Level::TRACE, "Ignoring broken pipe");
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Render markdown events one at a time.
+///
+/// Use this renderer to feed events from an existing event loop to mdcat; [`push_tty`] renders
+/// all events of an iterator at once.
+///
+/// Unlike [`push_tty`] this renderer cannot look ahead, so it does not align the numbers of
+/// ordered lists to the largest number in the list, and does not size borders of code blocks to
+/// their contents.
+///
+/// ```
+/// # use pulldown_cmark::Parser;
+/// # use pulldown_cmark_mdcat::resources::NoopResourceHandler;
+/// # use pulldown_cmark_mdcat::*;
+/// # use syntect::parsing::SyntaxSet;
+/// let syntax_set = SyntaxSet::default();
+/// let settings = Settings::new(
+///     TerminalProgram::Dumb.capabilities(),
+///     TerminalSize::default(),
+///     &syntax_set,
+/// );
+/// let environment = Environment::for_local_directory(&std::env::current_dir()?)?;
+/// let mut renderer = Renderer::new(&settings, &environment, &NoopResourceHandler);
+/// let mut output = Vec::new();
+/// for event in Parser::new("Hello *World*") {
+///     renderer.render(&mut output, event)?;
+/// }
+/// renderer.finish(&mut output)?;
+/// assert_eq!(String::from_utf8_lossy(&output), "Hello World\n");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Renderer<'a, 'e> {
+    settings: &'a Settings<'a>,
+    environment: &'a Environment,
+    resource_handler: &'a dyn ResourceUrlHandler,
+    state: render::State,
+    data: render::StateData<'e>,
+}
+
+impl<'a, 'e> Renderer<'a, 'e> {
+    /// Create a new renderer with the given `settings`, `environment`, and `resource_handler`.
+    pub fn new(
+        settings: &'a Settings<'a>,
+        environment: &'a Environment,
+        resource_handler: &'a dyn ResourceUrlHandler,
+    ) -> Self {
+        Self {
+            settings,
+            environment,
+            resource_handler,
+            state: render::State::default(),
+            data: render::StateData::default(),
+        }
+    }
+
+    /// Render a single `event` to `writer`.
+    ///
+    /// If rendering fails reset all styles, and start over at the top level of a document.
+    pub fn render<W: Write>(&mut self, writer: &mut W, event: Event<'e>) -> Result<()> {
+        self.render_styled(&mut AnsiSink::new(writer), event)
+    }
+
+    /// Render a single `event` to a style `sink`.
+    ///
+    /// Like [`Renderer::render`], but write styled text to `sink`, see [`push_styled`].
+    pub fn render_styled<S: StyleSink>(&mut self, sink: &mut S, event: Event<'e>) -> Result<()> {
+        self.render_with_lookahead(sink, event, render::Lookahead::default())
+    }
+
+    fn render_with_lookahead<S: StyleSink>(
+        &mut self,
+        writer: &mut S,
+        event: Event<'e>,
+        lookahead: render::Lookahead,
+    ) -> Result<()> {
+        let state = std::mem::take(&mut self.state);
+        let data = std::mem::take(&mut self.data);
+        match render::write_event(
+            writer,
+            self.settings,
+            self.environment,
+            &self.resource_handler,
+            state,
+            data.lookahead(lookahead),
+            event,
+        ) {
+            Ok(render::StateAndData(state, data)) => {
+                self.state = state;
+                self.data = data;
+                Ok(())
+            }
+            Err(error) => {
+                render::reset_after_error(writer, self.settings);
+                Err(error)
+            }
+        }
+    }
+
+    /// Finish rendering, and write all remaining output to `writer`.
+    ///
+    /// Write pending link references, and return a summary of the document.  Return an error if
+    /// rendering ends before all blocks are closed, and reset all styles which are still active.
+    pub fn finish<W: Write>(self, writer: &mut W) -> Result<RenderSummary> {
+        self.finish_styled(&mut AnsiSink::new(writer))
+    }
+
+    /// Finish rendering, and write all remaining output to a style `sink`.
+    ///
+    /// Like [`Renderer::finish`], but write styled text to `sink`, see [`push_styled`].
+    pub fn finish_styled<S: StyleSink>(self, sink: &mut S) -> Result<RenderSummary> {
+        render::finish(sink, self.settings, self.environment, self.state, self.data)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::LazyLock;
+
     use pulldown_cmark::Parser;
 
     use crate::resources::NoopResourceHandler;
@@ -172,21 +734,159 @@ mod tests {
         Ok(String::from_utf8_lossy(&sink).into())
     }
 
-    fn render_string_dumb(markup: &str) -> Result<String> {
-        render_string(
-            markup,
-            &Settings {
-                syntax_set: &SyntaxSet::default(),
-                terminal_capabilities: TerminalProgram::Dumb.capabilities(),
-                terminal_size: TerminalSize::default(),
-                theme: Theme::default(),
-            },
+    /// Settings for a dumb terminal of default size, without any syntax definitions.
+    pub(crate) fn test_settings() -> Settings<'static> {
+        static SYNTAX_SET: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::default);
+        Settings::new(
+            TerminalProgram::Dumb.capabilities(),
+            TerminalSize::default(),
+            &SYNTAX_SET,
         )
     }
 
+    fn render_string_dumb(markup: &str) -> Result<String> {
+        render_string(markup, &test_settings())
+    }
+
+    mod renderer {
+        use super::{render_string_dumb, test_settings};
+        use crate::resources::NoopResourceHandler;
+        use crate::*;
+        use pulldown_cmark::Parser;
+
+        #[test]
+        fn render_events_one_at_a_time() {
+            let markup = "# Hello\n\n> A [link][1]\n\n* one\n* two\n\n[1]: http://example.com";
+            let settings = test_settings();
+            let env = Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+            let mut renderer = Renderer::new(&settings, &env, &NoopResourceHandler);
+            let mut sink = Vec::new();
+            for event in Parser::new(markup) {
+                renderer.render(&mut sink, event).unwrap();
+            }
+            renderer.finish(&mut sink).unwrap();
+            assert_eq!(
+                String::from_utf8_lossy(&sink),
+                render_string_dumb(markup).unwrap()
+            );
+        }
+
+        #[test]
+        fn finish_with_unclosed_blocks_fails() {
+            let settings = test_settings();
+            let env = Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+            let mut renderer = Renderer::new(&settings, &env, &NoopResourceHandler);
+            let mut sink = Vec::new();
+            for event in Parser::new("> quote").take(2) {
+                renderer.render(&mut sink, event).unwrap();
+            }
+            assert!(renderer.finish(&mut sink).is_err());
+        }
+
+        #[test]
+        fn impossible_event_fails() {
+            let settings = test_settings();
+            let env = Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+            let mut renderer = Renderer::new(&settings, &env, &NoopResourceHandler);
+            let mut sink = Vec::new();
+            let error = renderer
+                .render(&mut sink, Event::Text("hi".into()))
+                .unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        }
+
+        #[test]
+        fn finish_returns_unknown_languages() {
+            // The test settings have no syntax definitions, so all languages are unknown
+            let markup = "```rsut\n```\n\n```\nplain\n```\n\n```rust\n```\n\n```rsut\n```\n";
+            let settings = test_settings();
+            let env = Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+            let mut renderer = Renderer::new(&settings, &env, &NoopResourceHandler);
+            let mut sink = Vec::new();
+            for event in Parser::new(markup) {
+                renderer.render(&mut sink, event).unwrap();
+            }
+            let summary = renderer.finish(&mut sink).unwrap();
+            assert_eq!(summary.unknown_languages, vec!["rsut", "rust"]);
+        }
+    }
+
+    mod environment {
+        use crate::terminal::capabilities::Passthrough;
+        use crate::terminal::osc::set_link_url;
+        use crate::Environment;
+        use std::ffi::OsString;
+        use std::io::ErrorKind;
+        use url::Url;
+
+        #[test]
+        fn with_hostname_sets_host_of_file_links() {
+            let env = Environment::for_local_directory(&std::env::current_dir().unwrap())
+                .unwrap()
+                .with_hostname("client-visible.example.com")
+                .unwrap();
+            assert_eq!(env.hostname, "client-visible.example.com");
+            let mut sink = Vec::new();
+            set_link_url(
+                &mut sink,
+                Passthrough::Direct,
+                Url::parse("file:///foo/bar.md").unwrap(),
+                &env.hostname,
+            )
+            .unwrap();
+            assert_eq!(
+                String::from_utf8(sink).unwrap(),
+                "\u{1b}]8;;file://client-visible.example.com/foo/bar.md\u{1b}\\"
+            );
+        }
+
+        #[test]
+        fn empty_local_hostname_falls_back_to_localhost() {
+            let base_url = Url::from_directory_path(std::env::current_dir().unwrap()).unwrap();
+            let env = Environment::for_raw_hostname(base_url, OsString::new()).unwrap();
+            assert_eq!(env.hostname, "localhost");
+            let mut sink = Vec::new();
+            set_link_url(
+                &mut sink,
+                Passthrough::Direct,
+                Url::parse("file:///foo/bar.md").unwrap(),
+                &env.hostname,
+            )
+            .unwrap();
+            assert_eq!(
+                String::from_utf8(sink).unwrap(),
+                "\u{1b}]8;;file://localhost/foo/bar.md\u{1b}\\"
+            );
+            // An explicit hostname still takes precedence
+            let env = env.with_hostname("client-visible.example.com").unwrap();
+            assert_eq!(env.hostname, "client-visible.example.com");
+        }
+
+        #[test]
+        fn invalid_local_hostname_falls_back_to_localhost() {
+            let base_url = Url::from_directory_path(std::env::current_dir().unwrap()).unwrap();
+            let env = Environment::for_raw_hostname(base_url, "foo bar".into()).unwrap();
+            assert_eq!(env.hostname, "localhost");
+        }
+
+        #[test]
+        fn with_hostname_rejects_invalid_hostnames() {
+            for hostname in ["", "foo bar", "foo/bar"] {
+                let env =
+                    Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+                let error = env.with_hostname(hostname).unwrap_err();
+                assert_eq!(error.kind(), ErrorKind::InvalidInput, "{hostname:?}");
+            }
+        }
+    }
+
     mod layout {
-        use super::render_string_dumb;
+        use super::{render_string, render_string_dumb, test_settings};
+        use crate::{
+            LinkReferencePlacement, LinkStyle, Settings, TerminalProgram, TerminalSize, Theme,
+        };
         use insta::assert_snapshot;
+        use syntect::parsing::SyntaxSet;
 
         #[test]
         #[allow(non_snake_case)]
@@ -197,6 +897,41 @@ mod tests {
             )
         }
 
+        #[test]
+        fn strip_control_characters() {
+            let settings = Settings {
+                syntax_set: &SyntaxSet::default(),
+                terminal_capabilities: TerminalProgram::Dumb.capabilities(),
+                terminal_size: TerminalSize {
+                    columns: 20,
+                    ..TerminalSize::default()
+                },
+                theme: Theme::default(),
+                infer_indented_code_syntax: false,
+                tab_width: None,
+                link_reference_placement: LinkReferencePlacement::default(),
+                image_dimensions: false,
+                render_html_images_and_links: false,
+                quiet_image_fallbacks: false,
+                preserve_line_breaks: false,
+                syntax_theme: None,
+                max_image_description_width: None,
+                render_front_matter_title: false,
+                diagram_languages: &[],
+                link_style: LinkStyle::Reference,
+                image_alt_captions: false,
+                indent: 0,
+            };
+            assert_eq!(
+                render_string(
+                    "Some\x1b[31m text\x07 with\r \u{9b}control\u{200B} characters \u{feff}`and\x08 code`\n\n```\nfn\tmain()\x1b[2J {}\n```",
+                    &settings
+                )
+                .unwrap(),
+                "Some[31m text with\ncontrol\u{200B} characters \u{feff}\nand code\n\n────────────────────\nfn\tmain()[2J {}\n────────────────────\n"
+            );
+        }
+
         #[test]
         fn begins_with_rule() {
             assert_snapshot!(render_string_dumb("----").unwrap())
@@ -217,6 +952,80 @@ mod tests {
             .unwrap());
         }
 
+        #[test]
+        fn rule_in_nested_list() {
+            assert_snapshot!(render_string_dumb(
+                "* Item
+
+  ----
+
+  * Nested item
+
+    ----"
+            )
+            .unwrap())
+        }
+
+        #[test]
+        fn rule_in_nested_list_on_narrow_terminal() {
+            let settings = Settings {
+                terminal_size: TerminalSize::default().with_max_columns(3),
+                ..test_settings()
+            };
+            let output = render_string("* a\n\n  * b\n\n    ----", &settings).unwrap();
+            // The rule still starts at the indentation of the item, with a minimum width
+            assert!(output.ends_with("\n\n    \u{2550}\n"), "{output:?}");
+        }
+
+        #[test]
+        fn zero_columns() {
+            let settings = Settings {
+                syntax_set: &SyntaxSet::default(),
+                terminal_capabilities: TerminalProgram::Dumb.capabilities(),
+                terminal_size: TerminalSize {
+                    columns: 0,
+                    ..TerminalSize::default()
+                },
+                theme: Theme::default(),
+                infer_indented_code_syntax: false,
+                tab_width: None,
+                link_reference_placement: LinkReferencePlacement::default(),
+                image_dimensions: false,
+                render_html_images_and_links: false,
+                quiet_image_fallbacks: false,
+                preserve_line_breaks: false,
+                syntax_theme: None,
+                max_image_description_width: None,
+                render_front_matter_title: false,
+                diagram_languages: &[],
+                link_style: LinkStyle::Reference,
+                image_alt_captions: false,
+                indent: 0,
+            };
+            let markup = "# Heading
+
+Some text
+
+> Quoted text
+>
+> ----
+>
+> ```
+> code
+> ```
+
+* Item
+
+  * Nested item with [a link](https://example.com)
+
+    ----
+
+| A | B |
+|---|---|
+| 1 | 2 |";
+            assert_snapshot!(render_string(markup, &settings).unwrap());
+        }
+
         #[test]
         fn heading_in_block_quote() {
             assert_snapshot!(render_string_dumb(
@@ -240,6 +1049,30 @@ mod tests {
             .unwrap())
         }
 
+        #[test]
+        fn reference_without_title() {
+            assert_eq!(
+                render_string_dumb("A [link](http://example.com)").unwrap(),
+                "A link[1]\n\n[1]: http://example.com\n"
+            )
+        }
+
+        #[test]
+        fn reference_with_quoted_title() {
+            assert_eq!(
+                render_string_dumb("A [link](http://example.com \"The title\")").unwrap(),
+                "A link[1]\n\n[1]: http://example.com \"The title\"\n"
+            )
+        }
+
+        #[test]
+        fn image_reference_with_quoted_title() {
+            assert_eq!(
+                render_string_dumb("An ![image](http://example.com/i.png \"The title\")").unwrap(),
+                "An image[1]\n\n[1]: image: http://example.com/i.png \"The title\"\n"
+            )
+        }
+
         #[test]
         fn autolink_creates_no_reference() {
             assert_eq!(
@@ -261,16 +1094,1213 @@ mod tests {
         }
 
         #[test]
-        fn flush_ref_links_at_end() {
-            assert_snapshot!(render_string_dumb(
+        fn preserve_line_breaks() {
+            let render = |preserve_line_breaks: bool| {
+                let settings = Settings {
+                    terminal_size: TerminalSize::default().with_max_columns(30),
+                    preserve_line_breaks,
+                    ..test_settings()
+                };
+                render_string(
+                    "One sentence.\nA second sentence, which is too long for one line.\n\n* An item\n  with two lines",
+                    &settings,
+                )
+                .unwrap()
+            };
+            assert_eq!(
+                render(false),
+                "One sentence. A second\nsentence, which is too long\nfor one line.\n\n• An item with two lines\n"
+            );
+            assert_eq!(
+                render(true),
+                "One sentence.\nA second sentence, which is\ntoo long for one line.\n\n• An item\n  with two lines\n"
+            );
+        }
+
+        #[test]
+        fn ref_links_at_document_end() {
+            let settings = Settings {
+                link_reference_placement: LinkReferencePlacement::DocumentEnd,
+                ..test_settings()
+            };
+            assert_snapshot!(render_string(
                 "Hello [World](http://example.com/world)
 
 # Headline
 
-Hello [Donald](http://example.com/Donald)"
+Hello [Donald](http://example.com/Donald) and [World](http://example.com/world)",
+                &settings
             )
             .unwrap())
         }
+
+        #[test]
+        fn flush_ref_links_at_end() {
+            assert_snapshot!(render_string_dumb(
+                "Hello [World](http://example.com/world)
+
+# Headline
+
+Hello [Donald](http://example.com/Donald)"
+            )
+            .unwrap())
+        }
+    }
+
+    mod headings {
+        use super::*;
+
+        #[test]
+        fn setext_headings_render_like_atx_headings() {
+            let settings = Settings {
+                terminal_capabilities: TerminalProgram::Ansi.capabilities(),
+                ..test_settings()
+            };
+            let cases = [
+                (
+                    "First\n=====\n\nSecond\n------\n\nText\n",
+                    "# First\n\n## Second\n\nText\n",
+                ),
+                (
+                    "> First\n> =====\n>\n> Second\n> ---\n",
+                    "> # First\n>\n> ## Second\n",
+                ),
+                (
+                    "* First\n  =====\n* Second\n  ---\n",
+                    "* # First\n* ## Second\n",
+                ),
+            ];
+            for (setext, atx) in cases {
+                let rendered = render_string(setext, &settings).unwrap();
+                assert_eq!(rendered, render_string(atx, &settings).unwrap());
+                assert!(!rendered.contains('\u{2550}'), "{rendered:?}");
+            }
+        }
+    }
+
+    mod theme {
+        use anstyle::{AnsiColor, Style};
+
+        use super::*;
+
+        #[test]
+        fn inline_code_with_background() {
+            let settings = Settings {
+                terminal_capabilities: TerminalProgram::Ansi.capabilities(),
+                theme: Theme::default().with_inline_code_style(
+                    Style::new()
+                        .fg_color(Some(AnsiColor::Yellow.into()))
+                        .bg_color(Some(AnsiColor::Black.into())),
+                ),
+                ..test_settings()
+            };
+            assert_eq!(
+                render_string("Some `code`", &settings).unwrap(),
+                "Some \x1b[33m\x1b[40mcode\x1b[0m\n\x1b[0m"
+            );
+        }
+
+        #[test]
+        fn image_fallback_style() {
+            let settings = Settings {
+                terminal_capabilities: TerminalProgram::Ansi.capabilities(),
+                theme: Theme::default().with_image_fallback_style(Style::new().italic()),
+                ..test_settings()
+            };
+            // mdcat can't link to an invalid URL, so it writes the description
+            assert_eq!(
+                render_string("![Alt text](http://[invalid)", &settings).unwrap(),
+                "\x1b[3mAlt text\x1b[0m\x1b[3m\x1b[35m[1]\x1b[0m\n\n\x1b[35m[1]: image: \x1b[0m\x1b[35mhttp://[invalid\x1b[0m\n\x1b[0m"
+            );
+        }
+
+        #[test]
+        fn strikethrough_overlay() {
+            let render = |theme: Theme| {
+                let settings = Settings {
+                    terminal_capabilities: TerminalProgram::Ansi.capabilities(),
+                    theme,
+                    ..test_settings()
+                };
+                let events = Parser::new_ext(
+                    "Some ~~struck *text*~~ `and` ~~`code`~~",
+                    pulldown_cmark::Options::ENABLE_STRIKETHROUGH,
+                );
+                let mut sink = Vec::new();
+                let env =
+                    Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+                push_tty(&settings, &env, &NoopResourceHandler, &mut sink, events).unwrap();
+                String::from_utf8(sink).unwrap()
+            };
+            assert_eq!(
+                render(Theme::default()),
+                "Some \x1b[9mstruck\x1b[0m \x1b[3m\x1b[9mtext\x1b[0m \x1b[33mand\x1b[0m \x1b[9m\x1b[33mcode\x1b[0m\n\x1b[0m"
+            );
+            assert_eq!(
+                render(Theme::default().with_strikethrough_overlay(true)),
+                "Some s\u{336}t\u{336}r\u{336}u\u{336}c\u{336}k\u{336} \x1b[3mt\u{336}e\u{336}x\u{336}t\u{336}\x1b[0m \x1b[33mand\x1b[0m \x1b[33mc\u{336}o\u{336}d\u{336}e\u{336}\x1b[0m\n\x1b[0m"
+            );
+        }
+
+        #[test]
+        fn block_margin() {
+            let markup = "# Heading\n\nParagraph\n\n* Item\n\n> Quote\n>\n> Quote";
+            let render = |theme: Theme| {
+                let settings = Settings {
+                    theme,
+                    ..test_settings()
+                };
+                render_string(markup, &settings).unwrap()
+            };
+            assert_eq!(
+                render(Theme::default()),
+                "┄Heading\n\nParagraph\n\n• Item\n\n    Quote\n\n    Quote\n"
+            );
+            assert_eq!(
+                render(Theme::default().with_block_margin(0)),
+                "┄Heading\nParagraph\n• Item\n    Quote\n    Quote\n"
+            );
+            assert_eq!(
+                render(Theme::default().with_block_margin(2)),
+                "┄Heading\n\n\nParagraph\n\n\n• Item\n\n\n    Quote\n\n\n    Quote\n"
+            );
+        }
+
+        #[test]
+        fn block_quote_styles() {
+            let markup = "> One\n>\n> > Two\n> >\n> > > Three\n\n> Four";
+            let render = |theme: Theme| {
+                let settings = Settings {
+                    terminal_capabilities: TerminalProgram::Ansi.capabilities(),
+                    theme,
+                    ..test_settings()
+                };
+                render_string(markup, &settings).unwrap()
+            };
+            assert_eq!(
+                render(Theme::default()),
+                "    \x1b[3mOne\x1b[0m\n\n        \x1b[3mTwo\x1b[0m\n\n            \x1b[3mThree\x1b[0m\n\n    \x1b[3mFour\x1b[0m\n\x1b[0m"
+            );
+            assert_eq!(
+                render(Theme::default().with_block_quote_styles(vec![
+                    Style::new().fg_color(Some(AnsiColor::Green.into())),
+                    Style::new().fg_color(Some(AnsiColor::Cyan.into())),
+                ])),
+                "    \x1b[3m\x1b[32mOne\x1b[0m\n\n        \x1b[3m\x1b[36mTwo\x1b[0m\n\n            \x1b[3m\x1b[32mThree\x1b[0m\n\n    \x1b[3m\x1b[32mFour\x1b[0m\n\x1b[0m"
+            );
+        }
+
+        #[test]
+        fn code_block_border_width() {
+            let markup = "```\nfn main() {}\n```\n\n* Item\n\n  ```\n  let x = 1;\n  ```\n";
+            let render = |width: CodeBlockBorderWidth| {
+                let settings = Settings {
+                    syntax_set: &SyntaxSet::default(),
+                    terminal_capabilities: TerminalProgram::Dumb.capabilities(),
+                    terminal_size: TerminalSize {
+                        columns: 30,
+                        ..TerminalSize::default()
+                    },
+                    theme: Theme::default()
+                        .with_glyphs(Glyphs::ASCII)
+                        .with_code_block_border_width(width),
+                    infer_indented_code_syntax: false,
+                    tab_width: None,
+                    link_reference_placement: LinkReferencePlacement::default(),
+                    image_dimensions: false,
+                    render_html_images_and_links: false,
+                    quiet_image_fallbacks: false,
+                    preserve_line_breaks: false,
+                    syntax_theme: None,
+                    max_image_description_width: None,
+                    render_front_matter_title: false,
+                    diagram_languages: &[],
+                    link_style: LinkStyle::Reference,
+                    image_alt_captions: false,
+                    indent: 0,
+                };
+                render_string(markup, &settings).unwrap()
+            };
+            assert_eq!(
+                render(CodeBlockBorderWidth::default()),
+                "--------------------\nfn main() {}\n--------------------\n\n* Item\n\n  --------------------\n  let x = 1;\n  --------------------\n"
+            );
+            assert_eq!(
+                render(CodeBlockBorderWidth::Fixed(5)),
+                "-----\nfn main() {}\n-----\n\n* Item\n\n  -----\n  let x = 1;\n  -----\n"
+            );
+            assert_eq!(
+                render(CodeBlockBorderWidth::FullWidth),
+                format!(
+                    "{0}\nfn main() {{}}\n{0}\n\n* Item\n\n  {1}\n  let x = 1;\n  {1}\n",
+                    "-".repeat(30),
+                    "-".repeat(28)
+                )
+            );
+            assert_eq!(
+                render(CodeBlockBorderWidth::Content),
+                "------------\nfn main() {}\n------------\n\n* Item\n\n  ----------\n  let x = 1;\n  ----------\n"
+            );
+        }
+
+        #[test]
+        fn ascii_glyphs() {
+            let settings = Settings {
+                terminal_size: TerminalSize::default().with_max_columns(10),
+                theme: Theme::default().with_glyphs(Glyphs::ASCII),
+                ..test_settings()
+            };
+            let markup = "## Heading\n\n* Item\n* [ ] Task\n* [x] Done\n\n----\n\n```\ncode\n```";
+            let source = Parser::new_ext(markup, pulldown_cmark::Options::ENABLE_TASKLISTS);
+            let mut sink = Vec::new();
+            let env = Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+            push_tty(&settings, &env, &NoopResourceHandler, &mut sink, source).unwrap();
+            assert_eq!(
+                String::from_utf8(sink).unwrap(),
+                "##Heading\n\n* Item\n* [ ] Task\n* [x] Done\n\n==========\n\n----------\ncode\n----------\n"
+            );
+        }
+    }
+
+    mod definition_lists {
+        use super::*;
+
+        fn render_definition_list(markup: &str, capabilities: TerminalCapabilities) -> String {
+            let settings = Settings {
+                terminal_capabilities: capabilities,
+                ..test_settings()
+            };
+            let source = Parser::new_ext(markup, pulldown_cmark::Options::ENABLE_DEFINITION_LIST);
+            let mut sink = Vec::new();
+            let env = Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+            push_tty(&settings, &env, &NoopResourceHandler, &mut sink, source).unwrap();
+            String::from_utf8(sink).unwrap()
+        }
+
+        #[test]
+        fn tight_definitions() {
+            assert_eq!(
+                render_definition_list(
+                    "Apple\n: A fruit\n: A company\n\nRust\n: A language",
+                    TerminalProgram::Dumb.capabilities()
+                ),
+                "Apple\n    A fruit\n    A company\nRust\n    A language\n"
+            );
+        }
+
+        #[test]
+        fn loose_definitions_in_list() {
+            assert_eq!(
+                render_definition_list(
+                    "Text\n\n* Item\n\n  Term\n\n  : First paragraph\n\n    Second paragraph\n\nMore text",
+                    TerminalProgram::Dumb.capabilities()
+                ),
+                "Text\n\n• Item\n\n  Term\n      First paragraph\n\n      Second paragraph\n\nMore text\n"
+            );
+        }
+
+        #[test]
+        fn bold_title() {
+            assert_eq!(
+                render_definition_list("Apple\n: A fruit", TerminalProgram::Ansi.capabilities()),
+                "\u{1b}[1mApple\u{1b}[0m\n    A fruit\n\u{1b}[0m"
+            );
+        }
+    }
+
+    mod front_matter {
+        use super::*;
+        use pulldown_cmark::Options;
+
+        fn render_front_matter(markup: &str, render_front_matter_title: bool) -> String {
+            let settings = Settings {
+                render_front_matter_title,
+                ..test_settings()
+            };
+            let source = Parser::new_ext(
+                markup,
+                Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
+                    | Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS,
+            );
+            let mut sink = Vec::new();
+            let env = Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+            push_tty(&settings, &env, &NoopResourceHandler, &mut sink, source).unwrap();
+            String::from_utf8(sink).unwrap()
+        }
+
+        #[test]
+        fn hide_front_matter() {
+            assert_eq!(
+                render_front_matter(
+                    "---\ntitle: A document\ndraft: true\n---\n\nSome text",
+                    false
+                ),
+                "Some text\n"
+            );
+        }
+
+        #[test]
+        fn yaml_title() {
+            assert_eq!(
+                render_front_matter("---\ntitle: \"A document\"\n---\n\nSome text", true),
+                "\u{2504}A document\n\nSome text\n"
+            );
+        }
+
+        #[test]
+        fn toml_title() {
+            assert_eq!(
+                render_front_matter("+++\ntitle = 'A document'\n+++\n\nSome text", true),
+                "\u{2504}A document\n\nSome text\n"
+            );
+        }
+
+        #[test]
+        fn no_title() {
+            assert_eq!(
+                render_front_matter("---\nauthor: Jane\n---\n\nSome text", true),
+                "Some text\n"
+            );
+        }
+    }
+
+    mod math {
+        use super::*;
+
+        fn render_math(markup: &str, capabilities: TerminalCapabilities) -> String {
+            let settings = Settings {
+                terminal_capabilities: capabilities,
+                ..test_settings()
+            };
+            let source = Parser::new_ext(
+                markup,
+                pulldown_cmark::Options::ENABLE_MATH | pulldown_cmark::Options::ENABLE_TABLES,
+            );
+            let mut sink = Vec::new();
+            let env = Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+            push_tty(&settings, &env, &NoopResourceHandler, &mut sink, source).unwrap();
+            String::from_utf8(sink).unwrap()
+        }
+
+        #[test]
+        fn inline_math() {
+            assert_eq!(
+                render_math(
+                    "Euler: $e^{i\\pi} + 1 = 0$",
+                    TerminalProgram::Ansi.capabilities()
+                ),
+                "Euler: \u{1b}[36me^{i\\pi} + 1 = 0\u{1b}[0m\n\u{1b}[0m"
+            );
+        }
+
+        #[test]
+        fn display_math() {
+            assert_eq!(
+                render_math(
+                    "$$\n\\sum_{i=1}^n i = \\frac{n(n+1)}{2}\n$$",
+                    TerminalProgram::Dumb.capabilities()
+                ),
+                "\\sum_{i=1}^n i = \\frac{n(n+1)}{2}\n"
+            );
+        }
+
+        #[test]
+        fn math_in_table() {
+            assert_eq!(
+                render_math(
+                    "| Formula |\n|---|\n| $a^2$ |",
+                    TerminalProgram::Dumb.capabilities()
+                ),
+                "─────────\n Formula \n─────────\n a^2     \n─────────\n"
+            );
+        }
+    }
+
+    mod emoji {
+        use super::*;
+
+        fn render_emoji(markup: &str, columns: u16) -> String {
+            let settings = Settings {
+                terminal_size: TerminalSize::default().with_max_columns(columns),
+                ..test_settings()
+            };
+            let source = Parser::new_ext(markup, pulldown_cmark::Options::ENABLE_TABLES);
+            let mut sink = Vec::new();
+            let env = Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+            push_tty(&settings, &env, &NoopResourceHandler, &mut sink, source).unwrap();
+            String::from_utf8(sink).unwrap()
+        }
+
+        #[test]
+        fn wrap_paragraph_with_flag_and_zwj_sequence() {
+            assert_eq!(
+                render_emoji(
+                    "aaaa \u{1F1E9}\u{1F1EA} bb \u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467} cc",
+                    10
+                ),
+                "aaaa \u{1F1E9}\u{1F1EA} bb\n\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467} cc\n"
+            );
+        }
+
+        #[test]
+        fn align_table_cells_with_flag_and_zwj_sequence() {
+            assert_eq!(
+                render_emoji(
+                    "| Emoji | Name |\n|:---:|---|\n| \u{1F1E9}\u{1F1EA} | Flag |\n| \u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467} | Family |",
+                    80
+                ),
+                "───────────────\n Emoji  Name   \n :───:  ──────\n  \u{1F1E9}\u{1F1EA}    Flag   \n  \u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}    Family \n───────────────\n"
+            );
+        }
+    }
+
+    #[cfg(feature = "image-processing")]
+    mod image_dimensions {
+        use super::*;
+        use crate::resources::{InMemoryResourceHandler, MimeData};
+
+        fn render_image(image_dimensions: bool) -> String {
+            let settings = Settings {
+                terminal_capabilities: TerminalProgram::ITerm2.capabilities(),
+                image_dimensions,
+                ..test_settings()
+            };
+            let resource_handler = InMemoryResourceHandler::from_iter([(
+                Url::parse("https://example.com/logo.png").unwrap(),
+                MimeData {
+                    mime_type: Some(mime::IMAGE_PNG),
+                    data: std::fs::read("../sample/rust-logo-128x128.png").unwrap(),
+                },
+            )]);
+            let env = Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+            let mut sink = Vec::new();
+            push_tty(
+                &settings,
+                &env,
+                &resource_handler,
+                &mut sink,
+                Parser::new("![Logo](https://example.com/logo.png)"),
+            )
+            .unwrap();
+            String::from_utf8(sink).unwrap()
+        }
+
+        #[test]
+        fn caption_with_image_dimensions() {
+            let rendered = render_image(true);
+            assert!(
+                rendered.ends_with("\u{1b}\\\n\u{1b}[2m(128\u{d7}128, PNG)\u{1b}[0m\n\u{1b}[0m"),
+                "{rendered:?}"
+            );
+        }
+
+        #[test]
+        fn no_caption_by_default() {
+            let rendered = render_image(false);
+            assert!(!rendered.contains("(128\u{d7}128, PNG)"), "{rendered:?}");
+        }
+    }
+
+    mod image_alt_captions {
+        use super::*;
+        use crate::resources::{InMemoryResourceHandler, MimeData};
+
+        fn render_image(markup: &str, image_alt_captions: bool) -> String {
+            let settings = Settings {
+                terminal_capabilities: TerminalProgram::ITerm2.capabilities(),
+                image_alt_captions,
+                ..test_settings()
+            };
+            let resource_handler = InMemoryResourceHandler::from_iter([(
+                Url::parse("https://example.com/logo.png").unwrap(),
+                MimeData {
+                    mime_type: Some(mime::IMAGE_PNG),
+                    data: std::fs::read("../sample/rust-logo-128x128.png").unwrap(),
+                },
+            )]);
+            let env = Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+            let mut sink = Vec::new();
+            push_tty(
+                &settings,
+                &env,
+                &resource_handler,
+                &mut sink,
+                Parser::new(markup),
+            )
+            .unwrap();
+            String::from_utf8(sink).unwrap()
+        }
+
+        #[test]
+        fn caption_when_enabled() {
+            let rendered = render_image(
+                "![The *Rust* logo with `code`](https://example.com/logo.png)",
+                true,
+            );
+            assert!(
+                rendered
+                    .ends_with("\u{1b}\\\n\u{1b}[3mThe Rust logo with code\u{1b}[0m\n\u{1b}[0m"),
+                "{rendered:?}"
+            );
+        }
+
+        #[test]
+        fn caption_with_nested_image() {
+            let rendered = render_image(
+                "![A ![nested](https://example.com/nested.png) logo](https://example.com/logo.png) and text",
+                true,
+            );
+            assert!(
+                rendered.ends_with("\u{1b}\\\n\u{1b}[3mA nested logo\u{1b}[0m and text\n\u{1b}[0m"),
+                "{rendered:?}"
+            );
+        }
+
+        #[test]
+        fn no_caption_by_default() {
+            let rendered = render_image("![Rust logo](https://example.com/logo.png)", false);
+            assert!(!rendered.contains("Rust logo"), "{rendered:?}");
+        }
+
+        #[test]
+        fn no_caption_for_empty_description() {
+            let rendered = render_image("![](https://example.com/logo.png)", true);
+            assert!(rendered.ends_with("\u{1b}\\\n\u{1b}[0m"), "{rendered:?}");
+        }
+    }
+
+    #[cfg(feature = "half-blocks")]
+    mod half_block_images {
+        use super::*;
+        use crate::resources::{InMemoryResourceHandler, MimeData};
+
+        fn render_image(
+            capabilities: TerminalCapabilities,
+            mime_data: MimeData,
+            markup: &str,
+        ) -> String {
+            let settings = Settings {
+                terminal_capabilities: capabilities,
+                terminal_size: TerminalSize {
+                    columns: 20,
+                    ..TerminalSize::default()
+                },
+                ..test_settings()
+            };
+            let resource_handler = InMemoryResourceHandler::from_iter([(
+                Url::parse("https://example.com/image").unwrap(),
+                mime_data,
+            )]);
+            let env = Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+            let mut sink = Vec::new();
+            push_tty(
+                &settings,
+                &env,
+                &resource_handler,
+                &mut sink,
+                Parser::new(markup),
+            )
+            .unwrap();
+            String::from_utf8(sink).unwrap()
+        }
+
+        #[test]
+        fn indent_image_in_list_item() {
+            let rendered = render_image(
+                TerminalProgram::Ansi
+                    .capabilities()
+                    .with_half_block_image_fallback(),
+                MimeData {
+                    mime_type: Some(mime::IMAGE_PNG),
+                    data: std::fs::read("../sample/rust-logo-128x128.png").unwrap(),
+                },
+                "* Logo ![Logo](https://example.com/image) and text",
+            );
+            let sgr = regex::Regex::new("\x1b\\[[0-9;]*m").unwrap();
+            let text = sgr.replace_all(&rendered, "");
+            let lines = text.lines().collect::<Vec<_>>();
+            // The image starts on a new line after the text, and the following text wraps after
+            // the last line of the image.
+            assert_eq!(lines.first(), Some(&"\u{2022} Logo"));
+            assert_eq!(lines.last(), Some(&"  and text"));
+            assert_eq!(lines.len(), 11, "{text}");
+            for line in &lines[1..lines.len() - 1] {
+                assert!(line.starts_with("  "), "{line:?}");
+                assert_eq!(line.chars().count(), 20, "{line:?}");
+            }
+        }
+
+        #[test]
+        #[cfg(not(feature = "image-processing"))]
+        fn fall_back_to_half_blocks_for_images_kitty_cannot_show() {
+            let mut data = Vec::new();
+            let mut encoder = gif::Encoder::new(&mut data, 1, 1, &[255, 0, 0]).unwrap();
+            encoder
+                .write_frame(&gif::Frame {
+                    width: 1,
+                    height: 1,
+                    buffer: vec![0].into(),
+                    ..gif::Frame::default()
+                })
+                .unwrap();
+            drop(encoder);
+            let rendered = render_image(
+                TerminalProgram::Kitty
+                    .capabilities()
+                    .with_half_block_image_fallback(),
+                MimeData {
+                    mime_type: Some(mime::IMAGE_GIF),
+                    data,
+                },
+                "![Red](https://example.com/image)",
+            );
+            assert_eq!(rendered, "\x1b[38;2;255;00;00m\u{2580}\x1b[0m\n\x1b[0m");
+        }
+    }
+
+    mod link_styles {
+        use super::*;
+
+        fn render_links(link_style: LinkStyle, capabilities: TerminalCapabilities) -> String {
+            let settings = Settings {
+                terminal_capabilities: capabilities,
+                link_style,
+                ..test_settings()
+            };
+            render_string(
+                "A [link](https://example.com/foo) and <https://example.com/bar>",
+                &settings,
+            )
+            .unwrap()
+        }
+
+        #[test]
+        fn reference() {
+            assert_eq!(
+                render_links(LinkStyle::Reference, TerminalProgram::Dumb.capabilities()),
+                "A link[1] and https://example.com/bar\n\n[1]: https://example.com/foo\n"
+            );
+        }
+
+        #[test]
+        fn inline() {
+            assert_eq!(
+                render_links(LinkStyle::Inline, TerminalProgram::Dumb.capabilities()),
+                "A link (https://example.com/foo) and https://example.com/bar\n"
+            );
+            assert_eq!(
+                render_links(LinkStyle::Inline, TerminalProgram::Ansi.capabilities()),
+                "A \x1b]8;;https://example.com/foo\x1b\\\x1b[34mlink\x1b[0m\x1b]8;;\x1b\\\x1b[2m\x1b[34m (https://example.com/foo)\x1b[0m and \x1b]8;;https://example.com/bar\x1b\\\x1b[34mhttps://example.com/bar\x1b[0m\x1b]8;;\x1b\\\n\x1b[0m"
+            );
+        }
+
+        #[test]
+        fn hidden() {
+            assert_eq!(
+                render_links(LinkStyle::Hidden, TerminalProgram::Dumb.capabilities()),
+                "A link and https://example.com/bar\n"
+            );
+            assert_eq!(
+                render_links(LinkStyle::Hidden, TerminalProgram::Ansi.capabilities()),
+                render_links(LinkStyle::Reference, TerminalProgram::Ansi.capabilities())
+            );
+        }
+    }
+
+    mod image_descriptions {
+        use super::*;
+
+        fn render_truncated(markdown: &str, capabilities: TerminalCapabilities) -> String {
+            let settings = Settings {
+                terminal_capabilities: capabilities,
+                max_image_description_width: Some(10),
+                ..test_settings()
+            };
+            render_string(markdown, &settings).unwrap()
+        }
+
+        #[test]
+        fn truncate_long_description() {
+            assert_eq!(
+                render_truncated(
+                    "An ![image of *a rather* long thing](https://example.com/i.png) here",
+                    TerminalProgram::Dumb.capabilities()
+                ),
+                "An image of \u{2026}[1] here\n\n[1]: image: https://example.com/i.png\n"
+            );
+        }
+
+        #[test]
+        fn keep_short_description() {
+            assert_eq!(
+                render_truncated(
+                    "An ![image](https://example.com/i.png) and [a long link](https://example.com)",
+                    TerminalProgram::Dumb.capabilities()
+                ),
+                "An image[1] and a long link[2]\n\n[1]: image: https://example.com/i.png\n[2]: https://example.com\n"
+            );
+        }
+
+        #[test]
+        fn truncate_description_in_image_link() {
+            assert_eq!(
+                render_truncated(
+                    "![Some rather long description](https://example.com/i.png)",
+                    TerminalProgram::Ansi.capabilities()
+                ),
+                "\x1b]8;;https://example.com/i.png\x1b\\\x1b[35mSome rath\u{2026}\x1b[0m\x1b]8;;\x1b\\\n\x1b[0m"
+            );
+        }
+    }
+
+    mod code_blocks {
+        use super::*;
+
+        fn render_string_ansi(markup: &str, infer_indented_code_syntax: bool) -> String {
+            render_string(
+                markup,
+                &Settings {
+                    syntax_set: &SyntaxSet::load_defaults_newlines(),
+                    terminal_capabilities: TerminalProgram::Ansi.capabilities(),
+                    infer_indented_code_syntax,
+                    ..test_settings()
+                },
+            )
+            .unwrap()
+        }
+
+        #[test]
+        fn infer_syntax_of_indented_code_block_from_shebang() {
+            let markup = "Script:\n\n    #!/bin/bash\n    echo \"Hello\"\n";
+            let plain = render_string_ansi(markup, false);
+            let inferred = render_string_ansi(markup, true);
+            assert_ne!(plain, inferred);
+            let fenced = render_string_ansi(
+                "Script:\n\n```bash\n#!/bin/bash\necho \"Hello\"\n```\n",
+                false,
+            );
+            assert_eq!(inferred, fenced);
+        }
+
+        #[test]
+        fn expand_tabs_in_code_blocks() {
+            let settings = Settings {
+                tab_width: Some(4),
+                ..test_settings()
+            };
+            let rendered = render_string("```\nab\tc\n\td\tefghi\te\n```", &settings).unwrap();
+            let lines = rendered.lines().collect::<Vec<_>>();
+            assert_eq!(lines[1], "ab  c");
+            assert_eq!(lines[2], "    d   efghi   e");
+        }
+
+        #[test]
+        fn indented_code_block_without_recognizable_first_line() {
+            let markup = "Code:\n\n    echo \"Hello\"\n";
+            assert_eq!(
+                render_string_ansi(markup, true),
+                render_string_ansi(markup, false)
+            );
+        }
+
+        #[test]
+        fn diagram_with_notice() {
+            let settings = Settings {
+                syntax_set: &SyntaxSet::load_defaults_newlines(),
+                terminal_capabilities: TerminalProgram::Ansi.capabilities(),
+                diagram_languages: &["mermaid", "dot"],
+                ..test_settings()
+            };
+            let rendered =
+                render_string("```Mermaid\ngraph TD\n  A --> B\n```", &settings).unwrap();
+            assert_eq!(
+                rendered,
+                "\u{1b}[32m────────────────────\u{1b}[0m
+\u{1b}[32m\u{2301} Mermaid diagram (not rendered)\u{1b}[0m
+\u{1b}[33mgraph TD
+\u{1b}[0m\u{1b}[33m  A --> B
+\u{1b}[0m\u{1b}[32m────────────────────\u{1b}[0m
+\u{1b}[0m"
+            );
+            // Do not highlight diagram languages which have a syntax
+            let rendered = render_string("```dot\ndigraph {}\n```", &settings).unwrap();
+            assert!(rendered.contains("\u{1b}[33mdigraph {}\n"), "{rendered:?}");
+        }
+    }
+
+    mod styles {
+        use pulldown_cmark::{Event, Tag, TagEnd};
+
+        use super::*;
+
+        fn ansi_settings(syntax_set: &SyntaxSet) -> Settings<'_> {
+            Settings {
+                syntax_set,
+                terminal_capabilities: TerminalProgram::Ansi.capabilities(),
+                ..test_settings()
+            }
+        }
+
+        #[test]
+        fn inline_code_with_backticks() {
+            let rendered = render_string(
+                "Run `` `date` `` or ```a``b```.",
+                &ansi_settings(&SyntaxSet::default()),
+            )
+            .unwrap();
+            assert_eq!(
+                rendered,
+                "Run \x1b[33m`date`\x1b[0m or \x1b[33ma``b\x1b[0m.\n\x1b[0m"
+            );
+        }
+
+        #[test]
+        fn wrap_inline_code_with_backticks() {
+            let syntax_set = SyntaxSet::default();
+            let settings = Settings {
+                terminal_size: TerminalSize::default().with_max_columns(12),
+                ..ansi_settings(&syntax_set)
+            };
+            // Without backticks the code would still fit on the first line
+            let rendered = render_string("Some text ``a`b`` and", &settings).unwrap();
+            assert_eq!(rendered, "Some text\n\x1b[33ma`b\x1b[0m and\n\x1b[0m");
+        }
+
+        #[test]
+        fn reset_styles_after_final_newline() {
+            let rendered = render_string(
+                "Some *emphasis* and **strong** text",
+                &ansi_settings(&SyntaxSet::default()),
+            )
+            .unwrap();
+            assert!(rendered.ends_with("\x1b[0m text\n\x1b[0m"), "{rendered:?}");
+        }
+
+        /// A writer which fails to write text containing `fail`.
+        struct FailingWriter(Vec<u8>);
+
+        impl Write for FailingWriter {
+            fn write(&mut self, buf: &[u8]) -> Result<usize> {
+                if buf.windows(4).any(|w| w == b"fail") {
+                    Err(Error::other("failed to write"))
+                } else {
+                    self.0.write(buf)
+                }
+            }
+
+            fn flush(&mut self) -> Result<()> {
+                Ok(())
+            }
+        }
+
+        #[test]
+        fn reset_active_style_if_writing_fails() {
+            let mut sink = FailingWriter(Vec::new());
+            let env = Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+            let error = push_tty(
+                &ansi_settings(&SyntaxSet::default()),
+                &env,
+                &NoopResourceHandler,
+                &mut sink,
+                pulldown_cmark::Parser::new("Some *fail*"),
+            )
+            .unwrap_err();
+            assert_eq!(error.to_string(), "failed to write");
+            let rendered = String::from_utf8(sink.0).unwrap();
+            assert!(rendered.ends_with("\x1b[3m\x1b[0m\n"), "{rendered:?}");
+        }
+
+        #[test]
+        fn reset_styles_if_events_end_mid_emphasis() {
+            let events = vec![
+                Event::Start(Tag::Paragraph),
+                Event::Start(Tag::Emphasis),
+                Event::Text("dangling".into()),
+                Event::End(TagEnd::Emphasis),
+            ];
+            let mut sink = Vec::new();
+            let env = Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+            let error = push_tty(
+                &ansi_settings(&SyntaxSet::default()),
+                &env,
+                &NoopResourceHandler,
+                &mut sink,
+                events.into_iter(),
+            )
+            .unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+            let rendered = String::from_utf8(sink).unwrap();
+            assert!(
+                rendered.ends_with("dangling\x1b[0m\x1b[0m\n"),
+                "{rendered:?}"
+            );
+        }
+
+        #[test]
+        fn no_extra_newline_if_events_end_between_blocks() {
+            let events = vec![
+                Event::Start(Tag::BlockQuote(None)),
+                Event::Start(Tag::Paragraph),
+                Event::Text("quoted".into()),
+                Event::End(TagEnd::Paragraph),
+            ];
+            let mut sink = Vec::new();
+            let env = Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+            let error = push_tty(
+                &ansi_settings(&SyntaxSet::default()),
+                &env,
+                &NoopResourceHandler,
+                &mut sink,
+                events.into_iter(),
+            )
+            .unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+            let rendered = String::from_utf8(sink).unwrap();
+            assert!(rendered.ends_with("quoted\x1b[0m\n\x1b[0m"), "{rendered:?}");
+        }
+    }
+
+    mod trailing_newline {
+        use insta::assert_debug_snapshot;
+        use pulldown_cmark::{Options, Parser};
+
+        use super::*;
+
+        fn render(markdown: &str) -> String {
+            let settings = test_settings();
+            let mut sink = Vec::new();
+            let env = Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+            let events = Parser::new_ext(markdown, Options::ENABLE_TABLES);
+            push_tty(&settings, &env, &NoopResourceHandler, &mut sink, events).unwrap();
+            String::from_utf8(sink).unwrap()
+        }
+
+        #[test]
+        fn ends_with_paragraph() {
+            assert_debug_snapshot!(render("Paragraph"));
+            assert_debug_snapshot!(render("Paragraph\n"));
+        }
+
+        #[test]
+        fn ends_with_heading() {
+            assert_debug_snapshot!(render("# Heading"));
+            assert_debug_snapshot!(render("# Heading\n"));
+        }
+
+        #[test]
+        fn ends_with_rule() {
+            assert_debug_snapshot!(render("Paragraph\n\n----"));
+            assert_debug_snapshot!(render("Paragraph\n\n----\n"));
+        }
+
+        #[test]
+        fn ends_with_code_block() {
+            assert_debug_snapshot!(render("```\ncode\n```"));
+            assert_debug_snapshot!(render("```\ncode\n```\n"));
+        }
+
+        #[test]
+        fn ends_with_unclosed_code_block() {
+            assert_debug_snapshot!(render("```\ncode"));
+            assert_debug_snapshot!(render("```\ncode\n"));
+        }
+
+        #[test]
+        fn ends_with_list() {
+            assert_debug_snapshot!(render("* Item\n  * Nested item"));
+            assert_debug_snapshot!(render("* Item\n  * Nested item\n"));
+        }
+
+        #[test]
+        fn ends_with_ordered_list() {
+            assert_debug_snapshot!(render("1. One\n2. Two"));
+            assert_debug_snapshot!(render("1. One\n2. Two\n"));
+        }
+
+        #[test]
+        fn ends_with_block_quote() {
+            assert_debug_snapshot!(render("> Quote"));
+            assert_debug_snapshot!(render("> Quote\n"));
+        }
+
+        #[test]
+        fn ends_with_table() {
+            assert_debug_snapshot!(render("| A | B |\n|---|---|\n| 1 | 2 |"));
+            assert_debug_snapshot!(render("| A | B |\n|---|---|\n| 1 | 2 |\n"));
+        }
+
+        #[test]
+        fn ends_with_html_block() {
+            assert_debug_snapshot!(render("<div>\nHTML\n</div>"));
+            assert_debug_snapshot!(render("<div>\nHTML\n</div>\n"));
+        }
+
+        #[test]
+        fn ends_with_html_block_in_list() {
+            assert_debug_snapshot!(render("* Item\n\n  <div>\n  HTML\n  </div>"));
+            assert_debug_snapshot!(render("* Item\n\n  <div>\n  HTML\n  </div>\n"));
+        }
+
+        #[test]
+        fn ends_with_link_references() {
+            assert_debug_snapshot!(render("[Link](https://example.com)"));
+            assert_debug_snapshot!(render("[Link](https://example.com)\n"));
+        }
+    }
+
+    mod html_tags {
+        use super::*;
+
+        fn render_html(markdown: &str, capabilities: TerminalCapabilities) -> String {
+            let settings = Settings {
+                terminal_capabilities: capabilities,
+                render_html_images_and_links: true,
+                ..test_settings()
+            };
+            render_string(markdown, &settings).unwrap()
+        }
+
+        fn render_dumb(markdown: &str) -> String {
+            render_html(markdown, TerminalProgram::Dumb.capabilities())
+        }
+
+        fn render_ansi(markdown: &str) -> String {
+            render_html(markdown, TerminalProgram::Ansi.capabilities())
+        }
+
+        #[test]
+        fn inline_image_with_alt() {
+            assert_eq!(
+                render_dumb("See <img src=\"https://example.com/logo.png\" alt=\"the logo\"> here"),
+                "See the logo[1] here\n\n[1]: image: https://example.com/logo.png\n"
+            );
+        }
+
+        #[test]
+        fn inline_image_without_alt() {
+            assert_eq!(
+                render_dumb("See <img src=\"https://example.com/logo.png\"> here"),
+                "See <img src=\"https://example.com/logo.png\"> here\n"
+            );
+            assert_eq!(
+                render_ansi("See <img src=\"https://example.com/logo.png\" alt=\"\"> here"),
+                "See \x1b[32m<img src=\"https://example.com/logo.png\" alt=\"\">\x1b[0m here\n\x1b[0m"
+            );
+        }
+
+        #[test]
+        fn inline_image_as_link() {
+            assert_eq!(
+                render_ansi("See <img src=\"https://example.com/logo.png\" alt=\"the logo\"> here"),
+                "See\x1b]8;;https://example.com/logo.png\x1b\\ \x1b[35mthe logo\x1b[0m\x1b]8;;\x1b\\ here\n\x1b[0m"
+            );
+        }
+
+        #[test]
+        fn inline_link() {
+            assert_eq!(
+                render_dumb("A <a href=\"https://example.com\">*link*</a> here"),
+                "A link[1] here\n\n[1]: https://example.com\n"
+            );
+            assert_eq!(
+                render_ansi("A <a href=\"https://example.com\">*link*</a> here"),
+                "A \x1b]8;;https://example.com/\x1b\\\x1b[3m\x1b[34mlink\x1b[0m\x1b]8;;\x1b\\ here\n\x1b[0m"
+            );
+        }
+
+        #[test]
+        fn unclosed_inline_link() {
+            assert_eq!(
+                render_dumb("*A <a href=\"https://example.com\">link*\n\nMore"),
+                "A <a href=\"https://example.com\">link\n\nMore\n"
+            );
+        }
+
+        #[test]
+        fn inline_link_closed_in_nested_element() {
+            assert_eq!(
+                render_dumb("<a href=\"https://example.com\">link *em</a> end*"),
+                "<a href=\"https://example.com\">link em</a> end\n"
+            );
+            assert_eq!(
+                render_ansi("<a href=\"https://example.com\">link *em</a> end*"),
+                "\x1b[32m<a href=\"https://example.com\">\x1b[0mlink \x1b[3mem\x1b[0m\x1b[3m\x1b[32m</a>\x1b[0m\x1b[3m end\x1b[0m\n\x1b[0m"
+            );
+        }
+
+        #[test]
+        fn inline_link_with_nested_elements() {
+            assert_eq!(
+                render_dumb("<a href=\"https://example.com\">*a* **b** `c`</a> d"),
+                "a b c[1] d\n\n[1]: https://example.com\n"
+            );
+        }
+
+        #[test]
+        fn unclosed_inline_link_before_html_block() {
+            assert_eq!(
+                render_dumb("- A <a href=\"https://example.com\">link\n  <div><img src=\"https://example.com/logo.png\" alt=\"Logo\"></div>\n"),
+                "• A <a href=\"https://example.com\">link<div>Logo[1]</div>\n\n[1]: image: https://example.com/logo.png\n"
+            );
+        }
+
+        #[test]
+        fn nested_inline_link() {
+            assert_eq!(
+                render_dumb("[A <a href=\"https://example.com\">link</a>](https://example.org)"),
+                "A <a href=\"https://example.com\">link</a>[1]\n\n[1]: https://example.org\n"
+            );
+        }
+
+        #[test]
+        fn block_image_and_link() {
+            assert_eq!(
+                render_dumb("<p align=\"center\">\n<a href=\"https://example.com\"><img src=\"https://example.com/badge.svg\" alt=\"Badge\"></a>\n<img src=\"https://example.com/logo.png\">\n</p>\n"),
+                "<p align=\"center\">\nBadge[1]\n<img src=\"https://example.com/logo.png\">\n</p>\n\n[1]: https://example.com\n"
+            );
+        }
+
+        #[test]
+        fn ignore_tags_if_disabled() {
+            let markdown = "<img src=\"https://example.com/logo.png\" alt=\"Logo\">\n\nSee <a href=\"https://example.com\">link</a>";
+            assert_eq!(
+                render_string_dumb(markdown).unwrap(),
+                "<img src=\"https://example.com/logo.png\" alt=\"Logo\">\n\nSee <a href=\"https://example.com\">link</a>\n"
+            );
+        }
+    }
+
+    mod indent {
+        use super::*;
+
+        fn render_indented(markdown: &str) -> String {
+            let settings = Settings {
+                terminal_size: TerminalSize::default().with_max_columns(30),
+                ..test_settings()
+            }
+            .with_indent(3);
+            let env = Environment::for_local_directory(&std::env::current_dir().unwrap()).unwrap();
+            let parser = Parser::new_ext(markdown, default_parser_options());
+            let mut sink = Vec::new();
+            push_tty(&settings, &env, &NoopResourceHandler, &mut sink, parser).unwrap();
+            String::from_utf8(sink).unwrap()
+        }
+
+        #[test]
+        fn indents_and_wraps_blocks() {
+            assert_eq!(
+                render_indented("# Heading\n\nSome text which is long enough to wrap.\n\n> Quote\n\n- Item\n\n---\n"),
+                "   ┄Heading\n\n   Some text which is long\n   enough to wrap.\n\n       Quote\n\n   • Item\n\n   ═══════════════════════════\n"
+            );
+        }
+
+        #[test]
+        fn indents_tables_and_link_references() {
+            assert_eq!(
+                render_indented("| a | b |\n|---|---|\n| 1 | 2 |\n\nA [link](https://example.com).\n"),
+                "   ──────\n    a  b \n   ──────\n    1  2 \n   ──────\n\n   A link[1].\n\n   [1]: https://example.com\n"
+            );
+        }
     }
 
     mod disabled_features {
@@ -6,7 +6,6 @@
 
 //! Provide utilities for references.
 
-use crate::Environment;
 use tracing::{event, Level};
 use url::Url;
 
@@ -32,16 +31,10 @@ impl UrlBase for Url {
     }
 }
 
-impl UrlBase for Environment {
-    /// Resolve a reference against the `base_url` of this environment.
-    fn resolve_reference(&self, reference: &str) -> Option<Url> {
-        self.base_url.resolve_reference(reference)
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Environment;
     use similar_asserts::assert_eq;
     use url::Url;
 
@@ -76,6 +69,54 @@ mod tests {
         assert_eq!(url.as_ref().map_or("", |u| u.as_str()), "file:///foo.md");
     }
 
+    #[test]
+    fn http_base_and_relative_path() {
+        let url = Url::parse("https://host/path/README.md")
+            .unwrap()
+            .resolve_reference("./images/logo.png");
+        assert_eq!(
+            url.as_ref().map_or("", |u| u.as_str()),
+            "https://host/path/images/logo.png"
+        );
+    }
+
+    #[test]
+    fn http_base_and_absolute_path() {
+        let url = Url::parse("https://host/path/README.md")
+            .unwrap()
+            .resolve_reference("/images/logo.png");
+        assert_eq!(
+            url.as_ref().map_or("", |u| u.as_str()),
+            "https://host/images/logo.png"
+        );
+    }
+
+    #[test]
+    fn environment_with_http_base() {
+        let env = Environment {
+            base_url: Url::parse("https://host/path/README.md").unwrap(),
+            hostname: "HOSTNAME".to_string(),
+        };
+        let url = env.resolve_reference("images/logo.png");
+        assert_eq!(
+            url.as_ref().map_or("", |u| u.as_str()),
+            "https://host/path/images/logo.png"
+        );
+    }
+
+    #[test]
+    fn environment_with_file_base() {
+        let env = Environment {
+            base_url: Url::parse("file:///some/root/").unwrap(),
+            hostname: "HOSTNAME".to_string(),
+        };
+        let url = env.resolve_reference("./images/logo.png");
+        assert_eq!(
+            url.as_ref().map_or("", |u| u.as_str()),
+            "file:///some/root/images/logo.png"
+        );
+    }
+
     #[test]
     fn base_with_drive_letter_and_absolute_path() {
         let url = Url::parse("file:///d:/some/folder")
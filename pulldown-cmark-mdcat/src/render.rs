@@ -6,46 +6,62 @@
 
 //! Rendering algorithm.
 
-use std::io::prelude::*;
-use std::io::Result;
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Result};
 
 use anstyle::{Effects, Style};
 use pulldown_cmark::Event::*;
 use pulldown_cmark::Tag;
 use pulldown_cmark::Tag::*;
 use pulldown_cmark::TagEnd;
-use pulldown_cmark::{Event, LinkType};
-use syntect::highlighting::HighlightIterator;
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, LinkType};
+use syntect::highlighting::{HighlightIterator, Highlighter};
 use syntect::util::LinesWithEndings;
-use textwrap::core::display_width;
 use tracing::{event, instrument, Level};
 use url::Url;
 
 use crate::render::highlighting::highlighter;
+use crate::resources::image::{image_caption, RecordingResourceHandler};
 use crate::resources::ResourceUrlHandler;
+use crate::sink::{RecordingSink, StyleSink};
 use crate::theme::CombineStyle;
-use crate::{Environment, Settings};
+use crate::{Environment, LinkReferencePlacement, LinkStyle, RenderSummary, Settings};
 
 mod data;
 mod highlighting;
+mod html;
+mod metadata;
 mod state;
+mod width;
 mod write;
 
-use crate::references::*;
+use html::{image_events, link_events, link_start, parse_tag, split_html, HtmlFragment, HtmlTag};
+use metadata::front_matter_title;
 use state::*;
+use width::display_width;
 use write::*;
 
-use crate::render::data::{CurrentLine, CurrentTable};
-use crate::render::state::MarginControl::NoMargin;
+use crate::render::data::{CurrentLine, CurrentTable, LinkReferenceKind, PendingHtmlLink};
 use crate::terminal::capabilities::StyleCapability;
 use crate::terminal::osc::{clear_link, set_link_url};
 pub use data::StateData;
 pub use state::State;
 pub use state::StateAndData;
 
+/// Log a fallback for an image at `$level`, or only at debug level if the fallback is expected.
+macro_rules! image_fallback_event {
+    ($expected:expr, $level:expr, $($arg:tt)+) => {
+        if $expected {
+            event!(Level::DEBUG, $($arg)+)
+        } else {
+            event!($level, $($arg)+)
+        }
+    };
+}
+
 #[allow(clippy::cognitive_complexity)]
 #[instrument(level = "trace", skip(writer, settings, environment, resource_handler))]
-pub fn write_event<'a, W: Write>(
+pub fn write_event<'a, W: StyleSink>(
     writer: &mut W,
     settings: &Settings,
     environment: &Environment,
@@ -60,62 +76,185 @@ pub fn write_event<'a, W: Write>(
     use State::*;
 
     event!(Level::TRACE, event = ?event, "rendering");
+    let data = match &event {
+        Start(CodeBlock(CodeBlockKind::Fenced(language)))
+            if !language.is_empty()
+                && !is_diagram_language(settings, language)
+                && settings.syntax_set.find_syntax_by_token(language).is_none() =>
+        {
+            event!(Level::DEBUG, %language, "Unknown language of code block");
+            data.add_unknown_language(language.clone())
+        }
+        _ => data,
+    };
+    // Buffer all events after a HTML `<a>` tag until its closing tag at the same level of inline
+    // nesting, and render them as link.  If the surrounding element ends before, write the tag
+    // literally and render the buffered events as they are.
+    let (data, pending_html_link) = data.take_pending_html_link();
+    let (state, data, event) = match pending_html_link {
+        None => (state, data, event),
+        Some(mut link) => match event {
+            InlineHtml(html) if link.nesting == 0 && is_html_link_end(&html) => {
+                let mut events = vec![link_start(link.href, link.title)];
+                events.append(&mut link.events);
+                events.push(End(TagEnd::Link));
+                return write_events(
+                    writer,
+                    settings,
+                    environment,
+                    resource_handler,
+                    state,
+                    data,
+                    events,
+                );
+            }
+            event @ Start(Emphasis | Strong | Strikethrough | Link { .. } | Image { .. }) => {
+                link.nesting += 1;
+                link.events.push(event);
+                return Ok(state.and_data(data.pending_html_link(link)));
+            }
+            event @ End(
+                TagEnd::Emphasis
+                | TagEnd::Strong
+                | TagEnd::Strikethrough
+                | TagEnd::Link
+                | TagEnd::Image,
+            ) if 0 < link.nesting => {
+                link.nesting -= 1;
+                link.events.push(event);
+                return Ok(state.and_data(data.pending_html_link(link)));
+            }
+            event @ (Text(_) | Code(_) | InlineMath(_) | InlineHtml(_) | SoftBreak | HardBreak
+            | FootnoteReference(_)) => {
+                link.events.push(event);
+                return Ok(state.and_data(data.pending_html_link(link)));
+            }
+            event => {
+                let StateAndData(state, data) = match state {
+                    Stacked(stack, Inline(inline, attrs)) => write_literal_inline_html(
+                        writer, settings, stack, inline, attrs, data, &link.tag,
+                    )?,
+                    // We only start HTML links in inline states
+                    state => state.and_data(data),
+                };
+                let StateAndData(state, data) = write_events(
+                    writer,
+                    settings,
+                    environment,
+                    resource_handler,
+                    state,
+                    data,
+                    link.events,
+                )?;
+                (state, data, event)
+            }
+        },
+    };
+    let event = match event {
+        // Keep the line structure of the source text if requested
+        SoftBreak if settings.preserve_line_breaks => HardBreak,
+        Text(text) if 0 < data.overlay_strikethroughs => {
+            Text(strike_through_with_overlay(&strip_control_characters(text)).into())
+        }
+        Code(code) if 0 < data.overlay_strikethroughs => {
+            Code(strike_through_with_overlay(&strip_control_characters(code)).into())
+        }
+        Text(text) => Text(strip_control_characters(text)),
+        Code(code) => Code(strip_control_characters(code)),
+        InlineMath(math) => InlineMath(strip_control_characters(math)),
+        DisplayMath(math) => DisplayMath(strip_control_characters(math)),
+        event => event,
+    };
+    // Truncate long descriptions of images which we could not show
+    let (event, data) = match (event, data.image_description_width) {
+        (Text(text), Some(remaining)) => {
+            let (text, remaining) =
+                truncate_image_description(text, remaining, settings.theme.glyphs.ellipsis);
+            (Text(text), data.image_description_width(Some(remaining)))
+        }
+        (Code(code), Some(remaining)) => {
+            let (code, remaining) =
+                truncate_image_description(code, remaining, settings.theme.glyphs.ellipsis);
+            (Code(code), data.image_description_width(Some(remaining)))
+        }
+        (event, _) => (event, data),
+    };
     match (state, event) {
         // Top level items
         (TopLevel(attrs), Start(Paragraph)) => {
-            if attrs.margin_before != NoMargin {
-                writeln!(writer)?;
-            }
+            write_margin(writer, &settings.theme, attrs.margin_before)?;
+            write_indent(writer, settings.indent)?;
             State::stack_onto(TopLevelAttrs::margin_before())
-                .current(Inline(InlineText, InlineAttrs::default()))
+                .current(Inline(InlineText, InlineAttrs::indented(settings.indent)))
                 .and_data(data)
                 .ok()
         }
         (TopLevel(attrs), Start(Tag::HtmlBlock)) => {
-            if attrs.margin_before != NoMargin {
-                writeln!(writer)?;
-            }
+            write_margin(writer, &settings.theme, attrs.margin_before)?;
             // We render HTML literally
             State::stack_onto(TopLevelAttrs::margin_before())
                 .current(
                     HtmlBlockAttrs {
-                        indent: 0,
-                        initial_indent: 0,
+                        indent: settings.indent,
+                        initial_indent: settings.indent,
                         style: settings.theme.html_block_style,
+                        open_line: false,
                     }
                     .into(),
                 )
                 .and_data(data)
                 .ok()
         }
+        (TopLevel(attrs), Start(MetadataBlock(kind))) => State::stack_onto(attrs)
+            .current(
+                FrontMatterAttrs {
+                    kind,
+                    contents: String::new(),
+                }
+                .into(),
+            )
+            .and_data(data)
+            .ok(),
         (TopLevel(attrs), Start(Heading { level, .. })) => {
-            let (data, links) = data.take_link_references();
-            write_link_refs(writer, environment, &settings.terminal_capabilities, links)?;
-            if attrs.margin_before != NoMargin {
-                writeln!(writer)?;
-            }
+            let data = match settings.link_reference_placement {
+                LinkReferencePlacement::PerSection => {
+                    let (data, links) = data.take_link_references();
+                    write_link_refs(
+                        writer,
+                        environment,
+                        &settings.terminal_capabilities,
+                        settings.indent,
+                        links,
+                    )?;
+                    data
+                }
+                LinkReferencePlacement::DocumentEnd => data,
+            };
+            write_margin(writer, &settings.theme, attrs.margin_before)?;
             write_mark(writer, &settings.terminal_capabilities)?;
+            write_indent(writer, settings.indent)?;
 
             State::stack_onto(TopLevelAttrs::margin_before())
                 .current(write_start_heading(
                     writer,
                     &settings.terminal_capabilities,
                     settings.theme.heading_style,
+                    &settings.theme.glyphs,
                     level,
                 )?)
                 .and_data(data)
                 .ok()
         }
         (TopLevel(attrs), Start(BlockQuote(_))) => {
-            if attrs.margin_before != NoMargin {
-                writeln!(writer)?;
-            }
+            write_margin(writer, &settings.theme, attrs.margin_before)?;
+            let data = data.enter_block_quote();
+            let depth_style = settings.theme.block_quote_style(data.block_quote_depth);
             State::stack_onto(TopLevelAttrs::margin_before())
                 .current(
                     // We've written a block-level margin already, so the first
                     // block inside the styled block should add another margin.
-                    StyledBlockAttrs::default()
-                        .block_quote()
+                    StyledBlockAttrs::from(InlineAttrs::indented(settings.indent))
+                        .block_quote(depth_style)
                         .without_margin_before()
                         .into(),
                 )
@@ -123,51 +262,60 @@ pub fn write_event<'a, W: Write>(
                 .ok()
         }
         (TopLevel(attrs), Rule) => {
-            if attrs.margin_before != NoMargin {
-                writeln!(writer)?;
-            }
+            write_margin(writer, &settings.theme, attrs.margin_before)?;
+            write_indent(writer, settings.indent)?;
             write_rule(
                 writer,
                 &settings.terminal_capabilities,
                 &settings.theme,
-                settings.terminal_size.columns,
+                settings
+                    .terminal_size
+                    .columns
+                    .saturating_sub(settings.indent)
+                    .max(1),
             )?;
             writeln!(writer)?;
             TopLevel(TopLevelAttrs::margin_before()).and_data(data).ok()
         }
         (TopLevel(attrs), Start(CodeBlock(kind))) => {
-            if attrs.margin_before != NoMargin {
-                writeln!(writer)?;
-            }
+            write_margin(writer, &settings.theme, attrs.margin_before)?;
 
             State::stack_onto(TopLevelAttrs::margin_before())
                 .current(write_start_code_block(
                     writer,
                     settings,
-                    0,
+                    settings.indent,
                     Style::new(),
                     kind,
+                    data.code_block_width,
                 )?)
                 .and_data(data)
                 .ok()
         }
         (TopLevel(attrs), Start(List(start))) => {
-            if attrs.margin_before != NoMargin {
-                writeln!(writer)?;
-            }
-            let kind = start.map_or(ListItemKind::Unordered, |start| {
-                ListItemKind::Ordered(start)
-            });
+            write_margin(writer, &settings.theme, attrs.margin_before)?;
+            let kind = ListItemKind::for_list(start, data.ordered_list_width);
 
             State::stack_onto(TopLevelAttrs::margin_before())
-                .current(Inline(ListItem(kind, StartItem), InlineAttrs::default()))
+                .current(Inline(
+                    ListItem(kind, StartItem),
+                    InlineAttrs::indented(settings.indent),
+                ))
+                .and_data(data)
+                .ok()
+        }
+        (TopLevel(attrs), Start(DefinitionList)) => {
+            write_margin(writer, &settings.theme, attrs.margin_before)?;
+            State::stack_onto(TopLevelAttrs::margin_before())
+                .current(Inline(
+                    ListItem(ListItemKind::Definition, StartItem),
+                    InlineAttrs::indented(settings.indent),
+                ))
                 .and_data(data)
                 .ok()
         }
         (TopLevel(attrs), Start(Table(alignments))) => {
-            if attrs.margin_before != NoMargin {
-                writeln!(writer)?;
-            }
+            write_margin(writer, &settings.theme, attrs.margin_before)?;
             let current_table = CurrentTable {
                 alignments,
                 ..data.current_table
@@ -184,9 +332,7 @@ pub fn write_event<'a, W: Write>(
 
         // Nested blocks with style, e.g. paragraphs in quotes, etc.
         (Stacked(stack, StyledBlock(attrs)), Start(Paragraph)) => {
-            if attrs.margin_before != NoMargin {
-                writeln!(writer)?;
-            }
+            write_margin(writer, &settings.theme, attrs.margin_before)?;
             write_indent(writer, attrs.indent)?;
             let inline = InlineAttrs::from(&attrs);
             stack
@@ -196,13 +342,12 @@ pub fn write_event<'a, W: Write>(
                 .ok()
         }
         (Stacked(stack, StyledBlock(attrs)), Start(Tag::HtmlBlock)) => {
-            if attrs.margin_before != NoMargin {
-                writeln!(writer)?;
-            }
+            write_margin(writer, &settings.theme, attrs.margin_before)?;
             let state = HtmlBlockAttrs {
                 indent: attrs.indent,
                 initial_indent: attrs.indent,
                 style: settings.theme.html_block_style.on_top_of(&attrs.style),
+                open_line: false,
             }
             .into();
             stack
@@ -212,25 +357,32 @@ pub fn write_event<'a, W: Write>(
                 .ok()
         }
         (Stacked(stack, StyledBlock(attrs)), Start(BlockQuote(_))) => {
-            if attrs.margin_before != NoMargin {
-                writeln!(writer)?;
-            }
+            write_margin(writer, &settings.theme, attrs.margin_before)?;
+            let data = data.enter_block_quote();
+            let depth_style = settings.theme.block_quote_style(data.block_quote_depth);
             stack
                 .push(attrs.clone().with_margin_before().into())
-                .current(attrs.without_margin_before().block_quote().into())
+                .current(
+                    attrs
+                        .without_margin_before()
+                        .block_quote(depth_style)
+                        .into(),
+                )
                 .and_data(data)
                 .ok()
         }
         (Stacked(stack, StyledBlock(attrs)), Rule) => {
-            if attrs.margin_before != NoMargin {
-                writeln!(writer)?;
-            }
+            write_margin(writer, &settings.theme, attrs.margin_before)?;
             write_indent(writer, attrs.indent)?;
             write_rule(
                 writer,
                 &settings.terminal_capabilities,
                 &settings.theme,
-                settings.terminal_size.columns - attrs.indent,
+                settings
+                    .terminal_size
+                    .columns
+                    .saturating_sub(attrs.indent)
+                    .max(1),
             )?;
             writeln!(writer)?;
             stack
@@ -239,9 +391,7 @@ pub fn write_event<'a, W: Write>(
                 .ok()
         }
         (Stacked(stack, StyledBlock(attrs)), Start(Heading { level, .. })) => {
-            if attrs.margin_before != NoMargin {
-                writeln!(writer)?;
-            }
+            write_margin(writer, &settings.theme, attrs.margin_before)?;
             write_indent(writer, attrs.indent)?;
 
             // We deliberately don't mark headings which aren't top-level.
@@ -252,18 +402,15 @@ pub fn write_event<'a, W: Write>(
                     writer,
                     &settings.terminal_capabilities,
                     settings.theme.heading_style.on_top_of(&style),
+                    &settings.theme.glyphs,
                     level,
                 )?)
                 .and_data(data)
                 .ok()
         }
         (Stacked(stack, StyledBlock(attrs)), Start(List(start))) => {
-            if attrs.margin_before != NoMargin {
-                writeln!(writer)?;
-            }
-            let kind = start.map_or(ListItemKind::Unordered, |start| {
-                ListItemKind::Ordered(start)
-            });
+            write_margin(writer, &settings.theme, attrs.margin_before)?;
+            let kind = ListItemKind::for_list(start, data.ordered_list_width);
             let inline = InlineAttrs::from(&attrs);
             stack
                 .push(attrs.with_margin_before().into())
@@ -271,22 +418,43 @@ pub fn write_event<'a, W: Write>(
                 .and_data(data)
                 .ok()
         }
+        (Stacked(stack, StyledBlock(attrs)), Start(DefinitionList)) => {
+            write_margin(writer, &settings.theme, attrs.margin_before)?;
+            let inline = InlineAttrs::from(&attrs);
+            stack
+                .push(attrs.with_margin_before().into())
+                .current(Inline(
+                    ListItem(ListItemKind::Definition, StartItem),
+                    inline,
+                ))
+                .and_data(data)
+                .ok()
+        }
         (Stacked(stack, StyledBlock(attrs)), Start(CodeBlock(kind))) => {
-            if attrs.margin_before != NoMargin {
-                writeln!(writer)?;
-            }
+            write_margin(writer, &settings.theme, attrs.margin_before)?;
             let StyledBlockAttrs { indent, style, .. } = attrs;
             stack
                 .push(attrs.into())
                 .current(write_start_code_block(
-                    writer, settings, indent, style, kind,
+                    writer,
+                    settings,
+                    indent,
+                    style,
+                    kind,
+                    data.code_block_width,
                 )?)
                 .and_data(data)
                 .ok()
         }
 
         // Lists
-        (Stacked(stack, Inline(ListItem(kind, state), attrs)), Start(Item)) => {
+        //
+        // We render definition lists like lists, with a bold title instead of a bullet, and the
+        // definitions indented below their title.
+        (
+            Stacked(stack, Inline(ListItem(kind, state), attrs)),
+            Start(Item | DefinitionListDefinition),
+        ) => {
             let InlineAttrs { indent, style, .. } = attrs;
             if state == ItemBlock {
                 // Add margin
@@ -295,11 +463,15 @@ pub fn write_event<'a, W: Write>(
             write_indent(writer, indent)?;
             let indent = match kind {
                 ListItemKind::Unordered => {
-                    write!(writer, "\u{2022} ")?;
+                    write!(writer, "{} ", settings.theme.glyphs.bullet)?;
                     indent + 2
                 }
-                ListItemKind::Ordered(no) => {
-                    write!(writer, "{no:>2}. ")?;
+                ListItemKind::Ordered(no, width) => {
+                    write!(writer, "{no:>width$}. ", width = usize::from(width))?;
+                    indent + width + 2
+                }
+                ListItemKind::Definition => {
+                    write_indent(writer, 4)?;
                     indent + 4
                 }
             };
@@ -308,10 +480,11 @@ pub fn write_event<'a, W: Write>(
                     ListItem(kind, StartItem),
                     InlineAttrs { style, indent },
                 ))
-                .and_data(data.current_line(CurrentLine {
-                    length: indent,
-                    trailing_space: None,
-                }))
+                // The indent includes the bullet, so the line is empty as far as wrapping goes
+                .and_data(
+                    data.current_line(CurrentLine::empty())
+                        .push_list_item_indent(attrs.indent),
+                )
                 .ok()
         }
         (Stacked(stack, Inline(ListItem(kind, state), attrs)), Start(Paragraph)) => {
@@ -342,6 +515,7 @@ pub fn write_event<'a, W: Write>(
                         style: settings.theme.html_block_style.on_top_of(&style),
                         indent,
                         initial_indent,
+                        open_line: false,
                     }
                     .into(),
                 )
@@ -353,7 +527,14 @@ pub fn write_event<'a, W: Write>(
             let InlineAttrs { indent, style, .. } = attrs;
             stack
                 .push(Inline(ListItem(kind, ItemBlock), attrs))
-                .current(write_start_code_block(writer, settings, indent, style, ck)?)
+                .current(write_start_code_block(
+                    writer,
+                    settings,
+                    indent,
+                    style,
+                    ck,
+                    data.code_block_width,
+                )?)
                 .and_data(data)
                 .ok()
         }
@@ -364,7 +545,11 @@ pub fn write_event<'a, W: Write>(
                 writer,
                 &settings.terminal_capabilities,
                 &settings.theme,
-                settings.terminal_size.columns - attrs.indent,
+                settings
+                    .terminal_size
+                    .columns
+                    .saturating_sub(attrs.indent)
+                    .max(1),
             )?;
             writeln!(writer)?;
             stack
@@ -385,6 +570,7 @@ pub fn write_event<'a, W: Write>(
                     writer,
                     &settings.terminal_capabilities,
                     settings.theme.heading_style.on_top_of(&style),
+                    &settings.theme.glyphs,
                     level,
                 )?)
                 .and_data(data)
@@ -392,9 +578,7 @@ pub fn write_event<'a, W: Write>(
         }
         (Stacked(stack, Inline(ListItem(kind, _), attrs)), Start(List(start))) => {
             writeln!(writer)?;
-            let nested_kind = start.map_or(ListItemKind::Unordered, |start| {
-                ListItemKind::Ordered(start)
-            });
+            let nested_kind = ListItemKind::for_list(start, data.ordered_list_width);
             stack
                 .push(Inline(ListItem(kind, ItemBlock), attrs.clone()))
                 .current(Inline(ListItem(nested_kind, StartItem), attrs))
@@ -403,17 +587,48 @@ pub fn write_event<'a, W: Write>(
         }
         (Stacked(stack, Inline(ListItem(kind, _), attrs)), Start(BlockQuote(_))) => {
             writeln!(writer)?;
+            let data = data.enter_block_quote();
+            let depth_style = settings.theme.block_quote_style(data.block_quote_depth);
             let block_quote = StyledBlockAttrs::from(&attrs)
                 .without_margin_before()
-                .block_quote();
+                .block_quote(depth_style);
             stack
                 .push(Inline(ListItem(kind, ItemBlock), attrs))
                 .current(block_quote.into())
                 .and_data(data)
                 .ok()
         }
-        (Stacked(stack, Inline(ListItem(kind, state), attrs)), End(TagEnd::Item)) => {
-            let InlineAttrs { indent, style, .. } = attrs;
+        (Stacked(stack, Inline(ListItem(kind, state), attrs)), Start(DefinitionListTitle)) => {
+            if state == ItemBlock {
+                writeln!(writer)?;
+            }
+            write_indent(writer, attrs.indent)?;
+            let InlineAttrs { indent, style } = attrs;
+            stack
+                .push(Inline(ListItem(kind, StartItem), attrs))
+                .current(Inline(
+                    InlineText,
+                    InlineAttrs {
+                        indent,
+                        style: style.effects(style.get_effects() | Effects::BOLD),
+                    },
+                ))
+                .and_data(data)
+                .ok()
+        }
+        (Stacked(stack, Inline(ListItem(kind, _), attrs)), Start(DefinitionList)) => {
+            writeln!(writer)?;
+            stack
+                .push(Inline(ListItem(kind, ItemBlock), attrs.clone()))
+                .current(Inline(ListItem(ListItemKind::Definition, StartItem), attrs))
+                .and_data(data)
+                .ok()
+        }
+        (
+            Stacked(stack, Inline(ListItem(kind, state), attrs)),
+            End(TagEnd::Item | TagEnd::DefinitionListDefinition),
+        ) => {
+            let InlineAttrs { style, .. } = attrs;
             let data = if state != ItemBlock {
                 // End the inline text of this item
                 writeln!(writer)?;
@@ -421,10 +636,11 @@ pub fn write_event<'a, W: Write>(
             } else {
                 data
             };
-            // Decrease indent back to the level where we can write the next item bullet, and increment the list item number.
-            let (indent, kind) = match kind {
-                ListItemKind::Unordered => (indent - 2, ListItemKind::Unordered),
-                ListItemKind::Ordered(no) => (indent - 4, ListItemKind::Ordered(no + 1)),
+            // Go back to the indent where we can write the next item bullet, and increment the list item number.
+            let (data, indent) = data.pop_list_item_indent();
+            let kind = match kind {
+                ListItemKind::Ordered(no, width) => ListItemKind::Ordered(no + 1, width),
+                kind => kind,
             };
             stack
                 .current(Inline(ListItem(kind, state), InlineAttrs { style, indent }))
@@ -433,22 +649,67 @@ pub fn write_event<'a, W: Write>(
         }
 
         // Literal blocks without highlighting
+        (Stacked(stack, LiteralBlock(attrs)), Text(text)) if attrs.infer_syntax => {
+            let syntax = LinesWithEndings::from(&text)
+                .next()
+                .and_then(|line| settings.syntax_set.find_syntax_by_first_line(line));
+            let state: StackedState = match syntax {
+                Some(syntax) => {
+                    event!(
+                        Level::TRACE,
+                        "Inferred syntax {} for indented code block",
+                        syntax.name
+                    );
+                    HighlightBlockAttrs::new(
+                        syntax,
+                        settings.syntax_theme,
+                        attrs.indent,
+                        attrs.border_width,
+                    )
+                    .into()
+                }
+                None => LiteralBlockAttrs {
+                    infer_syntax: false,
+                    ..attrs
+                }
+                .into(),
+            };
+            write_event(
+                writer,
+                settings,
+                environment,
+                resource_handler,
+                stack.current(state),
+                data,
+                Text(text),
+            )
+        }
         (Stacked(stack, LiteralBlock(attrs)), Text(text)) => {
             let LiteralBlockAttrs { indent, style, .. } = attrs;
             for line in LinesWithEndings::from(&text) {
-                write_styled(writer, &settings.terminal_capabilities, &style, line)?;
+                let line = expand_tabs(line, settings.tab_width);
+                write_styled(writer, &settings.terminal_capabilities, &style, &line)?;
                 if line.ends_with('\n') {
                     write_indent(writer, indent)?;
                 }
             }
+            let attrs = LiteralBlockAttrs {
+                open_line: !text.ends_with('\n'),
+                ..attrs
+            };
             stack.current(attrs.into()).and_data(data).ok()
         }
-        (Stacked(stack, LiteralBlock(_)), End(TagEnd::CodeBlock)) => {
+        (Stacked(stack, LiteralBlock(attrs)), End(TagEnd::CodeBlock)) => {
+            // The last line lacks a newline if the document ends inside an unclosed code block
+            if attrs.open_line {
+                writeln!(writer)?;
+                write_indent(writer, attrs.indent)?;
+            }
             write_code_block_border(
                 writer,
                 &settings.theme,
                 &settings.terminal_capabilities,
-                &settings.terminal_size,
+                attrs.border_width,
             )?;
             stack.pop().and_data(data).ok()
         }
@@ -458,6 +719,7 @@ pub fn write_event<'a, W: Write>(
                 indent,
                 initial_indent,
                 style,
+                ..
             } = attrs;
             for (n, line) in LinesWithEndings::from(&text).enumerate() {
                 let line_indent = if n == 0 { initial_indent } else { indent };
@@ -470,6 +732,7 @@ pub fn write_event<'a, W: Write>(
                         initial_indent: attrs.indent,
                         indent: attrs.indent,
                         style: attrs.style,
+                        open_line: !text.ends_with('\n'),
                     }
                     .into(),
                 )
@@ -478,44 +741,160 @@ pub fn write_event<'a, W: Write>(
         }
         (Stacked(stack, HtmlBlock(attrs)), Html(html)) => {
             write_indent(writer, attrs.initial_indent)?;
-            // TODO: Split html into lines and properly account for initial indent
-            write_styled(writer, &settings.terminal_capabilities, &attrs.style, html)?;
+            let fragments = if settings.render_html_images_and_links {
+                split_html(&html)
+            } else {
+                vec![HtmlFragment::Html(&html)]
+            };
+            let mut data = data;
+            for fragment in fragments {
+                let (events, fragment_html) = match fragment {
+                    HtmlFragment::Html(html) => {
+                        // TODO: Split html into lines and properly account for initial indent
+                        write_styled(writer, &settings.terminal_capabilities, &attrs.style, html)?;
+                        continue;
+                    }
+                    HtmlFragment::Image {
+                        src,
+                        alt,
+                        title,
+                        html,
+                    } => (image_events(src, alt, title), html),
+                    HtmlFragment::Link {
+                        href,
+                        title,
+                        text,
+                        html,
+                    } => (link_events(href, title, text), html),
+                };
+                // Render images and links as inline text within the HTML block.  Render into a
+                // buffer first, and write the HTML literally if the events do not end in the
+                // inline state they started in.
+                let inline = || {
+                    State::stack_onto(TopLevelAttrs::default()).current(Inline(
+                        InlineText,
+                        InlineAttrs {
+                            style: Style::new(),
+                            indent: attrs.indent,
+                        },
+                    ))
+                };
+                let mut buffer = RecordingSink::default();
+                let StateAndData(state, new_data) = write_events(
+                    &mut buffer,
+                    settings,
+                    environment,
+                    resource_handler,
+                    inline(),
+                    data.current_line(CurrentLine::empty()),
+                    events,
+                )?;
+                data = new_data;
+                if state == inline() {
+                    buffer.replay(writer)?;
+                } else {
+                    event!(
+                        Level::WARN,
+                        ?state,
+                        "Unexpected state after HTML fragment, writing HTML literally"
+                    );
+                    write_styled(
+                        writer,
+                        &settings.terminal_capabilities,
+                        &attrs.style,
+                        fragment_html,
+                    )?;
+                }
+            }
             stack
                 .current(
                     HtmlBlockAttrs {
                         initial_indent: attrs.indent,
                         indent: attrs.indent,
                         style: attrs.style,
+                        open_line: !html.ends_with('\n'),
                     }
                     .into(),
                 )
                 .and_data(data)
                 .ok()
         }
+        // Front matter
+        (Stacked(stack, FrontMatter(mut attrs)), Text(text)) => {
+            attrs.contents.push_str(&text);
+            stack.current(attrs.into()).and_data(data).ok()
+        }
+        (Stacked(stack, FrontMatter(attrs)), End(TagEnd::MetadataBlock(_))) => {
+            let state = stack.pop();
+            match front_matter_title(attrs.kind, &attrs.contents) {
+                Some(title) if settings.render_front_matter_title => {
+                    let title = title.to_owned();
+                    write_events(
+                        writer,
+                        settings,
+                        environment,
+                        resource_handler,
+                        state,
+                        data,
+                        vec![
+                            Start(Heading {
+                                level: HeadingLevel::H1,
+                                id: None,
+                                classes: Vec::new(),
+                                attrs: Vec::new(),
+                            }),
+                            Text(title.into()),
+                            End(TagEnd::Heading(HeadingLevel::H1)),
+                        ],
+                    )
+                }
+                _ => state.and_data(data).ok(),
+            }
+        }
+
+        // The last line of an HTML block lacks a newline if the document ends right after it
+        (Stacked(stack, HtmlBlock(attrs)), End(TagEnd::HtmlBlock)) => {
+            if attrs.open_line {
+                writeln!(writer)?;
+            }
+            stack.pop().and_data(data).ok()
+        }
 
         // Highlighted code blocks
         (Stacked(stack, HighlightBlock(mut attrs)), Text(text)) => {
+            let custom_highlighter = settings.syntax_theme.map(Highlighter::new);
+            let highlighter = custom_highlighter.as_ref().unwrap_or_else(|| highlighter());
             for line in LinesWithEndings::from(&text) {
+                let line = expand_tabs(line, settings.tab_width);
                 let ops = attrs
                     .parse_state
-                    .parse_line(line, settings.syntax_set)
+                    .parse_line(&line, settings.syntax_set)
                     .expect("syntect parsing shouldn't fail in mdcat");
-                highlighting::write_as_ansi(
-                    writer,
-                    HighlightIterator::new(&mut attrs.highlight_state, &ops, line, highlighter()),
-                )?;
+                let regions =
+                    HighlightIterator::new(&mut attrs.highlight_state, &ops, &line, highlighter);
+                match settings.syntax_theme {
+                    // Solarized maps to ANSI colours, but other themes need their exact colours
+                    None => highlighting::write_as_ansi(writer, regions)?,
+                    Some(_) => highlighting::write_as_rgb(writer, regions)?,
+                }
                 if text.ends_with('\n') {
                     write_indent(writer, attrs.indent)?;
                 }
             }
+            attrs.open_line = !text.ends_with('\n');
             stack.current(attrs.into()).and_data(data).ok()
         }
-        (Stacked(stack, HighlightBlock(_)), End(TagEnd::CodeBlock)) => {
+        (Stacked(stack, HighlightBlock(attrs)), End(TagEnd::CodeBlock)) => {
+            // The last line lacks a newline if the document ends inside an unclosed code block
+            if attrs.open_line {
+                writeln!(writer)?;
+                write_indent(writer, attrs.indent)?;
+            }
             write_code_block_border(
                 writer,
                 &settings.theme,
                 &settings.terminal_capabilities,
-                &settings.terminal_size,
+                attrs.border_width,
             )?;
             stack.pop().and_data(data).ok()
         }
@@ -541,6 +920,19 @@ pub fn write_event<'a, W: Write>(
                 .and_data(data)
                 .ok()
         }
+        (Stacked(stack, Inline(state, attrs)), Start(Strikethrough))
+            if settings.theme.strikethrough_overlay =>
+        {
+            let data = StateData {
+                overlay_strikethroughs: data.overlay_strikethroughs + 1,
+                ..data
+            };
+            stack
+                .push(Inline(state, attrs.clone()))
+                .current(Inline(state, attrs))
+                .and_data(data)
+                .ok()
+        }
         (Stacked(stack, Inline(state, attrs)), Start(Strikethrough)) => {
             let InlineAttrs { indent, .. } = attrs;
             let style = attrs.style.strikethrough();
@@ -550,6 +942,15 @@ pub fn write_event<'a, W: Write>(
                 .and_data(data)
                 .ok()
         }
+        (Stacked(stack, Inline(_, _)), End(TagEnd::Strikethrough))
+            if settings.theme.strikethrough_overlay =>
+        {
+            let data = StateData {
+                overlay_strikethroughs: data.overlay_strikethroughs.saturating_sub(1),
+                ..data
+            };
+            stack.pop().and_data(data).ok()
+        }
         (
             Stacked(stack, Inline(_, _)),
             End(TagEnd::Strong | TagEnd::Emphasis | TagEnd::Strikethrough),
@@ -558,7 +959,7 @@ pub fn write_event<'a, W: Write>(
             let current_line = write_styled_and_wrapped(
                 writer,
                 &settings.terminal_capabilities,
-                &settings.theme.code_style.on_top_of(&attrs.style),
+                &settings.theme.inline_code_style.on_top_of(&attrs.style),
                 settings.terminal_size.columns,
                 attrs.indent,
                 data.current_line,
@@ -571,15 +972,16 @@ pub fn write_event<'a, W: Write>(
             Ok(stack.current(Inline(state, attrs)).and_data(data))
         }
 
-        (Stacked(stack, Inline(state, attrs)), InlineHtml(html)) => {
+        // We do not typeset math, but show the math source literally, like inline code.
+        (Stacked(stack, Inline(state, attrs)), InlineMath(math) | DisplayMath(math)) => {
             let current_line = write_styled_and_wrapped(
                 writer,
                 &settings.terminal_capabilities,
-                &settings.theme.inline_html_style.on_top_of(&attrs.style),
+                &settings.theme.math_style.on_top_of(&attrs.style),
                 settings.terminal_size.columns,
                 attrs.indent,
                 data.current_line,
-                html,
+                math.trim(),
             )?;
             let data = StateData {
                 current_line,
@@ -587,21 +989,76 @@ pub fn write_event<'a, W: Write>(
             };
             Ok(stack.current(Inline(state, attrs)).and_data(data))
         }
+        (Stacked(stack, Inline(state, attrs)), InlineHtml(html)) => {
+            let tag = if settings.render_html_images_and_links {
+                parse_tag(&html)
+                    .filter(|(_, rest)| rest.trim().is_empty())
+                    .map(|(tag, _)| tag)
+            } else {
+                None
+            };
+            let inside_link = state == InlineLink || data.has_pending_link();
+            match tag {
+                Some(HtmlTag::Image { src, alt, title }) => write_events(
+                    writer,
+                    settings,
+                    environment,
+                    resource_handler,
+                    stack.current(Inline(state, attrs)),
+                    data,
+                    image_events(src, alt, title),
+                ),
+                // We cannot nest links, so keep nested HTML links literally
+                Some(HtmlTag::Link { href, title }) if !inside_link => {
+                    let link = PendingHtmlLink {
+                        tag: html,
+                        href,
+                        title,
+                        events: Vec::new(),
+                        nesting: 0,
+                    };
+                    Ok(stack
+                        .current(Inline(state, attrs))
+                        .and_data(data.pending_html_link(link)))
+                }
+                _ => write_literal_inline_html(writer, settings, stack, state, attrs, data, &html),
+            }
+        }
         (Stacked(stack, Inline(inline, attrs)), TaskListMarker(checked)) => {
-            let marker = if checked { "\u{2611}" } else { "\u{2610}" };
+            let glyphs = &settings.theme.glyphs;
+            let marker = if checked {
+                glyphs.task_checked
+            } else {
+                glyphs.task_unchecked
+            };
             write_styled(
                 writer,
                 &settings.terminal_capabilities,
                 &attrs.style,
                 marker,
             )?;
-            let length = data.current_line.length + display_width(marker) as u16;
+            write!(writer, " ")?;
+            let width = display_width(marker) as u16;
+            // Align continuation lines and nested blocks of the item with the text after the
+            // marker, i.e. make the marker part of the indent, like the bullet.  In loose lists
+            // the marker is inside the first paragraph of the item, so indent the item below the
+            // paragraph as well.
+            let indent_item = |attrs: InlineAttrs| InlineAttrs {
+                indent: attrs.indent + width + 1,
+                ..attrs
+            };
+            let stack = match inline {
+                ListItem(..) => stack,
+                _ => stack.map_last(|state| match state {
+                    Inline(ListItem(kind, state), item_attrs) => {
+                        Inline(ListItem(kind, state), indent_item(item_attrs))
+                    }
+                    state => state,
+                }),
+            };
             Ok(stack
-                .current(Inline(inline, attrs))
-                .and_data(data.current_line(CurrentLine {
-                    length,
-                    trailing_space: Some(" ".to_owned()),
-                })))
+                .current(Inline(inline, indent_item(attrs)))
+                .and_data(data))
         }
         // Inline line breaks
         (Stacked(stack, Inline(state, attrs)), SoftBreak) => {
@@ -669,7 +1126,7 @@ pub fn write_event<'a, W: Write>(
                 .pop()
                 .and_data(data.current_line(CurrentLine::empty())))
         }
-        (Stacked(stack, Inline(_, _)), End(TagEnd::Heading(_))) => {
+        (Stacked(stack, Inline(_, _)), End(TagEnd::Heading(_) | TagEnd::DefinitionListTitle)) => {
             writeln!(writer)?;
             Ok(stack
                 .pop()
@@ -689,6 +1146,8 @@ pub fn write_event<'a, W: Write>(
                 ..
             }),
         ) => {
+            // OSC 8 has no parameter for a link title, so inline links drop the title; only link
+            // references show it.
             let maybe_link = settings
                 .terminal_capabilities
                 .style
@@ -705,9 +1164,19 @@ pub fn write_event<'a, W: Write>(
             let (link_state, data) = match maybe_link {
                 None => (
                     InlineText,
-                    data.push_pending_link(link_type, dest_url, title),
+                    data.push_pending_link(LinkReferenceKind::Link, link_type, dest_url, title),
                 ),
                 Some(url) => {
+                    // Remember the URL to show it after the link text
+                    let data = match settings.link_style {
+                        LinkStyle::Inline => data.push_pending_link(
+                            LinkReferenceKind::Link,
+                            link_type,
+                            dest_url,
+                            title,
+                        ),
+                        LinkStyle::Reference | LinkStyle::Hidden => data,
+                    };
                     let data = match data.current_line.trailing_space.as_ref() {
                         Some(space) => {
                             // Flush trailing space before starting a link
@@ -720,7 +1189,12 @@ pub fn write_event<'a, W: Write>(
                         }
                         None => data,
                     };
-                    set_link_url(writer, url, &environment.hostname)?;
+                    set_link_url(
+                        writer,
+                        settings.terminal_capabilities.passthrough,
+                        url,
+                        &environment.hostname,
+                    )?;
                     (InlineLink, data)
                 }
             };
@@ -746,22 +1220,56 @@ pub fn write_event<'a, W: Write>(
                     // to the link text, was already written.
                     stack.pop().and_data(data).ok()
                 }
-                _ => {
-                    let (data, index) = data.add_link_reference(
-                        link.dest_url,
-                        link.title,
-                        settings.theme.link_style,
-                    );
-                    write_styled(
-                        writer,
-                        &settings.terminal_capabilities,
-                        &settings.theme.link_style.on_top_of(&attrs.style),
-                        format!("[{index}]"),
-                    )?;
-                    stack.pop().and_data(data).ok()
-                }
+                _ => match settings.link_style {
+                    LinkStyle::Reference => {
+                        let (data, index) = data.add_link_reference(
+                            LinkReferenceKind::Link,
+                            link.dest_url,
+                            link.title,
+                            settings.theme.link_style,
+                        );
+                        write_styled(
+                            writer,
+                            &settings.terminal_capabilities,
+                            &settings.theme.link_style.on_top_of(&attrs.style),
+                            format!("[{index}]"),
+                        )?;
+                        stack.pop().and_data(data).ok()
+                    }
+                    LinkStyle::Inline => {
+                        let data = write_inline_link_url(
+                            writer,
+                            &settings.terminal_capabilities,
+                            &attrs.style,
+                            &link.dest_url,
+                            data,
+                        )?;
+                        stack.pop().and_data(data).ok()
+                    }
+                    LinkStyle::Hidden => stack.pop().and_data(data).ok(),
+                },
             }
         }
+        (Stacked(stack, Inline(InlineLink, attrs)), End(TagEnd::Link))
+            if settings.link_style == LinkStyle::Inline =>
+        {
+            clear_link(writer, settings.terminal_capabilities.passthrough)?;
+            let (data, link) = data.pop_pending_link();
+            let data = match link.link_type {
+                LinkType::Autolink | LinkType::Email => data,
+                _ => write_inline_link_url(
+                    writer,
+                    &settings.terminal_capabilities,
+                    &attrs.style,
+                    &link.dest_url,
+                    data,
+                )?,
+            };
+            stack
+                .pop()
+                .and_data(data.image_description_width(None))
+                .ok()
+        }
 
         // Images
         (
@@ -775,84 +1283,202 @@ pub fn write_event<'a, W: Write>(
         ) => {
             let InlineAttrs { style, indent } = attrs;
             let resolved_link = environment.resolve_reference(&dest_url);
-            let image_state = match (settings.terminal_capabilities.image, resolved_link) {
-                (Some(capability), Some(ref url)) => capability
-                    .image_protocol()
-                    .write_inline_image(writer, &resource_handler, url, settings.terminal_size)
-                    .map_err(|error| {
-                        event!(Level::ERROR, ?error, %url, "failed to render image with capability {:?}: {:#}", capability, error);
-                        error
+            // Record the image data to describe the image in a caption.
+            let recording_handler = settings
+                .image_dimensions
+                .then(|| RecordingResourceHandler::new(resource_handler));
+            let image_handler: &dyn ResourceUrlHandler = match &recording_handler {
+                Some(handler) => handler,
+                None => resource_handler,
+            };
+            let image_line = resolved_link.as_ref().and_then(|url| {
+                write_image(
+                    writer,
+                    &settings.terminal_capabilities,
+                    settings.terminal_size,
+                    image_handler,
+                    url,
+                    indent,
+                    data.current_line.clone(),
+                )
+            });
+            let rendered_image = image_line.is_some();
+            let data = match image_line {
+                Some(current_line) => data.current_line(current_line),
+                None => data,
+            };
+            let caption = recording_handler
+                .filter(|_| rendered_image)
+                .and_then(|handler| handler.take_last_resource())
+                .and_then(|mime_data| image_caption(&mime_data));
+            let data = match caption {
+                Some(caption) => {
+                    writeln!(writer)?;
+                    write_indent(writer, indent)?;
+                    write_styled(
+                        writer,
+                        &settings.terminal_capabilities,
+                        &style.dimmed(),
+                        &caption,
+                    )?;
+                    data.current_line(CurrentLine {
+                        length: display_width(&caption) as u16,
+                        trailing_space: None,
                     })
-                    .map(|_| RenderedImage)
-                    .ok(),
-                (None, Some(url)) =>
-                    if let InlineLink = state {
-                        event!(Level::WARN, url = %url, "Terminal does not support images, want to render image as link but cannot: Already inside a link");
-                        None
-                    } else {
-                        event!(Level::INFO, url = %url, "Terminal does not support images, rendering image as link");
-                        match settings.terminal_capabilities.style {
-                            Some(StyleCapability::Ansi) => {
-                                set_link_url(writer, url, &environment.hostname)?;
-                                Some(Inline(
-                                    InlineLink,
-                                    InlineAttrs {
-                                        indent,
-                                        style: settings.theme.image_link_style.on_top_of(&style),
-                                    },
-                                ))
-                            },
-                            None => None,
+                }
+                None => data,
+            };
+            let inside_link = state == InlineLink || data.has_pending_link();
+            // Falling back is expected if the terminal doesn't support images at all, as opposed to
+            // images which we failed to render or whose URL is invalid.
+            let expected_fallback = settings.quiet_image_fallbacks
+                && settings.terminal_capabilities.image.is_none()
+                && resolved_link.is_some();
+            // If we failed to render the image, either because the terminal doesn't support images
+            // or because rendering failed, try to write the image as inline link to the image URL.
+            let image_state = match resolved_link {
+                Some(_) if rendered_image => Some(RenderedImage(RenderedImageAttrs {
+                    nested_images: 0,
+                    caption: settings.image_alt_captions.then(String::new),
+                    indent,
+                    style,
+                })),
+                // We cannot nest links, and the surrounding link is more important than the image,
+                // so just write the image description as part of the link text, e.g. for badges.
+                _ if inside_link => {
+                    image_fallback_event!(
+                        expected_fallback,
+                        Level::INFO,
+                        "Image {} not rendered, rendering description as part of the surrounding link",
+                        dest_url
+                    );
+                    Some(Inline(LinkedImageText, InlineAttrs { indent, style }))
+                }
+                Some(url) => {
+                    image_fallback_event!(
+                        expected_fallback,
+                        Level::INFO,
+                        url = %url,
+                        "Image not rendered, rendering image as link"
+                    );
+                    match settings.terminal_capabilities.style {
+                        Some(StyleCapability::Ansi) => {
+                            set_link_url(
+                                writer,
+                                settings.terminal_capabilities.passthrough,
+                                url,
+                                &environment.hostname,
+                            )?;
+                            Some(Inline(
+                                InlineLink,
+                                InlineAttrs {
+                                    indent,
+                                    style: settings.theme.image_link_style.on_top_of(&style),
+                                },
+                            ))
                         }
-                    },
-                (_, None) => None,
+                        None => None,
+                    }
+                }
+                None => None,
             };
 
             let (image_state, data) = match image_state {
                 Some(state) => (state, data),
                 None => {
-                    event!(
+                    image_fallback_event!(
+                        expected_fallback,
                         Level::WARN,
                         "Rendering image {} as inline text, without link",
                         dest_url
                     );
-                    // Inside an inline link keep the link style; we cannot nest links so we
-                    // should clarify that clicking the link follows the link target and not the image.
-                    let style = if let InlineLink = state {
-                        style
-                    } else {
-                        settings.theme.image_link_style.on_top_of(&style)
-                    };
+                    let style = settings.theme.image_fallback_style.on_top_of(&style);
                     let state = Inline(InlineText, InlineAttrs { style, indent });
-                    (state, data.push_pending_link(link_type, dest_url, title))
+                    (
+                        state,
+                        data.push_pending_link(
+                            LinkReferenceKind::Image,
+                            link_type,
+                            dest_url,
+                            title,
+                        ),
+                    )
                 }
             };
+            let data = match image_state {
+                RenderedImage(_) => data,
+                _ => data.image_description_width(settings.max_image_description_width),
+            };
             stack
                 .push(Inline(state, attrs))
                 .current(image_state)
                 .and_data(data)
                 .ok()
         }
-        // To correctly handle nested images in the image description, we push a dummy rendered
-        // image state so to maintain a correct state stack at the end of image event, where the
-        // tail of the stack gets popped.
-        (Stacked(stack, RenderedImage), Start(Image { .. })) => stack
-            .push(RenderedImage)
-            .current(RenderedImage)
-            .and_data(data)
-            .ok(),
-        (Stacked(stack, RenderedImage), End(TagEnd::Image)) => stack.pop().and_data(data).ok(),
-        // Immediately after the start of image event comes the alt text, which we do not support
-        // for rendered images. So we just ignore all events other than image events, which are
-        // handled above.
+        // To correctly handle nested images in the image description, we count nested images, so
+        // that we only leave the rendered image at the end of the outermost image.
+        (Stacked(stack, RenderedImage(mut attrs)), Start(Image { .. })) => {
+            attrs.nested_images += 1;
+            Stacked(stack, RenderedImage(attrs)).and_data(data).ok()
+        }
+        (Stacked(stack, RenderedImage(mut attrs)), End(TagEnd::Image))
+            if 0 < attrs.nested_images =>
+        {
+            attrs.nested_images -= 1;
+            Stacked(stack, RenderedImage(attrs)).and_data(data).ok()
+        }
+        (Stacked(stack, RenderedImage(attrs)), End(TagEnd::Image)) => {
+            let data = match attrs.caption.as_deref().map(str::trim) {
+                Some(caption) if !caption.is_empty() => {
+                    writeln!(writer)?;
+                    write_indent(writer, attrs.indent)?;
+                    let current_line = write_styled_and_wrapped(
+                        writer,
+                        &settings.terminal_capabilities,
+                        &attrs.style.italic(),
+                        settings.terminal_size.columns,
+                        attrs.indent,
+                        CurrentLine::empty(),
+                        caption,
+                    )?;
+                    data.current_line(current_line)
+                }
+                _ => data,
+            };
+            stack.pop().and_data(data).ok()
+        }
+        // Immediately after the start of image event comes the description, which we write as
+        // caption if enabled, and otherwise ignore.
         //
         // See also https://docs.rs/pulldown-cmark/0.9.6/src/pulldown_cmark/html.rs.html#280-290 for
         // how the upstream handles images.
-        (Stacked(stack, RenderedImage), _) => Stacked(stack, RenderedImage).and_data(data).ok(),
+        (Stacked(stack, RenderedImage(mut attrs)), Text(text) | Code(text)) => {
+            if let Some(caption) = attrs.caption.as_mut() {
+                caption.push_str(&text);
+            }
+            Stacked(stack, RenderedImage(attrs)).and_data(data).ok()
+        }
+        (Stacked(stack, RenderedImage(mut attrs)), SoftBreak | HardBreak) => {
+            if let Some(caption) = attrs.caption.as_mut() {
+                caption.push(' ');
+            }
+            Stacked(stack, RenderedImage(attrs)).and_data(data).ok()
+        }
+        (Stacked(stack, RenderedImage(attrs)), _) => {
+            Stacked(stack, RenderedImage(attrs)).and_data(data).ok()
+        }
+        (Stacked(stack, Inline(LinkedImageText, _)), End(TagEnd::Image)) => stack
+            .pop()
+            .and_data(data.image_description_width(None))
+            .ok(),
         (Stacked(stack, Inline(InlineText, attrs)), End(TagEnd::Image)) => {
-            let (data, link) = data.pop_pending_link();
-            let (data, index) =
-                data.add_link_reference(link.dest_url, link.title, settings.theme.image_link_style);
+            let (data, link) = data.image_description_width(None).pop_pending_link();
+            let (data, index) = data.add_link_reference(
+                LinkReferenceKind::Image,
+                link.dest_url,
+                link.title,
+                settings.theme.image_link_style,
+            );
             write_styled(
                 writer,
                 &settings.terminal_capabilities,
@@ -866,8 +1492,11 @@ pub fn write_event<'a, W: Write>(
 
         // End any kind of inline link, either a proper link, or an image written out as inline link
         (Stacked(stack, Inline(InlineLink, _)), End(TagEnd::Link | TagEnd::Image)) => {
-            clear_link(writer)?;
-            stack.pop().and_data(data).ok()
+            clear_link(writer, settings.terminal_capabilities.passthrough)?;
+            stack
+                .pop()
+                .and_data(data.image_description_width(None))
+                .ok()
         }
 
         // Tables
@@ -900,7 +1529,10 @@ pub fn write_event<'a, W: Write>(
             };
             Stacked(stack, TableBlock).and_data(data).ok()
         }
-        (Stacked(stack, TableBlock), Text(text)) | (Stacked(stack, TableBlock), Code(text)) => {
+        (
+            Stacked(stack, TableBlock),
+            Text(text) | Code(text) | InlineMath(text) | DisplayMath(text),
+        ) => {
             let current_table = data.current_table.push_fragment(text);
             let data = StateData {
                 current_table,
@@ -912,7 +1544,9 @@ pub fn write_event<'a, W: Write>(
             write_table(
                 writer,
                 &settings.terminal_capabilities,
+                &settings.theme.glyphs,
                 &settings.terminal_size,
+                settings.indent,
                 data.current_table,
             )?;
             let current_table = data::CurrentTable::empty();
@@ -939,25 +1573,206 @@ pub fn write_event<'a, W: Write>(
         }
 
         // Unconditional returns to previous states
-        (Stacked(stack, _), End(TagEnd::BlockQuote(_) | TagEnd::List(_) | TagEnd::HtmlBlock)) => {
+        (Stacked(stack, _), End(TagEnd::BlockQuote(_))) => {
+            stack.pop().and_data(data.leave_block_quote()).ok()
+        }
+        (Stacked(stack, _), End(TagEnd::List(_) | TagEnd::DefinitionList | TagEnd::HtmlBlock)) => {
             stack.pop().and_data(data).ok()
         }
 
-        // Impossible events
-        (s, e) => panic!("Event {e:?} impossible in state {s:?}"),
+        // Impossible events, e.g. from events which did not come from a parser
+        (s, e) => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Event {e:?} impossible in state {s:?}"),
+        )),
+    }
+}
+
+/// What we know about an event from looking at the events which follow it.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Lookahead {
+    /// At the start of an ordered list, the width of the largest item number in the list.
+    pub ordered_list_width: Option<u16>,
+    /// At the start of a code block, the width of the widest line in the code block.
+    pub code_block_width: Option<u16>,
+}
+
+/// Annotate the start of ordered lists and code blocks with their widths.
+///
+/// Ordered lists align item numbers to the largest number in the list, and code blocks may size
+/// their borders to their contents, but `write_event` only sees one event at a time.  Hence, this
+/// iterator buffers all events of an ordered list or code block, and measures the list or block
+/// and all nested lists and blocks before passing on the events.
+pub struct LookaheadEvents<'a, I> {
+    events: I,
+    tab_width: Option<u16>,
+    buffer: VecDeque<(Event<'a>, Lookahead)>,
+}
+
+impl<'a, I: Iterator<Item = Event<'a>>> LookaheadEvents<'a, I> {
+    /// Look ahead at `events`, measuring code with tabs expanded to `tab_width`.
+    pub fn new(events: I, tab_width: Option<u16>) -> Self {
+        Self {
+            events,
+            tab_width,
+            buffer: VecDeque::new(),
+        }
+    }
+
+    /// Buffer all events of the list or code block which starts with `start`.
+    fn buffer_block(&mut self, start: Event<'a>) {
+        let mut events = vec![start];
+        let mut depth = 1;
+        while 0 < depth {
+            match self.events.next() {
+                None => break,
+                Some(event) => {
+                    match event {
+                        Start(List(_)) | Start(CodeBlock(_)) => depth += 1,
+                        End(TagEnd::List(_)) | End(TagEnd::CodeBlock) => depth -= 1,
+                        _ => {}
+                    }
+                    events.push(event);
+                }
+            }
+        }
+
+        let mut annotations = vec![Lookahead::default(); events.len()];
+        // The index of the start event, the start number and the number of items of all open lists
+        let mut lists: Vec<(usize, Option<u64>, u64)> = Vec::new();
+        // The index of the start event and the text of the current code block
+        let mut code_block: Option<(usize, String)> = None;
+        for (index, event) in events.iter().enumerate() {
+            match event {
+                Start(List(start)) => lists.push((index, *start, 0)),
+                Start(Item) => {
+                    if let Some((_, _, items)) = lists.last_mut() {
+                        *items += 1;
+                    }
+                }
+                End(TagEnd::List(_)) => {
+                    if let Some((index, Some(start), items)) = lists.pop() {
+                        let largest = start.saturating_add(items.saturating_sub(1));
+                        annotations[index].ordered_list_width =
+                            Some(largest.to_string().len() as u16);
+                    }
+                }
+                Start(CodeBlock(_)) => code_block = Some((index, String::new())),
+                Text(text) => {
+                    if let Some((_, code)) = code_block.as_mut() {
+                        code.push_str(text);
+                    }
+                }
+                End(TagEnd::CodeBlock) => {
+                    if let Some((index, code)) = code_block.take() {
+                        let width = code
+                            .lines()
+                            .map(|line| display_width(&expand_tabs(line, self.tab_width)))
+                            .max()
+                            .unwrap_or(0);
+                        annotations[index].code_block_width =
+                            Some(u16::try_from(width).unwrap_or(u16::MAX));
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.buffer.extend(events.into_iter().zip(annotations));
+    }
+}
+
+impl<'a, I: Iterator<Item = Event<'a>>> Iterator for LookaheadEvents<'a, I> {
+    type Item = (Event<'a>, Lookahead);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(item) = self.buffer.pop_front() {
+            return Some(item);
+        }
+        match self.events.next()? {
+            event @ (Start(List(Some(_))) | Start(CodeBlock(_))) => {
+                self.buffer_block(event);
+                self.buffer.pop_front()
+            }
+            event => Some((event, Lookahead::default())),
+        }
     }
 }
 
+/// Whether `html` is a closing `</a>` tag.
+fn is_html_link_end(html: &str) -> bool {
+    matches!(parse_tag(html), Some((HtmlTag::LinkEnd, rest)) if rest.trim().is_empty())
+}
+
+/// Write inline `html` literally, in the inline `state` on top of `stack`.
+fn write_literal_inline_html<'a, W: StyleSink>(
+    writer: &mut W,
+    settings: &Settings,
+    stack: StateStack,
+    state: InlineState,
+    attrs: InlineAttrs,
+    data: StateData<'a>,
+    html: &str,
+) -> Result<StateAndData<StateData<'a>>> {
+    let current_line = write_styled_and_wrapped(
+        writer,
+        &settings.terminal_capabilities,
+        &settings.theme.inline_html_style.on_top_of(&attrs.style),
+        settings.terminal_size.columns,
+        attrs.indent,
+        data.current_line,
+        html,
+    )?;
+    let data = StateData {
+        current_line,
+        ..data
+    };
+    Ok(stack
+        .current(StackedState::Inline(state, attrs))
+        .and_data(data))
+}
+
+/// Write all `events` in turn, starting at `state`.
+fn write_events<'a, W: StyleSink>(
+    writer: &mut W,
+    settings: &Settings,
+    environment: &Environment,
+    resource_handler: &dyn ResourceUrlHandler,
+    state: State,
+    data: StateData<'a>,
+    events: Vec<Event<'a>>,
+) -> Result<StateAndData<StateData<'a>>> {
+    events
+        .into_iter()
+        .try_fold(state.and_data(data), |StateAndData(state, data), event| {
+            write_event(
+                writer,
+                settings,
+                environment,
+                resource_handler,
+                state,
+                data,
+                event,
+            )
+        })
+}
+
 #[instrument(level = "trace", skip(writer, settings, environment))]
-pub fn finish<'a, W: Write>(
+pub fn finish<'a, W: StyleSink>(
     writer: &mut W,
     settings: &Settings,
     environment: &Environment,
     state: State,
     data: StateData<'a>,
-) -> Result<()> {
+) -> Result<RenderSummary> {
     match state {
         State::TopLevel(_) => {
+            let summary = RenderSummary {
+                unknown_languages: data
+                    .unknown_languages
+                    .iter()
+                    .map(|language| language.to_string())
+                    .collect(),
+            };
             event!(
                 Level::TRACE,
                 "Writing {} pending link definitions",
@@ -967,12 +1782,33 @@ pub fn finish<'a, W: Write>(
                 writer,
                 environment,
                 &settings.terminal_capabilities,
+                settings.indent,
                 data.pending_link_definitions,
             )?;
-            Ok(())
+            write_style_reset(writer, &settings.terminal_capabilities)?;
+            Ok(summary)
         }
         _ => {
-            panic!("Must finish in state TopLevel but got: {state:?}");
+            // The events ended prematurely; end the output cleanly nonetheless.
+            write_style_reset(writer, &settings.terminal_capabilities)?;
+            if !state.at_line_start() {
+                writeln!(writer)?;
+            }
+            Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Events ended in state {state:?}, expected TopLevel"),
+            ))
         }
     }
 }
+
+/// Reset styles after rendering failed with an error.
+///
+/// Make a best effort to not leave the terminal in a styled state, and ignore all errors.
+pub fn reset_after_error<W: StyleSink>(writer: &mut W, settings: &Settings) {
+    if let Err(error) =
+        write_style_reset(writer, &settings.terminal_capabilities).and_then(|_| writeln!(writer))
+    {
+        event!(Level::DEBUG, %error, "Failed to reset styles after error");
+    }
+}
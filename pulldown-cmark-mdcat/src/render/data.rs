@@ -5,11 +5,13 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use anstyle::Style;
-use pulldown_cmark::{Alignment, CowStr, LinkType};
+use pulldown_cmark::{Alignment, CowStr, Event, LinkType};
 
 /// A pending link.
 #[derive(Debug, PartialEq)]
 pub struct PendingLink<'a> {
+    /// Whether this is a link or an image.
+    pub(crate) kind: LinkReferenceKind,
     /// The type of this link.
     pub(crate) link_type: LinkType,
     /// The destination URL of this link.
@@ -18,11 +20,37 @@ pub struct PendingLink<'a> {
     pub(crate) title: CowStr<'a>,
 }
 
+/// A link from a HTML `<a>` tag whose closing `</a>` tag we didn't see yet.
+#[derive(Debug, PartialEq)]
+pub struct PendingHtmlLink<'a> {
+    /// The literal opening tag.
+    pub(crate) tag: CowStr<'a>,
+    /// The destination URL of this link.
+    pub(crate) href: String,
+    /// The title of this link.
+    pub(crate) title: String,
+    /// All events after the opening tag.
+    pub(crate) events: Vec<Event<'a>>,
+    /// The number of inline elements opened after the opening tag and not closed yet.
+    pub(crate) nesting: usize,
+}
+
+/// What a reference link points to.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum LinkReferenceKind {
+    /// A regular link.
+    Link,
+    /// An image which was not rendered inline.
+    Image,
+}
+
 /// The definition of a reference link, i.e. a numeric index for a link.
 #[derive(Debug, PartialEq)]
 pub struct LinkReferenceDefinition<'a> {
     /// The reference index of this link.
     pub(crate) index: u16,
+    /// What this link points to.
+    pub(crate) kind: LinkReferenceKind,
     /// The link target as it appeared in Markdown.
     pub(crate) target: CowStr<'a>,
     /// The link title as it appeared in Markdown.
@@ -32,7 +60,7 @@ pub struct LinkReferenceDefinition<'a> {
 }
 
 /// The state of the current line for render.md.wrapping.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CurrentLine {
     /// The line length
     pub(super) length: u16,
@@ -159,6 +187,32 @@ pub struct StateData<'a> {
     pub(super) current_line: CurrentLine,
     /// The state of the current table.
     pub(super) current_table: CurrentTable<'a>,
+    /// The width of the largest item number of the ordered list which starts with the current
+    /// event, see [`super::LookaheadEvents`].
+    pub(super) ordered_list_width: Option<u16>,
+    /// The width of the widest line of the code block which starts with the current event, see
+    /// [`super::LookaheadEvents`].
+    pub(super) code_block_width: Option<u16>,
+    /// Languages of code blocks which had no syntax for highlighting, in order of appearance.
+    pub(super) unknown_languages: Vec<CowStr<'a>>,
+    /// The indent of the bullets of all open list items, from the outermost to the innermost item.
+    ///
+    /// We restore the indent from this list at the end of an item, because the indent of an item
+    /// also depends on its content, e.g. the marker of a task list item.
+    pub(super) list_item_indents: Vec<u16>,
+    /// The link from a HTML `<a>` tag whose closing tag we're looking for.
+    ///
+    /// Unlike markdown links HTML links need not end within their surrounding inline element, so
+    /// we buffer all events after the opening tag, and only render a link if the closing tag
+    /// follows at the same level of inline nesting.
+    pub(super) pending_html_link: Option<PendingHtmlLink<'a>>,
+    /// The number of open strikethrough elements which we strike through with an overlay.
+    pub(super) overlay_strikethroughs: u16,
+    /// The number of columns left for the description of the current image, if mdcat truncates
+    /// it, see [`crate::Settings::max_image_description_width`].
+    pub(super) image_description_width: Option<u16>,
+    /// The number of open block quotes, i.e. the depth of the current block quote.
+    pub(super) block_quote_depth: u16,
 }
 
 impl<'a> StateData<'a> {
@@ -169,14 +223,40 @@ impl<'a> StateData<'a> {
         }
     }
 
+    pub(crate) fn lookahead(self, lookahead: super::Lookahead) -> Self {
+        Self {
+            ordered_list_width: lookahead.ordered_list_width,
+            code_block_width: lookahead.code_block_width,
+            ..self
+        }
+    }
+
+    /// Enter a nested block quote.
+    pub(crate) fn enter_block_quote(self) -> Self {
+        Self {
+            block_quote_depth: self.block_quote_depth + 1,
+            ..self
+        }
+    }
+
+    /// Leave the current block quote.
+    pub(crate) fn leave_block_quote(self) -> Self {
+        Self {
+            block_quote_depth: self.block_quote_depth.saturating_sub(1),
+            ..self
+        }
+    }
+
     /// Push a pending link.
     pub(crate) fn push_pending_link(
         mut self,
+        kind: LinkReferenceKind,
         link_type: LinkType,
         dest_url: CowStr<'a>,
         title: CowStr<'a>,
     ) -> Self {
         self.pending_links.push(PendingLink {
+            kind,
             link_type,
             dest_url,
             title,
@@ -184,6 +264,14 @@ impl<'a> StateData<'a> {
         self
     }
 
+    /// Whether there's a pending link, i.e. whether we're currently inside a link which we write
+    /// as reference link.
+    pub(crate) fn has_pending_link(&self) -> bool {
+        self.pending_links
+            .iter()
+            .any(|link| link.kind == LinkReferenceKind::Link)
+    }
+
     /// Pop a pending link.
     ///
     /// Panics if there is no pending link.
@@ -194,11 +282,12 @@ impl<'a> StateData<'a> {
 
     /// Add a pending link to the state data.
     ///
-    /// `target` is the link target, and `title` the link title to show after the URL.
-    /// `colour` is the colour to use for foreground text to differentiate between
-    /// different types of links.
+    /// `kind` denotes what the link points to, `target` is the link target, and `title` the link
+    /// title to show after the URL.  `style` is the style to use for the link to differentiate
+    /// between different types of links.
     pub(crate) fn add_link_reference(
         mut self,
+        kind: LinkReferenceKind,
         target: CowStr<'a>,
         title: CowStr<'a>,
         style: Style,
@@ -207,6 +296,7 @@ impl<'a> StateData<'a> {
         self.next_link += 1;
         self.pending_link_definitions.push(LinkReferenceDefinition {
             index,
+            kind,
             target,
             title,
             style,
@@ -214,6 +304,46 @@ impl<'a> StateData<'a> {
         (self, index)
     }
 
+    /// Remember the `indent` of the bullet of a list item.
+    pub(crate) fn push_list_item_indent(mut self, indent: u16) -> Self {
+        self.list_item_indents.push(indent);
+        self
+    }
+
+    /// Get the indent of the bullet of the innermost list item.
+    ///
+    /// Panics if there is no open list item.
+    pub(crate) fn pop_list_item_indent(mut self) -> (Self, u16) {
+        let indent = self.list_item_indents.pop().unwrap();
+        (self, indent)
+    }
+
+    /// Look for the closing tag of the given link from a HTML tag.
+    pub(crate) fn pending_html_link(mut self, link: PendingHtmlLink<'a>) -> Self {
+        self.pending_html_link = Some(link);
+        self
+    }
+
+    /// Take the link from a HTML tag whose closing tag we're looking for, if any.
+    pub(crate) fn take_pending_html_link(mut self) -> (Self, Option<PendingHtmlLink<'a>>) {
+        let link = self.pending_html_link.take();
+        (self, link)
+    }
+
+    /// Set the number of columns left for the description of the current image.
+    pub(crate) fn image_description_width(mut self, width: Option<u16>) -> Self {
+        self.image_description_width = width;
+        self
+    }
+
+    /// Remember an unknown code block `language`, unless we already know about it.
+    pub(crate) fn add_unknown_language(mut self, language: CowStr<'a>) -> Self {
+        if !self.unknown_languages.contains(&language) {
+            self.unknown_languages.push(language);
+        }
+        self
+    }
+
     pub(crate) fn take_link_references(self) -> (Self, Vec<LinkReferenceDefinition<'a>>) {
         let links = self.pending_link_definitions;
         (
@@ -234,6 +364,14 @@ impl Default for StateData<'_> {
             next_link: 1,
             current_line: CurrentLine::empty(),
             current_table: CurrentTable::empty(),
+            ordered_list_width: None,
+            code_block_width: None,
+            unknown_languages: Vec::new(),
+            list_item_indents: Vec::new(),
+            pending_html_link: None,
+            overlay_strikethroughs: 0,
+            image_description_width: None,
+            block_quote_depth: 0,
         }
     }
 }
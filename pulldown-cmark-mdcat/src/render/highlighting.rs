@@ -6,13 +6,12 @@
 
 //! Tools for syntax highlighting.
 
-use anstyle::{AnsiColor, Effects};
-use std::{
-    io::{Result, Write},
-    sync::OnceLock,
-};
+use anstyle::{AnsiColor, Effects, RgbColor};
+use std::{io::Result, sync::OnceLock};
 use syntect::highlighting::{FontStyle, Highlighter, Style, Theme};
 
+use crate::sink::StyleSink;
+
 static SOLARIZED_DARK_DUMP: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/theme.dump"));
 static THEME: OnceLock<Theme> = OnceLock::new();
 static HIGHLIGHTER: OnceLock<Highlighter> = OnceLock::new();
@@ -40,7 +39,7 @@ pub fn highlighter() -> &'static Highlighter<'static> {
 ///
 /// Furthermore we completely ignore any background colour settings, to avoid
 /// conflicts with the terminal colour themes.
-pub fn write_as_ansi<'a, W: Write, I: Iterator<Item = (Style, &'a str)>>(
+pub fn write_as_ansi<'a, W: StyleSink, I: Iterator<Item = (Style, &'a str)>>(
     writer: &mut W,
     regions: I,
 ) -> Result<()> {
@@ -75,7 +74,31 @@ pub fn write_as_ansi<'a, W: Write, I: Iterator<Item = (Style, &'a str)>>(
             .set(Effects::ITALIC, font.contains(FontStyle::ITALIC))
             .set(Effects::UNDERLINE, font.contains(FontStyle::UNDERLINE));
         let style = anstyle::Style::new().fg_color(color).effects(effects);
-        write!(writer, "{}{}{}", style.render(), text, style.render_reset())?;
+        writer.write_styled(&style, text)?;
+    }
+    Ok(())
+}
+
+/// Write regions as text with 24-bit colours.
+///
+/// Unlike [`write_as_ansi`] this function works with any theme, but the colours of the theme
+/// must fit the background of the terminal.  Like [`write_as_ansi`] we ignore any background
+/// colour settings.
+pub fn write_as_rgb<'a, W: StyleSink, I: Iterator<Item = (Style, &'a str)>>(
+    writer: &mut W,
+    regions: I,
+) -> Result<()> {
+    for (style, text) in regions {
+        let fg = style.foreground;
+        let font = style.font_style;
+        let effects = Effects::new()
+            .set(Effects::BOLD, font.contains(FontStyle::BOLD))
+            .set(Effects::ITALIC, font.contains(FontStyle::ITALIC))
+            .set(Effects::UNDERLINE, font.contains(FontStyle::UNDERLINE));
+        let style = anstyle::Style::new()
+            .fg_color(Some(RgbColor(fg.r, fg.g, fg.b).into()))
+            .effects(effects);
+        writer.write_styled(&style, text)?;
     }
     Ok(())
 }
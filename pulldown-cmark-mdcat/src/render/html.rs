@@ -0,0 +1,341 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Minimal parsing of HTML image and link tags.
+//!
+//! This is by no means a HTML parser; we only extract attributes of simple `<img>` and `<a>` tags
+//! to render them like markdown images and links.
+
+use pulldown_cmark::{CowStr, Event, LinkType, Tag, TagEnd};
+
+/// A HTML tag which mdcat renders as markdown.
+#[derive(Debug, PartialEq, Eq)]
+pub enum HtmlTag {
+    /// An `<img>` tag with alt text.
+    Image {
+        src: String,
+        alt: String,
+        title: String,
+    },
+    /// An opening `<a>` tag with a `href` attribute.
+    Link { href: String, title: String },
+    /// A closing `</a>` tag.
+    LinkEnd,
+}
+
+/// Markdown events for an image with the given `src`, `alt` text and `title`.
+pub fn image_events<'a>(src: String, alt: String, title: String) -> Vec<Event<'a>> {
+    let mut events = vec![Event::Start(Tag::Image {
+        link_type: LinkType::Inline,
+        dest_url: src.into(),
+        title: title.into(),
+        id: CowStr::Borrowed(""),
+    })];
+    events.push(Event::Text(alt.into()));
+    events.push(Event::End(TagEnd::Image));
+    events
+}
+
+/// The markdown event which starts a link to `href` with `title`.
+pub fn link_start<'a>(href: String, title: String) -> Event<'a> {
+    Event::Start(Tag::Link {
+        link_type: LinkType::Inline,
+        dest_url: href.into(),
+        title: title.into(),
+        id: CowStr::Borrowed(""),
+    })
+}
+
+/// Markdown events for a link to `href` with `title` and the given HTML `text`.
+///
+/// Turn images in `text` into image events, and everything else into plain text.
+pub fn link_events<'a>(href: String, title: String, text: &str) -> Vec<Event<'a>> {
+    let mut events = vec![link_start(href, title)];
+    for fragment in split_html(text) {
+        match fragment {
+            HtmlFragment::Image {
+                src, alt, title, ..
+            } => events.extend(image_events(src, alt, title)),
+            HtmlFragment::Html(text) | HtmlFragment::Link { text, .. } => {
+                events.push(Event::Text(text.to_owned().into()));
+            }
+        }
+    }
+    events.push(Event::End(TagEnd::Link));
+    events
+}
+
+/// Decode the few character references commonly found in attribute values.
+fn decode_entities(value: &str) -> String {
+    if !value.contains('&') {
+        return value.to_owned();
+    }
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Parse the attributes of a tag from `s`, up to the end of the tag.
+///
+/// Return the attributes with lowercase names and decoded values, and the remainder of `s` after
+/// the closing `>` of the tag, or `None` if the tag has no end.
+fn parse_attributes(mut s: &str) -> Option<(Vec<(String, String)>, &str)> {
+    let mut attributes = Vec::new();
+    loop {
+        s = s.trim_start();
+        if let Some(rest) = s.strip_prefix("/>").or_else(|| s.strip_prefix('>')) {
+            return Some((attributes, rest));
+        }
+        let name_end = s.find(|c: char| c.is_whitespace() || matches!(c, '=' | '>' | '/'))?;
+        let (name, rest) = s.split_at(name_end);
+        if name.is_empty() {
+            // A stray slash inside the tag
+            s = &s[1..];
+            continue;
+        }
+        let rest = rest.trim_start();
+        let (value, rest) = match rest.strip_prefix('=') {
+            Some(rest) => {
+                let rest = rest.trim_start();
+                match rest.chars().next()? {
+                    quote @ ('"' | '\'') => {
+                        let end = rest[1..].find(quote)? + 1;
+                        (&rest[1..end], &rest[end + 1..])
+                    }
+                    _ => {
+                        let end = rest.find(|c: char| c.is_whitespace() || c == '>')?;
+                        rest.split_at(end)
+                    }
+                }
+            }
+            None => ("", rest),
+        };
+        attributes.push((name.to_ascii_lowercase(), decode_entities(value)));
+        s = rest;
+    }
+}
+
+/// Parse an image or link tag at the beginning of `html`.
+///
+/// Return the tag and the remainder of `html` after the tag, or `None` if `html` doesn't start
+/// with an `<img>` tag with a `src` and a non-empty `alt` attribute, an `<a>` tag with a `href`
+/// attribute, or a closing `</a>` tag.
+///
+/// We skip images without alt text, because we'd have nothing to show for them if the terminal
+/// cannot display the image.
+pub fn parse_tag(html: &str) -> Option<(HtmlTag, &str)> {
+    let rest = html.strip_prefix('<')?;
+    if let Some(rest) = rest.strip_prefix('/') {
+        let name_end = rest.find(|c: char| c.is_whitespace() || c == '>')?;
+        let (name, rest) = rest.split_at(name_end);
+        let rest = rest.trim_start().strip_prefix('>')?;
+        return name
+            .eq_ignore_ascii_case("a")
+            .then_some((HtmlTag::LinkEnd, rest));
+    }
+    let name_end = rest.find(|c: char| c.is_whitespace() || c == '>' || c == '/')?;
+    let (name, rest) = rest.split_at(name_end);
+    let (mut attributes, rest) = parse_attributes(rest)?;
+    let mut take = |wanted: &str| {
+        attributes
+            .iter()
+            .position(|(name, _)| name == wanted)
+            .map(|index| attributes.swap_remove(index).1)
+    };
+    let tag = if name.eq_ignore_ascii_case("img") {
+        HtmlTag::Image {
+            src: take("src")?,
+            alt: take("alt").filter(|alt| !alt.is_empty())?,
+            title: take("title").unwrap_or_default(),
+        }
+    } else if name.eq_ignore_ascii_case("a") {
+        HtmlTag::Link {
+            href: take("href")?,
+            title: take("title").unwrap_or_default(),
+        }
+    } else {
+        return None;
+    };
+    Some((tag, rest))
+}
+
+/// A piece of a HTML block.
+#[derive(Debug, PartialEq, Eq)]
+pub enum HtmlFragment<'a> {
+    /// HTML to write literally.
+    Html(&'a str),
+    /// An image.
+    Image {
+        src: String,
+        alt: String,
+        title: String,
+        /// The literal HTML of the image.
+        html: &'a str,
+    },
+    /// A link with its text.
+    Link {
+        href: String,
+        title: String,
+        text: &'a str,
+        /// The literal HTML of the link, including its text.
+        html: &'a str,
+    },
+}
+
+/// Split `html` into literal HTML, images, and links.
+///
+/// Only split off links whose closing tag is part of `html`.
+pub fn split_html(html: &str) -> Vec<HtmlFragment<'_>> {
+    let mut fragments = Vec::new();
+    let mut literal_start = 0;
+    let mut position = 0;
+    while let Some(offset) = html[position..].find('<') {
+        let tag_start = position + offset;
+        let tag_html = |rest: &str| &html[tag_start..html.len() - rest.len()];
+        let parsed = parse_tag(&html[tag_start..]).and_then(|(tag, rest)| match tag {
+            HtmlTag::Image { src, alt, title } => Some((
+                HtmlFragment::Image {
+                    src,
+                    alt,
+                    title,
+                    html: tag_html(rest),
+                },
+                rest,
+            )),
+            HtmlTag::Link { href, title } => {
+                // Find the closing tag of the link
+                let mut search = rest;
+                loop {
+                    let end = search.find('<')?;
+                    match parse_tag(&search[end..]) {
+                        Some((HtmlTag::LinkEnd, after)) => {
+                            let text = &rest[..rest.len() - search.len() + end];
+                            let html = tag_html(after);
+                            let link = HtmlFragment::Link {
+                                href,
+                                title,
+                                text,
+                                html,
+                            };
+                            break Some((link, after));
+                        }
+                        _ => search = &search[end + 1..],
+                    }
+                }
+            }
+            HtmlTag::LinkEnd => None,
+        });
+        match parsed {
+            Some((fragment, rest)) => {
+                if literal_start < tag_start {
+                    fragments.push(HtmlFragment::Html(&html[literal_start..tag_start]));
+                }
+                fragments.push(fragment);
+                position = html.len() - rest.len();
+                literal_start = position;
+            }
+            None => position = tag_start + 1,
+        }
+    }
+    if literal_start < html.len() {
+        fragments.push(HtmlFragment::Html(&html[literal_start..]));
+    }
+    fragments
+}
+
+#[cfg(test)]
+mod tests {
+    use similar_asserts::assert_eq;
+
+    use super::*;
+
+    fn image(src: &str, alt: &str, title: &str) -> HtmlTag {
+        HtmlTag::Image {
+            src: src.to_owned(),
+            alt: alt.to_owned(),
+            title: title.to_owned(),
+        }
+    }
+
+    #[test]
+    fn parse_image_tags() {
+        assert_eq!(
+            parse_tag(r#"<img src="logo.png" alt="The logo">"#),
+            Some((image("logo.png", "The logo", ""), ""))
+        );
+        assert_eq!(
+            parse_tag("<IMG width=20 SRC='a&amp;b.png' alt=Hi title=\"&quot;Hi&quot;\"/> more"),
+            Some((image("a&b.png", "Hi", "\"Hi\""), " more"))
+        );
+        assert_eq!(
+            parse_tag("<img src=logo.png alt=logo />"),
+            Some((image("logo.png", "logo", ""), ""))
+        );
+    }
+
+    #[test]
+    fn parse_link_tags() {
+        assert_eq!(
+            parse_tag(r#"<a href="https://example.com" title="Example">"#),
+            Some((
+                HtmlTag::Link {
+                    href: "https://example.com".to_owned(),
+                    title: "Example".to_owned()
+                },
+                ""
+            ))
+        );
+        assert_eq!(parse_tag("</A >"), Some((HtmlTag::LinkEnd, "")));
+    }
+
+    #[test]
+    fn ignore_other_tags() {
+        assert_eq!(parse_tag("<img alt=\"No source\">"), None);
+        assert_eq!(parse_tag("<img src=\"logo.png\">"), None);
+        assert_eq!(parse_tag("<img src=\"logo.png\" alt=\"\">"), None);
+        assert_eq!(parse_tag("<a name=\"anchor\">"), None);
+        assert_eq!(parse_tag("<abbr title=\"Foo\">"), None);
+        assert_eq!(parse_tag("</p>"), None);
+        assert_eq!(parse_tag("<img src=\"unterminated"), None);
+        assert_eq!(parse_tag("<!-- comment -->"), None);
+    }
+
+    #[test]
+    fn split_html_block() {
+        assert_eq!(
+            split_html("<p align=\"center\"><img src=\"logo.png\" alt=\"Logo\"> <a href=\"https://example.com\">Example</a></p>\n"),
+            vec![
+                HtmlFragment::Html("<p align=\"center\">"),
+                HtmlFragment::Image {
+                    src: "logo.png".to_owned(),
+                    alt: "Logo".to_owned(),
+                    title: String::new(),
+                    html: "<img src=\"logo.png\" alt=\"Logo\">"
+                },
+                HtmlFragment::Html(" "),
+                HtmlFragment::Link {
+                    href: "https://example.com".to_owned(),
+                    title: String::new(),
+                    text: "Example",
+                    html: "<a href=\"https://example.com\">Example</a>"
+                },
+                HtmlFragment::Html("</p>\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_html_keeps_unclosed_links() {
+        assert_eq!(
+            split_html("<a href=\"https://example.com\">\n"),
+            vec![HtmlFragment::Html("<a href=\"https://example.com\">\n")]
+        );
+    }
+}
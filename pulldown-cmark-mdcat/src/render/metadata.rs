@@ -0,0 +1,84 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Minimal parsing of front matter.
+//!
+//! This is by no means a YAML or TOML parser; we only extract a simple top-level title.
+
+use pulldown_cmark::MetadataBlockKind;
+
+/// Remove matching quotes around `value`.
+fn unquote(value: &str) -> &str {
+    ['"', '\'']
+        .into_iter()
+        .find_map(|quote| {
+            value
+                .strip_prefix(quote)
+                .and_then(|value| value.strip_suffix(quote))
+        })
+        .unwrap_or(value)
+}
+
+/// Extract the title from front matter of the given `kind`.
+///
+/// Only look at a top-level `title: …` key in YAML front matter and `title = …` in TOML front
+/// matter, with a plain or quoted single-line value.  Return `None` if the front matter has no
+/// such title, or if the title is empty.
+pub fn front_matter_title(kind: MetadataBlockKind, front_matter: &str) -> Option<&str> {
+    let separator = match kind {
+        MetadataBlockKind::YamlStyle => ':',
+        MetadataBlockKind::PlusesStyle => '=',
+    };
+    front_matter.lines().find_map(|line| {
+        let value = line
+            .strip_prefix("title")?
+            .trim_start()
+            .strip_prefix(separator)?
+            .trim();
+        Some(unquote(value)).filter(|title| !title.is_empty())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use pulldown_cmark::MetadataBlockKind::{PlusesStyle, YamlStyle};
+
+    use super::front_matter_title;
+
+    #[test]
+    fn yaml_title() {
+        assert_eq!(
+            front_matter_title(YamlStyle, "author: Jane\ntitle: A document\n"),
+            Some("A document")
+        );
+        assert_eq!(
+            front_matter_title(YamlStyle, "title: \"Quoted: title\"\n"),
+            Some("Quoted: title")
+        );
+        assert_eq!(
+            front_matter_title(YamlStyle, "title: 'Single'"),
+            Some("Single")
+        );
+    }
+
+    #[test]
+    fn toml_title() {
+        assert_eq!(
+            front_matter_title(PlusesStyle, "draft = true\ntitle = \"A document\"\n"),
+            Some("A document")
+        );
+        assert_eq!(front_matter_title(PlusesStyle, "title: YAML\n"), None);
+    }
+
+    #[test]
+    fn no_title() {
+        assert_eq!(front_matter_title(YamlStyle, "author: Jane\n"), None);
+        assert_eq!(front_matter_title(YamlStyle, "title:\n"), None);
+        assert_eq!(front_matter_title(YamlStyle, "  title: Nested\n"), None);
+        assert_eq!(front_matter_title(YamlStyle, "subtitle: Nope\n"), None);
+        assert_eq!(front_matter_title(YamlStyle, "titles: Nope\n"), None);
+    }
+}
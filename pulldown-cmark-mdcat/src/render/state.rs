@@ -5,9 +5,13 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use anstyle::Style;
+use pulldown_cmark::MetadataBlockKind;
 use std::borrow::Borrow;
-use syntect::highlighting::HighlightState;
-use syntect::parsing::ParseState;
+use syntect::highlighting::{HighlightState, Highlighter, Theme};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference};
+
+use super::highlighting::highlighter;
+use crate::theme::CombineStyle;
 
 /// Whether to add a margin.
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -36,6 +40,16 @@ impl Default for InlineAttrs {
     }
 }
 
+impl InlineAttrs {
+    /// Attributes for unstyled inline text at the given `indent`.
+    pub(super) fn indented(indent: u16) -> Self {
+        InlineAttrs {
+            indent,
+            ..InlineAttrs::default()
+        }
+    }
+}
+
 impl<T> From<T> for InlineAttrs
 where
     T: Borrow<StyledBlockAttrs>,
@@ -51,7 +65,21 @@ where
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ListItemKind {
     Unordered,
-    Ordered(u64),
+    /// An ordered list item, with the item number and the width to align numbers to.
+    Ordered(u64, u16),
+    /// A definition in a definition list.
+    Definition,
+}
+
+impl ListItemKind {
+    /// The kind of items for a list starting at `start`.
+    ///
+    /// Align numbers of ordered lists to `width`, but to at least two columns.
+    pub(super) fn for_list(start: Option<u64>, width: Option<u16>) -> Self {
+        start.map_or(ListItemKind::Unordered, |start| {
+            ListItemKind::Ordered(start, width.unwrap_or(2).max(2))
+        })
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -79,6 +107,12 @@ pub enum InlineState {
     /// This state suppresses link references being written when reading a link
     /// end event.
     InlineLink,
+    /// The description of an image inside a link, if mdcat could not render the image.
+    ///
+    /// This state suppresses image references being written when reading an image end event,
+    /// because the image description is part of the link text, and the link already refers to
+    /// the link target.
+    LinkedImageText,
     /// A list item.
     ///
     /// This is a hybrid between inline and block state because it can contain nested blocks as well
@@ -120,10 +154,14 @@ impl StyledBlockAttrs {
         }
     }
 
-    pub(super) fn block_quote(self) -> Self {
+    /// Turn these attributes into attributes for a block quote inside this block.
+    ///
+    /// If given, put `depth_style` on top of the italic style of the block quote.
+    pub(super) fn block_quote(self, depth_style: Option<Style>) -> Self {
+        let style = self.style.italic();
         StyledBlockAttrs {
             indent: self.indent + 4,
-            style: self.style.italic(),
+            style: depth_style.map_or(style, |depth_style| depth_style.on_top_of(&style)),
             ..self
         }
     }
@@ -162,6 +200,34 @@ pub struct HighlightBlockAttrs {
     /// Code blocks in nested blocks such as quotes, lists, etc. gain an additional indent to align
     /// them in the surrounding block.
     pub(super) indent: u16,
+    /// Whether the code written so far ends in the middle of a line.
+    pub(super) open_line: bool,
+    /// The width of the borders of this code block.
+    pub(super) border_width: u16,
+}
+
+impl HighlightBlockAttrs {
+    /// Start highlighting a code block with the given `syntax` and syntax `theme`.
+    ///
+    /// If `theme` is `None` use the built-in Solarized theme.
+    pub(super) fn new(
+        syntax: &SyntaxReference,
+        theme: Option<&Theme>,
+        indent: u16,
+        border_width: u16,
+    ) -> Self {
+        let highlight_state = match theme {
+            Some(theme) => HighlightState::new(&Highlighter::new(theme), ScopeStack::new()),
+            None => HighlightState::new(highlighter(), ScopeStack::new()),
+        };
+        Self {
+            parse_state: ParseState::new(syntax),
+            highlight_state,
+            indent,
+            open_line: false,
+            border_width,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -170,9 +236,15 @@ pub struct LiteralBlockAttrs {
     pub(super) indent: u16,
     /// The outer style to include.
     pub(super) style: Style,
+    /// Whether to infer the syntax of this block from its first line.
+    pub(super) infer_syntax: bool,
+    /// Whether the code written so far ends in the middle of a line.
+    pub(super) open_line: bool,
+    /// The width of the borders of this code block.
+    pub(super) border_width: u16,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct HtmlBlockAttrs {
     /// The initial indent for this block.
     pub(super) initial_indent: u16,
@@ -180,6 +252,30 @@ pub struct HtmlBlockAttrs {
     pub(super) indent: u16,
     /// The base style for this block.
     pub(super) style: Style,
+    /// Whether the HTML written so far ends in the middle of a line.
+    pub(super) open_line: bool,
+}
+
+/// Attributes for front matter.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FrontMatterAttrs {
+    /// The kind of front matter.
+    pub(super) kind: MetadataBlockKind,
+    /// The contents of the front matter read so far.
+    pub(super) contents: String,
+}
+
+/// Attributes for rendered images.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RenderedImageAttrs {
+    /// The number of images nested in the description of the rendered image which are still open.
+    pub(super) nested_images: u16,
+    /// The description of the image read so far, if we write it as caption.
+    pub(super) caption: Option<String>,
+    /// The indent of the surrounding inline text.
+    pub(super) indent: u16,
+    /// The style of the surrounding inline text.
+    pub(super) style: Style,
 }
 
 #[derive(Debug, PartialEq)]
@@ -194,11 +290,16 @@ pub enum StackedState {
     LiteralBlock(LiteralBlockAttrs),
     /// A block of HTML contents.
     HtmlBlock(HtmlBlockAttrs),
+    /// Front matter at the beginning of the document.
+    ///
+    /// We never write front matter, but collect its contents to extract the title.
+    FrontMatter(FrontMatterAttrs),
     /// A rendered inline image.
     ///
     /// We move to this state when we can render an image directly to the terminal, in order to
-    /// suppress intermediate events, namely the image title.
-    RenderedImage,
+    /// suppress intermediate events, namely the image description, or to collect the description
+    /// for a caption.
+    RenderedImage(RenderedImageAttrs),
     /// A table block.
     TableBlock,
     /// Some inline markup.
@@ -229,6 +330,18 @@ impl From<HtmlBlockAttrs> for StackedState {
     }
 }
 
+impl From<RenderedImageAttrs> for StackedState {
+    fn from(attrs: RenderedImageAttrs) -> Self {
+        StackedState::RenderedImage(attrs)
+    }
+}
+
+impl From<FrontMatterAttrs> for StackedState {
+    fn from(attrs: FrontMatterAttrs) -> Self {
+        StackedState::FrontMatter(attrs)
+    }
+}
+
 /// State attributes for top level.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TopLevelAttrs {
@@ -286,6 +399,14 @@ including the document causing this panic.",
         self
     }
 
+    /// Replace the last stacked state with the result of `f`.
+    pub(crate) fn map_last<F: FnOnce(StackedState) -> StackedState>(mut self, f: F) -> StateStack {
+        if let Some(state) = self.states.pop() {
+            self.states.push(f(state));
+        }
+        self
+    }
+
     /// Return a state by combining this stack with the current stacked state.
     pub(crate) fn current(self, state: StackedState) -> State {
         State::Stacked(self, state)
@@ -323,6 +444,27 @@ impl State {
     pub(super) fn and_data<T>(self, data: T) -> StateAndData<T> {
         StateAndData(self, data)
     }
+
+    /// Whether the output in this state ends at the start of a line.
+    ///
+    /// Blocks end their last line before they return to the surrounding state, but inline text
+    /// and rendered images are always in the middle of a line.  Code and HTML blocks track
+    /// whether their last line is still open.
+    pub(super) fn at_line_start(&self) -> bool {
+        match self {
+            State::TopLevel(_) => true,
+            State::Stacked(_, StackedState::Inline(_, _) | StackedState::RenderedImage(_)) => false,
+            State::Stacked(_, StackedState::HighlightBlock(attrs)) => !attrs.open_line,
+            State::Stacked(_, StackedState::LiteralBlock(attrs)) => !attrs.open_line,
+            State::Stacked(_, StackedState::HtmlBlock(attrs)) => !attrs.open_line,
+            State::Stacked(
+                _,
+                StackedState::StyledBlock(_)
+                | StackedState::FrontMatter(_)
+                | StackedState::TableBlock,
+            ) => true,
+        }
+    }
 }
 
 impl Default for State {
@@ -0,0 +1,151 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Width of text in terminal columns.
+
+use std::ops::Deref;
+
+use textwrap::core::{Fragment, Word};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Zero width joiner, to join emoji into a single emoji, e.g. a family.
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+
+/// Variation selector 16, to select the emoji presentation of a character.
+const EMOJI_PRESENTATION_SELECTOR: char = '\u{FE0F}';
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&c)
+}
+
+fn is_emoji_modifier(c: char) -> bool {
+    ('\u{1F3FB}'..='\u{1F3FF}').contains(&c)
+}
+
+/// The width of a single grapheme cluster.
+///
+/// Terminals render emoji sequences, i.e. ZWJ sequences, emoji presentation sequences, flags, and
+/// emoji with skin tone modifiers, as a single wide character.
+fn grapheme_width(grapheme: &str) -> usize {
+    let mut chars = grapheme.chars();
+    let is_sequence = match (chars.next(), chars.next()) {
+        (Some(first), Some(_)) => {
+            is_regional_indicator(first)
+                || grapheme.contains([ZERO_WIDTH_JOINER, EMOJI_PRESENTATION_SELECTOR])
+                || grapheme.chars().any(is_emoji_modifier)
+        }
+        _ => false,
+    };
+    if is_sequence {
+        2
+    } else {
+        grapheme.width()
+    }
+}
+
+/// The width of `text` in terminal columns.
+///
+/// Unlike a plain sum of the widths of all characters this measures grapheme clusters, so that
+/// emoji sequences count as a single wide character.
+pub fn display_width(text: &str) -> usize {
+    text.graphemes(true).map(grapheme_width).sum()
+}
+
+/// The longest prefix of `text` which fits into `width` columns.
+///
+/// Never split grapheme clusters.
+pub fn truncate_to_width(text: &str, width: usize) -> &str {
+    let mut columns = 0;
+    for (index, grapheme) in text.grapheme_indices(true) {
+        columns += grapheme_width(grapheme);
+        if width < columns {
+            return &text[..index];
+        }
+    }
+    text
+}
+
+/// A word to wrap, with its width measured by [`display_width`].
+#[derive(Debug, Clone, Copy)]
+pub struct MeasuredWord<'a> {
+    word: Word<'a>,
+    width: usize,
+}
+
+impl<'a> From<Word<'a>> for MeasuredWord<'a> {
+    fn from(word: Word<'a>) -> Self {
+        Self {
+            width: display_width(word.word),
+            word,
+        }
+    }
+}
+
+impl<'a> Deref for MeasuredWord<'a> {
+    type Target = Word<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.word
+    }
+}
+
+impl Fragment for MeasuredWord<'_> {
+    fn width(&self) -> f64 {
+        self.width as f64
+    }
+
+    fn whitespace_width(&self) -> f64 {
+        self.word.whitespace_width()
+    }
+
+    fn penalty_width(&self) -> f64 {
+        self.word.penalty_width()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{display_width, truncate_to_width};
+
+    #[test]
+    fn plain_text() {
+        assert_eq!(display_width("hello"), 5);
+        assert_eq!(display_width("e\u{301}t\u{e9}"), 3);
+        assert_eq!(display_width("日本"), 4);
+    }
+
+    #[test]
+    fn emoji() {
+        assert_eq!(display_width("😀"), 2);
+        // Flag
+        assert_eq!(display_width("🇩🇪"), 2);
+        // ZWJ family
+        assert_eq!(display_width("👨\u{200D}👩\u{200D}👧"), 2);
+        // Emoji presentation sequence
+        assert_eq!(display_width("❤\u{FE0F}"), 2);
+        // Skin tone modifier
+        assert_eq!(display_width("👍🏽"), 2);
+        assert_eq!(display_width("a 🇩🇪 b"), 6);
+    }
+
+    #[test]
+    fn zero_width() {
+        // Zero width space, BOM aka zero width no-break space, and word joiner
+        assert_eq!(display_width("a\u{200B}b\u{FEFF}c\u{2060}d"), 4);
+        assert_eq!(display_width("\u{200B}"), 0);
+    }
+
+    #[test]
+    fn truncate() {
+        assert_eq!(truncate_to_width("hello", 10), "hello");
+        assert_eq!(truncate_to_width("hello", 3), "hel");
+        assert_eq!(truncate_to_width("hello", 0), "");
+        assert_eq!(truncate_to_width("日本", 3), "日");
+        assert_eq!(truncate_to_width("a🇩🇪b", 2), "a");
+        assert_eq!(truncate_to_width("e\u{301}t", 1), "e\u{301}");
+    }
+}
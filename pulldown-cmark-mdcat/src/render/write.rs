@@ -4,33 +4,131 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use std::borrow::Cow;
 use std::cmp::{max, min};
-use std::io::{Result, Write};
+use std::io::Result;
 use std::iter::zip;
 
 use anstyle::Style;
-use pulldown_cmark::{Alignment, CodeBlockKind, HeadingLevel};
-use syntect::highlighting::HighlightState;
-use syntect::parsing::{ParseState, ScopeStack};
-use textwrap::core::{display_width, Word};
+use pulldown_cmark::{Alignment, CodeBlockKind, CowStr, HeadingLevel};
+use textwrap::core::{break_words, Fragment};
 use textwrap::WordSeparator;
+use tracing::{event, Level};
+use url::Url;
 
-use crate::references::*;
-use crate::render::data::{CurrentLine, CurrentTable, LinkReferenceDefinition, TableCell};
-use crate::render::highlighting::highlighter;
+use crate::render::data::{
+    CurrentLine, CurrentTable, LinkReferenceDefinition, LinkReferenceKind, StateData, TableCell,
+};
 use crate::render::state::*;
-use crate::terminal::capabilities::{MarkCapability, StyleCapability, TerminalCapabilities};
+use crate::render::width::{display_width, truncate_to_width, MeasuredWord};
+use crate::resources::ResourceUrlHandler;
+use crate::sink::StyleSink;
+use crate::terminal::capabilities::{
+    ImageCapability, MarkCapability, StyleCapability, TerminalCapabilities,
+};
 use crate::terminal::osc::{clear_link, set_link_url};
 use crate::terminal::TerminalSize;
 use crate::theme::CombineStyle;
-use crate::Theme;
+use crate::{CodeBlockBorderWidth, Glyphs, Theme};
 use crate::{Environment, Settings};
 
-pub fn write_indent<W: Write>(writer: &mut W, level: u16) -> Result<()> {
+/// Expand tabs in `line` to spaces, up to the next multiple of `tab_width`.
+///
+/// Return `line` unchanged if `tab_width` is `None`.
+pub fn expand_tabs(line: &str, tab_width: Option<u16>) -> Cow<'_, str> {
+    match tab_width {
+        Some(tab_width) if line.contains('\t') => {
+            let tab_width = usize::from(tab_width.max(1));
+            let mut expanded = String::with_capacity(line.len());
+            for (n, segment) in line.split('\t').enumerate() {
+                if 0 < n {
+                    let column = display_width(&expanded);
+                    let spaces = tab_width - column % tab_width;
+                    expanded.push_str(&" ".repeat(spaces));
+                }
+                expanded.push_str(segment);
+            }
+            Cow::Owned(expanded)
+        }
+        _ => Cow::Borrowed(line),
+    }
+}
+
+/// Remove all C0 and C1 control characters except tabs and newlines from `text`.
+///
+/// Control characters in text would mess up the terminal, e.g. with stray escape sequences or
+/// carriage returns, and throw off the width of the text.
+pub fn strip_control_characters(text: CowStr<'_>) -> CowStr<'_> {
+    let is_stray_control = |c: char| c.is_control() && c != '\t' && c != '\n';
+    if text.contains(is_stray_control) {
+        text.chars()
+            .filter(|c| !is_stray_control(*c))
+            .collect::<String>()
+            .into()
+    } else {
+        text
+    }
+}
+
+/// Write the `url` of a link in parentheses after the link text, dimmed on top of `style`.
+///
+/// Account for the URL in the current line of `data`, and return the updated `data`.
+pub fn write_inline_link_url<'a, W: StyleSink>(
+    writer: &mut W,
+    capabilities: &TerminalCapabilities,
+    style: &Style,
+    url: &str,
+    data: StateData<'a>,
+) -> Result<StateData<'a>> {
+    let text = format!(" ({url})");
+    write_styled(writer, capabilities, &style.dimmed(), &text)?;
+    let length = data.current_line.length + display_width(&text) as u16;
+    Ok(data.current_line(CurrentLine {
+        length,
+        trailing_space: None,
+    }))
+}
+
+/// Truncate `text` to the `remaining` columns of an image description.
+///
+/// If `text` doesn't fit, cut it and end it with the `ellipsis`, within the remaining columns.
+/// Return the truncated text, and the number of columns left for the description.
+pub fn truncate_image_description<'a>(
+    text: CowStr<'a>,
+    remaining: u16,
+    ellipsis: &str,
+) -> (CowStr<'a>, u16) {
+    let width = display_width(&text);
+    match u16::try_from(width) {
+        Ok(width) if width <= remaining => (text, remaining - width),
+        _ if remaining == 0 => (CowStr::Borrowed(""), 0),
+        _ => {
+            let available = usize::from(remaining).saturating_sub(display_width(ellipsis));
+            let truncated = truncate_to_width(&text, available);
+            (format!("{truncated}{ellipsis}").into(), 0)
+        }
+    }
+}
+
+/// Add a combining long stroke overlay to every character in `text` except whitespace.
+///
+/// Leave whitespace alone, because a combining character would join it to the next word.
+pub fn strike_through_with_overlay(text: &str) -> String {
+    let mut struck = String::with_capacity(text.len() * 3);
+    for c in text.chars() {
+        struck.push(c);
+        if !c.is_whitespace() {
+            struck.push('\u{0336}');
+        }
+    }
+    struck
+}
+
+pub fn write_indent<W: StyleSink>(writer: &mut W, level: u16) -> Result<()> {
     write!(writer, "{}", " ".repeat(level as usize))
 }
 
-pub fn write_styled<W: Write, S: AsRef<str>>(
+pub fn write_styled<W: StyleSink, S: AsRef<str>>(
     writer: &mut W,
     capabilities: &TerminalCapabilities,
     style: &Style,
@@ -38,24 +136,31 @@ pub fn write_styled<W: Write, S: AsRef<str>>(
 ) -> Result<()> {
     match capabilities.style {
         None => write!(writer, "{}", text.as_ref()),
-        Some(StyleCapability::Ansi) => write!(
-            writer,
-            "{}{}{}",
-            style.render(),
-            text.as_ref(),
-            style.render_reset()
-        ),
+        Some(StyleCapability::Ansi) => writer.write_styled(style, text.as_ref()),
     }
 }
 
-fn write_remaining_lines<W: Write>(
+/// Reset all styles of the terminal.
+///
+/// Does nothing if the terminal does not support styles.
+pub fn write_style_reset<W: StyleSink>(
+    writer: &mut W,
+    capabilities: &TerminalCapabilities,
+) -> Result<()> {
+    match capabilities.style {
+        None => Ok(()),
+        Some(StyleCapability::Ansi) => writer.write_style_reset(),
+    }
+}
+
+fn write_remaining_lines<W: StyleSink>(
     writer: &mut W,
     capabilities: &TerminalCapabilities,
     style: &Style,
     indent: u16,
     mut buffer: String,
-    next_lines: &[&[Word]],
-    last_line: &[Word],
+    next_lines: &[&[MeasuredWord]],
+    last_line: &[MeasuredWord],
 ) -> Result<CurrentLine> {
     // Finish the previous line
     writeln!(writer)?;
@@ -94,14 +199,14 @@ fn write_remaining_lines<W: Write>(
             buffer.push_str(last.word);
             write_styled(writer, capabilities, style, &buffer)?;
             Ok(CurrentLine {
-                length: textwrap::core::display_width(&buffer) as u16,
+                length: display_width(&buffer) as u16,
                 trailing_space: Some(last.whitespace.to_owned()),
             })
         }
     }
 }
 
-pub fn write_styled_and_wrapped<W: Write, S: AsRef<str>>(
+pub fn write_styled_and_wrapped<W: StyleSink, S: AsRef<str>>(
     writer: &mut W,
     capabilities: &TerminalCapabilities,
     style: &Style,
@@ -110,9 +215,15 @@ pub fn write_styled_and_wrapped<W: Write, S: AsRef<str>>(
     current_line: CurrentLine,
     text: S,
 ) -> Result<CurrentLine> {
-    let words = WordSeparator::UnicodeBreakProperties
-        .find_words(text.as_ref())
-        .collect::<Vec<_>>();
+    // Break words which do not even fit on a line of their own, e.g. long URLs or hashes, so
+    // that we never exceed the maximum width.
+    let words = break_words(
+        WordSeparator::UnicodeBreakProperties.find_words(text.as_ref()),
+        max_width.saturating_sub(indent).max(1) as usize,
+    )
+    .into_iter()
+    .map(MeasuredWord::from)
+    .collect::<Vec<_>>();
     match words.first() {
         // There were no words in the text so we just do nothing.
         None => Ok(current_line),
@@ -127,9 +238,7 @@ pub fn write_styled_and_wrapped<W: Write, S: AsRef<str>>(
             // If the current line is not empty and we can't even add the first first word of the text to it
             // then lets finish the line and start over.  If the current line is empty the word simply doesn't
             // fit into the terminal size so we must print it anyway.
-            if 0 < current_line.length
-                && max_width < current_width + display_width(first_word) as u16
-            {
+            if 0 < current_line.length && max_width < current_width + first_word.width() as u16 {
                 writeln!(writer)?;
                 write_indent(writer, indent)?;
                 return write_styled_and_wrapped(
@@ -148,7 +257,7 @@ pub fn write_styled_and_wrapped<W: Write, S: AsRef<str>>(
                 // the trailing space we need to add if we add more words to this line
                 (max_width - current_width.min(max_width)) as f64,
                 // For remaining lines we only need to account for the indent
-                (max_width - indent) as f64,
+                max_width.saturating_sub(indent) as f64,
             ];
             let lines = textwrap::wrap_algorithms::wrap_first_fit(&words, &widths);
             match lines.split_first() {
@@ -166,16 +275,22 @@ pub fn write_styled_and_wrapped<W: Write, S: AsRef<str>>(
                             current_line
                         }
                         Some((last, heads)) => {
-                            if let Some(s) = current_line.trailing_space {
-                                buffer.push_str(&s);
-                            }
+                            // The trailing space separates the previous text from this text, so
+                            // don't apply the style of this text, e.g. its background colour.
+                            let space_width = match current_line.trailing_space {
+                                Some(s) => {
+                                    write!(writer, "{s}")?;
+                                    display_width(&s) as u16
+                                }
+                                None => 0,
+                            };
                             for word in heads {
                                 buffer.push_str(word.word);
                                 buffer.push_str(word.whitespace);
                             }
                             buffer.push_str(last.word);
                             let length =
-                                current_line.length + textwrap::core::display_width(&buffer) as u16;
+                                current_line.length + space_width + display_width(&buffer) as u16;
                             write_styled(writer, capabilities, style, &buffer)?;
                             buffer.clear();
                             CurrentLine {
@@ -211,7 +326,21 @@ pub fn write_styled_and_wrapped<W: Write, S: AsRef<str>>(
     }
 }
 
-pub fn write_mark<W: Write>(writer: &mut W, capabilities: &TerminalCapabilities) -> Result<()> {
+/// Write the margin between blocks, unless `margin` is [`MarginControl::NoMargin`].
+pub fn write_margin<W: StyleSink>(
+    writer: &mut W,
+    theme: &Theme,
+    margin: MarginControl,
+) -> Result<()> {
+    if margin != MarginControl::NoMargin {
+        for _ in 0..theme.block_margin {
+            writeln!(writer)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn write_mark<W: StyleSink>(writer: &mut W, capabilities: &TerminalCapabilities) -> Result<()> {
     if let Some(mark) = capabilities.marks {
         match mark {
             MarkCapability::ITerm2(marks) => marks.set_mark(writer),
@@ -221,13 +350,93 @@ pub fn write_mark<W: Write>(writer: &mut W, capabilities: &TerminalCapabilities)
     }
 }
 
-pub fn write_rule<W: Write>(
+/// Write the image at `url` with the given image `capability`.
+///
+/// Start half block images on a new line if the `current_line` is not empty, because they span
+/// multiple lines, and return the current line after the last line of the image.  Other image
+/// protocols leave the `current_line` as it is.
+fn write_image_with<W: StyleSink>(
+    writer: &mut W,
+    capability: ImageCapability,
+    terminal_size: TerminalSize,
+    resource_handler: &dyn ResourceUrlHandler,
+    url: &Url,
+    indent: u16,
+    current_line: CurrentLine,
+) -> Result<CurrentLine> {
+    match capability {
+        ImageCapability::HalfBlock(protocol) => {
+            // Render the image first, to not break the line for an image we cannot show.
+            let mut image = Vec::new();
+            let width = protocol.write_image(
+                &mut image,
+                resource_handler,
+                url,
+                terminal_size.columns.saturating_sub(indent),
+                indent,
+            )?;
+            if 0 < current_line.length {
+                writeln!(writer)?;
+                write_indent(writer, indent)?;
+            }
+            writer.write_all(&image)?;
+            Ok(CurrentLine {
+                length: width,
+                trailing_space: None,
+            })
+        }
+        capability => {
+            capability.image_protocol().write_inline_image(
+                writer,
+                resource_handler,
+                url,
+                terminal_size,
+            )?;
+            Ok(current_line)
+        }
+    }
+}
+
+/// Write the image at `url` with the image capabilities of the terminal.
+///
+/// If the image capability fails to write the image, try the image fallback capability.  Return
+/// the current line after the image, or `None` if we failed to write the image.
+pub fn write_image<W: StyleSink>(
+    writer: &mut W,
+    capabilities: &TerminalCapabilities,
+    terminal_size: TerminalSize,
+    resource_handler: &dyn ResourceUrlHandler,
+    url: &Url,
+    indent: u16,
+    current_line: CurrentLine,
+) -> Option<CurrentLine> {
+    [capabilities.image, capabilities.image_fallback]
+        .into_iter()
+        .flatten()
+        .find_map(|capability| {
+            write_image_with(
+                writer,
+                capability,
+                terminal_size,
+                resource_handler,
+                url,
+                indent,
+                current_line.clone(),
+            )
+            .map_err(|error| {
+                event!(Level::ERROR, ?error, %url, "failed to render image with capability {:?}: {:#}", capability, error);
+            })
+            .ok()
+        })
+}
+
+pub fn write_rule<W: StyleSink>(
     writer: &mut W,
     capabilities: &TerminalCapabilities,
     theme: &Theme,
     length: u16,
 ) -> std::io::Result<()> {
-    let rule = "\u{2550}".repeat(length as usize);
+    let rule = theme.glyphs.rule.repeat(length as usize);
     write_styled(
         writer,
         capabilities,
@@ -236,13 +445,29 @@ pub fn write_rule<W: Write>(
     )
 }
 
-pub fn write_code_block_border<W: Write>(
+/// The width of the borders of a code block at `indent`, whose widest line is `content_width` wide.
+fn code_block_border_width(settings: &Settings, indent: u16, content_width: Option<u16>) -> u16 {
+    let columns = settings.terminal_size.columns;
+    let available = columns.saturating_sub(indent).max(1);
+    match (settings.theme.code_block_border_width, content_width) {
+        (CodeBlockBorderWidth::Fixed(width), _) => width.min(columns),
+        (CodeBlockBorderWidth::FullWidth, _) => available,
+        (CodeBlockBorderWidth::Content, Some(width)) => width.clamp(1, available),
+        // Without lookahead we can't measure the contents, so fall back to the default width
+        (CodeBlockBorderWidth::Content, None) => match CodeBlockBorderWidth::default() {
+            CodeBlockBorderWidth::Fixed(width) => width.min(columns),
+            _ => available,
+        },
+    }
+}
+
+pub fn write_code_block_border<W: StyleSink>(
     writer: &mut W,
     theme: &Theme,
     capabilities: &TerminalCapabilities,
-    terminal_size: &TerminalSize,
+    width: u16,
 ) -> std::io::Result<()> {
-    let separator = "\u{2500}".repeat(terminal_size.columns.min(20) as usize);
+    let separator = theme.glyphs.code_block_border.repeat(width as usize);
     write_styled(
         writer,
         capabilities,
@@ -252,20 +477,27 @@ pub fn write_code_block_border<W: Write>(
     writeln!(writer)
 }
 
-pub fn write_link_refs<W: Write>(
+pub fn write_link_refs<W: StyleSink>(
     writer: &mut W,
     environment: &Environment,
     capabilities: &TerminalCapabilities,
+    indent: u16,
     links: Vec<LinkReferenceDefinition>,
 ) -> Result<()> {
     if !links.is_empty() {
         writeln!(writer)?;
         for link in links {
+            write_indent(writer, indent)?;
+            // Tell images from links, because images and links share the same indexes
+            let prefix = match link.kind {
+                LinkReferenceKind::Link => "",
+                LinkReferenceKind::Image => "image: ",
+            };
             write_styled(
                 writer,
                 capabilities,
                 &link.style,
-                format!("[{}]: ", link.index),
+                format!("[{}]: {}", link.index, prefix),
             )?;
 
             // If we can resolve the link try to write it as inline link to make the URL
@@ -274,9 +506,9 @@ pub fn write_link_refs<W: Write>(
             if let Some(url) = environment.resolve_reference(&link.target) {
                 match &capabilities.style {
                     Some(StyleCapability::Ansi) => {
-                        set_link_url(writer, url, &environment.hostname)?;
+                        set_link_url(writer, capabilities.passthrough, url, &environment.hostname)?;
                         write_styled(writer, capabilities, &link.style, link.target)?;
-                        clear_link(writer)?;
+                        clear_link(writer, capabilities.passthrough)?;
                     }
                     None => write_styled(writer, capabilities, &link.style, link.target)?,
                 };
@@ -289,7 +521,7 @@ pub fn write_link_refs<W: Write>(
                     writer,
                     capabilities,
                     &link.style,
-                    format!(" {}", link.title),
+                    format!(" \"{}\"", link.title),
                 )?;
             }
             writeln!(writer)?;
@@ -298,62 +530,96 @@ pub fn write_link_refs<W: Write>(
     Ok(())
 }
 
-pub fn write_start_code_block<W: Write>(
+/// Whether `language` is one of the diagram languages in `settings`.
+pub fn is_diagram_language(settings: &Settings, language: &str) -> bool {
+    settings
+        .diagram_languages
+        .iter()
+        .any(|diagram| diagram.eq_ignore_ascii_case(language))
+}
+
+pub fn write_start_code_block<W: StyleSink>(
     writer: &mut W,
     settings: &Settings,
     indent: u16,
     style: Style,
     block_kind: CodeBlockKind<'_>,
+    content_width: Option<u16>,
 ) -> Result<StackedState> {
+    let border_width = code_block_border_width(settings, indent, content_width);
     write_indent(writer, indent)?;
     write_code_block_border(
         writer,
         &settings.theme,
         &settings.terminal_capabilities,
-        &settings.terminal_size,
+        border_width,
     )?;
+    // Tell that we do not render diagrams, and write their source as is
+    let is_diagram = match &block_kind {
+        CodeBlockKind::Fenced(name) if is_diagram_language(settings, name) => {
+            write_indent(writer, indent)?;
+            write_styled(
+                writer,
+                &settings.terminal_capabilities,
+                &Style::new().fg_color(Some(settings.theme.code_block_border_color)),
+                format!(
+                    "{} {name} diagram (not rendered)",
+                    settings.theme.glyphs.diagram
+                ),
+            )?;
+            writeln!(writer)?;
+            true
+        }
+        _ => false,
+    };
     // And start the indent for the contents of the block
     write_indent(writer, indent)?;
 
+    let literal_block = |infer_syntax| {
+        LiteralBlockAttrs {
+            indent,
+            style: settings.theme.code_style.on_top_of(&style),
+            infer_syntax,
+            open_line: false,
+            border_width,
+        }
+        .into()
+    };
     match (&settings.terminal_capabilities.style, block_kind) {
+        (_, _) if is_diagram => Ok(literal_block(false)),
         (Some(StyleCapability::Ansi), CodeBlockKind::Fenced(name)) if !name.is_empty() => {
             match settings.syntax_set.find_syntax_by_token(&name) {
-                None => Ok(LiteralBlockAttrs {
+                None => Ok(literal_block(false)),
+                Some(syntax) => Ok(HighlightBlockAttrs::new(
+                    syntax,
+                    settings.syntax_theme,
                     indent,
-                    style: settings.theme.code_style.on_top_of(&style),
-                }
+                    border_width,
+                )
                 .into()),
-                Some(syntax) => {
-                    let parse_state = ParseState::new(syntax);
-                    let highlight_state = HighlightState::new(highlighter(), ScopeStack::new());
-                    Ok(HighlightBlockAttrs {
-                        indent,
-                        highlight_state,
-                        parse_state,
-                    }
-                    .into())
-                }
             }
         }
-        (_, _) => Ok(LiteralBlockAttrs {
-            indent,
-            style: settings.theme.code_style.on_top_of(&style),
+        (Some(StyleCapability::Ansi), CodeBlockKind::Indented)
+            if settings.infer_indented_code_syntax =>
+        {
+            Ok(literal_block(true))
         }
-        .into()),
+        (_, _) => Ok(literal_block(false)),
     }
 }
 
-pub fn write_start_heading<W: Write>(
+pub fn write_start_heading<W: StyleSink>(
     writer: &mut W,
     capabilities: &TerminalCapabilities,
     style: Style,
+    glyphs: &Glyphs,
     level: HeadingLevel,
 ) -> Result<StackedState> {
     write_styled(
         writer,
         capabilities,
         &style,
-        "\u{2504}".repeat(level as usize),
+        glyphs.heading.repeat(level as usize),
     )?;
 
     // Headlines never wrap, so indent doesn't matter
@@ -372,7 +638,7 @@ fn calculate_column_widths(table: &CurrentTable) -> Option<Vec<usize>> {
             cell.fragments
                 .as_slice()
                 .iter()
-                .fold(0, |acc, x| acc + x.len())
+                .fold(0, |acc, x| acc + display_width(x))
         });
         widths = zip(widths, current).map(|(a, b)| max(a, b)).collect();
     }
@@ -380,30 +646,70 @@ fn calculate_column_widths(table: &CurrentTable) -> Option<Vec<usize>> {
 }
 
 // TODO: Support themes for table rule.
-fn write_table_rule<W: Write>(
+fn write_table_rule<W: StyleSink>(
     writer: &mut W,
     capabilities: &TerminalCapabilities,
+    glyphs: &Glyphs,
     length: u16,
 ) -> Result<()> {
-    let rule = "\u{2500}".repeat(length.into());
+    let rule = glyphs.table_border.repeat(length.into());
     write_styled(writer, capabilities, &Style::new(), rule)?;
     writeln!(writer)
 }
 
+/// Write the rule below the head of a table with aligned columns.
+///
+/// Mark the alignment of columns like in markdown, i.e. with a colon on the left for left-aligned
+/// columns, on the right for right-aligned columns, and on both sides for centered columns.
+/// Separate the rules of columns by spaces, to keep markers of adjacent columns apart.
+fn write_table_head_rule<W: StyleSink>(
+    writer: &mut W,
+    capabilities: &TerminalCapabilities,
+    glyphs: &Glyphs,
+    widths: &[usize],
+    alignments: &[Alignment],
+    length: u16,
+) -> Result<()> {
+    use Alignment::*;
+    let mut rule = String::new();
+    for (&width, &alignment) in zip(widths, alignments) {
+        // Every cell has one column of padding on each side, see format_table_cell
+        rule.push(' ');
+        for column in 0..width {
+            let marker = match alignment {
+                Left | Center if column == 0 => true,
+                Right | Center if column + 1 == width => true,
+                _ => false,
+            };
+            rule.push_str(if marker { ":" } else { glyphs.table_border });
+        }
+        rule.push(' ');
+    }
+    let rule = rule.chars().take(length.into()).collect::<String>();
+    write_styled(writer, capabilities, &Style::new(), rule.trim_end())?;
+    writeln!(writer)
+}
+
 fn format_table_cell(cell: TableCell, width: usize, alignment: Alignment) -> String {
     use Alignment::*;
     let content = cell.fragments.join("");
-    match alignment {
-        Left | None => format!(" {:<width$} ", content),
-        Center => format!(" {:^width$} ", content),
-        Right => format!(" {:>width$} ", content),
-    }
+    // Pad by display width; format! pads by the number of characters which is wrong for wide
+    // characters like emoji.
+    let padding = width.saturating_sub(display_width(&content));
+    let (left, right) = match alignment {
+        Left | None => (0, padding),
+        Center => (padding / 2, padding - padding / 2),
+        Right => (padding, 0),
+    };
+    format!(" {}{content}{} ", " ".repeat(left), " ".repeat(right))
 }
 
-pub fn write_table<W: Write>(
+pub fn write_table<W: StyleSink>(
     writer: &mut W,
     capabilities: &TerminalCapabilities,
+    glyphs: &Glyphs,
     terminal_size: &TerminalSize,
+    indent: u16,
     table: CurrentTable,
 ) -> Result<()> {
     if let Some(widths) = calculate_column_widths(&table) {
@@ -414,12 +720,14 @@ pub fn write_table<W: Write>(
             (total_width + 2 * widths.len())
                 .try_into()
                 .unwrap_or(u16::MAX),
-            terminal_size.columns,
+            terminal_size.columns.saturating_sub(indent).max(1),
         );
-        write_table_rule(writer, capabilities, rule_length)?;
+        write_indent(writer, indent)?;
+        write_table_rule(writer, capabilities, glyphs, rule_length)?;
 
         // Write the table head in bold if any.
         if let Some(head) = table.head {
+            write_indent(writer, indent)?;
             for ((cell, &width), &alignment) in zip(zip(head.cells, &widths), &table.alignments) {
                 write_styled(
                     writer,
@@ -429,11 +737,24 @@ pub fn write_table<W: Write>(
                 )?;
             }
             writeln!(writer)?;
-            write_table_rule(writer, capabilities, rule_length)?;
+            write_indent(writer, indent)?;
+            if table.alignments.iter().any(|a| *a != Alignment::None) {
+                write_table_head_rule(
+                    writer,
+                    capabilities,
+                    glyphs,
+                    &widths,
+                    &table.alignments,
+                    rule_length,
+                )?;
+            } else {
+                write_table_rule(writer, capabilities, glyphs, rule_length)?;
+            }
         }
 
         // Write table body.
         for row in table.rows {
+            write_indent(writer, indent)?;
             for ((cell, &width), &alignment) in zip(zip(row.cells, &widths), &table.alignments) {
                 write_styled(
                     writer,
@@ -444,7 +765,8 @@ pub fn write_table<W: Write>(
             }
             writeln!(writer)?;
         }
-        write_table_rule(writer, capabilities, rule_length)?;
+        write_indent(writer, indent)?;
+        write_table_rule(writer, capabilities, glyphs, rule_length)?;
     }
     // Do nothing when there are no rows in the table, which should be impossible.
     Ok(())
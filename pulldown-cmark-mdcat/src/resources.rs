@@ -6,10 +6,12 @@
 
 //! Access to resources referenced from markdown documents.
 
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::io::{Error, ErrorKind, Result};
 
 use mime::Mime;
+use tracing::{event, Level};
 use url::Url;
 
 mod file;
@@ -52,9 +54,10 @@ pub trait ResourceUrlHandler {
     /// Read data from the given `url`, and return the data and its associated mime type if known,
     /// or any IO error which occurred while reading from the resource.
     ///
-    /// Alternatively, return an IO error with [`ErrorKind::Unsupported`] to indicate that the
-    /// given `url` is not supported by this resource handler.  In this case a higher level
-    /// resource handler may try a different handler.
+    /// Alternatively, return an [`UnsupportedScheme`] error to indicate that the given `url` is
+    /// not supported by this resource handler, e.g. with [`filter_schemes`].  In this case a higher
+    /// level resource handler may try a different handler.  Any other error, including errors of
+    /// kind [`ErrorKind::Unsupported`], indicates that this handler failed to read `url`.
     fn read_resource(&self, url: &Url) -> Result<MimeData>;
 }
 
@@ -64,22 +67,56 @@ impl<R: ResourceUrlHandler + ?Sized> ResourceUrlHandler for &'_ R {
     }
 }
 
+/// A resource handler does not support the scheme of a URL.
+///
+/// Resource handlers return this error wrapped in an IO error of kind [`ErrorKind::Unsupported`],
+/// see [`UnsupportedScheme::error`], to tell [`DispatchingResourceHandler`] to try the next
+/// handler.
+#[derive(Debug)]
+pub struct UnsupportedScheme(String);
+
+impl UnsupportedScheme {
+    /// Create an IO error for an unsupported scheme, with the given `message`.
+    pub fn error<S: Into<String>>(message: S) -> Error {
+        Error::new(ErrorKind::Unsupported, UnsupportedScheme(message.into()))
+    }
+
+    /// Whether `error` denotes an unsupported scheme.
+    pub fn is_unsupported_scheme(error: &Error) -> bool {
+        error.kind() == ErrorKind::Unsupported
+            && error
+                .get_ref()
+                .is_some_and(|inner| inner.is::<UnsupportedScheme>())
+    }
+}
+
+impl std::fmt::Display for UnsupportedScheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedScheme {}
+
 /// Filter by URL scheme.
 ///
-/// Return `Ok(url)` if `url` has the given `scheme`, otherwise return an IO error with error kind
-/// [`ErrorKind::Unsupported`].
+/// Return `Ok(url)` if `url` has the given `scheme`, otherwise return an [`UnsupportedScheme`]
+/// error.
 pub fn filter_schemes<'a>(schemes: &[&str], url: &'a Url) -> Result<&'a Url> {
     if schemes.contains(&url.scheme()) {
         Ok(url)
     } else {
-        Err(Error::new(
-            ErrorKind::Unsupported,
-            format!("Unsupported scheme in {url}, expected one of {schemes:?}"),
-        ))
+        Err(UnsupportedScheme::error(format!(
+            "Unsupported scheme in {url}, expected one of {schemes:?}"
+        )))
     }
 }
 
 /// A resource handler which dispatches reading among a list of inner handlers.
+///
+/// Inner handlers typically only support a subset of URL schemes, and each handler applies its
+/// own read limit, e.g. [`FileResourceHandler::new`].  Combine handlers with different limits to
+/// limit reading per scheme, e.g. to allow large local files, but only small remote resources.
 pub struct DispatchingResourceHandler {
     /// Inner handlers.
     handlers: Vec<Box<dyn ResourceUrlHandler>>,
@@ -95,22 +132,62 @@ impl DispatchingResourceHandler {
 impl ResourceUrlHandler for DispatchingResourceHandler {
     /// Read from the given resource `url`.
     ///
-    /// Try every inner handler one after another, while handlers return an
-    /// [`ErrorKind::Unsupported`] IO error.  For any other error abort and return the error.
+    /// Try every inner handler one after another, while handlers return an [`UnsupportedScheme`]
+    /// error.  For any other error abort and return the error.
     ///
-    /// Return the first different result, i.e. either data read or another error.
+    /// Return the first different result, i.e. either data read or another error, or an
+    /// [`UnsupportedScheme`] error if no handler supports `url`.
     fn read_resource(&self, url: &Url) -> Result<MimeData> {
         for handler in &self.handlers {
             match handler.read_resource(url) {
                 Ok(data) => return Ok(data),
-                Err(error) if error.kind() == ErrorKind::Unsupported => continue,
+                Err(error) if UnsupportedScheme::is_unsupported_scheme(&error) => continue,
                 Err(error) => return Err(error),
             }
         }
-        Err(Error::new(
-            ErrorKind::Unsupported,
-            format!("No handler supported reading from {url}"),
-        ))
+        Err(UnsupportedScheme::error(format!(
+            "No handler supported reading from {url}"
+        )))
+    }
+}
+
+/// A resource handler which rewrites URLs before reading.
+///
+/// This handler applies a `rewrite` function to every URL, and then reads the rewritten URL with
+/// an `inner` handler.  Use it for instance to route all remote resources through a caching proxy,
+/// or to upgrade `http:` URLs to `https:`.
+///
+/// mdcat resolves references in the document against the base URL of the
+/// [`Environment`](crate::Environment) first, so `rewrite` always receives absolute URLs.
+///
+/// Note that some terminals (e.g. Terminology) load images directly from URLs; in this case mdcat
+/// never passes the URL to the resource handler, so this handler doesn't rewrite these URLs.
+pub struct RewritingResourceHandler<H, F> {
+    inner: H,
+    rewrite: F,
+}
+
+impl<H, F> RewritingResourceHandler<H, F>
+where
+    H: ResourceUrlHandler,
+    F: Fn(&Url) -> Url,
+{
+    /// Create a new handler which rewrites URLs with `rewrite` before reading them with `inner`.
+    pub fn new(inner: H, rewrite: F) -> Self {
+        Self { inner, rewrite }
+    }
+}
+
+impl<H, F> ResourceUrlHandler for RewritingResourceHandler<H, F>
+where
+    H: ResourceUrlHandler,
+    F: Fn(&Url) -> Url,
+{
+    /// Rewrite the given `url`, and read the rewritten URL with the inner handler.
+    fn read_resource(&self, url: &Url) -> Result<MimeData> {
+        let rewritten = (self.rewrite)(url);
+        event!(Level::DEBUG, %url, %rewritten, "Rewrote resource URL");
+        self.inner.read_resource(&rewritten)
     }
 }
 
@@ -119,11 +196,182 @@ impl ResourceUrlHandler for DispatchingResourceHandler {
 pub struct NoopResourceHandler;
 
 impl ResourceUrlHandler for NoopResourceHandler {
-    /// Always return an [`ErrorKind::Unsupported`] error.
+    /// Always return an [`UnsupportedScheme`] error.
     fn read_resource(&self, url: &Url) -> Result<MimeData> {
-        Err(Error::new(
-            ErrorKind::Unsupported,
-            format!("Reading from resource {url} is not supported"),
-        ))
+        Err(UnsupportedScheme::error(format!(
+            "Reading from resource {url} is not supported"
+        )))
+    }
+}
+
+/// A resource handler which reads resources from memory.
+///
+/// Use this handler to provide resources from a bundle instead of the file system or the network,
+/// or to render images deterministically in tests.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryResourceHandler {
+    resources: HashMap<Url, MimeData>,
+}
+
+impl InMemoryResourceHandler {
+    /// Create a new handler which reads from the given `resources`.
+    pub fn new(resources: HashMap<Url, MimeData>) -> Self {
+        Self { resources }
+    }
+
+    /// Add a resource with the given `data` at `url`.
+    ///
+    /// Replace any resource previously added at `url`.
+    pub fn insert(&mut self, url: Url, data: MimeData) {
+        self.resources.insert(url, data);
+    }
+}
+
+impl FromIterator<(Url, MimeData)> for InMemoryResourceHandler {
+    fn from_iter<T: IntoIterator<Item = (Url, MimeData)>>(iter: T) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+impl ResourceUrlHandler for InMemoryResourceHandler {
+    /// Return a copy of the resource at `url`.
+    ///
+    /// Return an [`ErrorKind::NotFound`] error if there's no resource at `url`.
+    fn read_resource(&self, url: &Url) -> Result<MimeData> {
+        self.resources.get(url).cloned().ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("Resource {url} not found in memory"),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use similar_asserts::assert_eq;
+
+    use super::*;
+
+    struct RecordingHandler(RefCell<Vec<String>>);
+
+    impl ResourceUrlHandler for RecordingHandler {
+        fn read_resource(&self, url: &Url) -> Result<MimeData> {
+            self.0.borrow_mut().push(url.to_string());
+            Ok(MimeData {
+                mime_type: None,
+                data: Vec::new(),
+            })
+        }
+    }
+
+    /// A handler which supports `file:` URLs, but fails to read them.
+    struct FailingHandler(ErrorKind);
+
+    impl ResourceUrlHandler for FailingHandler {
+        fn read_resource(&self, url: &Url) -> Result<MimeData> {
+            filter_schemes(&["file"], url).and_then(|_| Err(Error::new(self.0, "Failed to read")))
+        }
+    }
+
+    fn dispatching_handler(kind: ErrorKind) -> DispatchingResourceHandler {
+        let data = MimeData {
+            mime_type: None,
+            data: Vec::new(),
+        };
+        DispatchingResourceHandler::new(vec![
+            Box::new(FailingHandler(kind)),
+            Box::new(InMemoryResourceHandler::from_iter([
+                (Url::parse("file:///foo.png").unwrap(), data.clone()),
+                (Url::parse("https://example.com/foo.png").unwrap(), data),
+            ])),
+        ])
+    }
+
+    #[test]
+    fn dispatching_handler_returns_read_errors() {
+        for kind in [ErrorKind::PermissionDenied, ErrorKind::Unsupported] {
+            let error = dispatching_handler(kind)
+                .read_resource(&Url::parse("file:///foo.png").unwrap())
+                .unwrap_err();
+            assert_eq!(error.kind(), kind);
+            assert_eq!(error.to_string(), "Failed to read");
+            assert!(!UnsupportedScheme::is_unsupported_scheme(&error));
+        }
+    }
+
+    #[test]
+    fn dispatching_handler_skips_unsupported_schemes() {
+        let handler = dispatching_handler(ErrorKind::PermissionDenied);
+        assert!(handler
+            .read_resource(&Url::parse("https://example.com/foo.png").unwrap())
+            .is_ok());
+        let handler = DispatchingResourceHandler::new(vec![
+            Box::new(FailingHandler(ErrorKind::PermissionDenied)),
+            Box::new(NoopResourceHandler),
+        ]);
+        let error = handler
+            .read_resource(&Url::parse("ftp://example.com/foo.png").unwrap())
+            .unwrap_err();
+        assert!(UnsupportedScheme::is_unsupported_scheme(&error));
+        assert_eq!(
+            error.to_string(),
+            "No handler supported reading from ftp://example.com/foo.png"
+        );
+    }
+
+    #[test]
+    fn in_memory_handler_reads_stored_resource() {
+        let url = Url::parse("https://example.com/image.png").unwrap();
+        let handler = InMemoryResourceHandler::from_iter([(
+            url.clone(),
+            MimeData {
+                mime_type: Some(mime::IMAGE_PNG),
+                data: vec![1, 2, 3],
+            },
+        )]);
+        let data = handler.read_resource(&url).unwrap();
+        assert_eq!(data.mime_type, Some(mime::IMAGE_PNG));
+        assert_eq!(data.data, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn in_memory_handler_missing_resource() {
+        let mut handler = InMemoryResourceHandler::default();
+        handler.insert(
+            Url::parse("https://example.com/image.png").unwrap(),
+            MimeData {
+                mime_type: None,
+                data: Vec::new(),
+            },
+        );
+        let error = handler
+            .read_resource(&Url::parse("https://example.com/other.png").unwrap())
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn rewriting_handler_reads_rewritten_url() {
+        let inner = RecordingHandler(RefCell::new(Vec::new()));
+        let handler = RewritingResourceHandler::new(&inner, |url: &Url| {
+            let mut url = url.clone();
+            if url.scheme() == "http" {
+                url.set_scheme("https").unwrap();
+            }
+            url
+        });
+        handler
+            .read_resource(&Url::parse("http://example.com/foo.png").unwrap())
+            .unwrap();
+        handler
+            .read_resource(&Url::parse("file:///foo.png").unwrap())
+            .unwrap();
+        assert_eq!(
+            *inner.0.borrow(),
+            vec!["https://example.com/foo.png", "file:///foo.png"]
+        );
     }
 }
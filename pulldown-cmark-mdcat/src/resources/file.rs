@@ -9,7 +9,7 @@
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::{Error, ErrorKind, Result};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use mime::Mime;
 use tracing::{event, instrument, Level};
@@ -21,6 +21,7 @@ use super::{filter_schemes, MimeData, ResourceUrlHandler};
 #[derive(Debug, Clone)]
 pub struct FileResourceHandler {
     read_limit: u64,
+    root: Option<PathBuf>,
 }
 
 impl FileResourceHandler {
@@ -28,7 +29,51 @@ impl FileResourceHandler {
     ///
     /// The resource handler does not read beyond `read_limit`.
     pub fn new(read_limit: u64) -> Self {
-        Self { read_limit }
+        Self {
+            read_limit,
+            root: None,
+        }
+    }
+
+    /// Only read files within the given `root` directory.
+    ///
+    /// Reject all files outside of `root` with [`ErrorKind::PermissionDenied`], after resolving
+    /// `..` components and symlinks.  Use this to prevent documents from reading arbitrary files,
+    /// e.g. with `../../.ssh/id_ed25519` as image URL.
+    pub fn confined_to<P: Into<PathBuf>>(self, root: P) -> Self {
+        Self {
+            root: Some(root.into()),
+            ..self
+        }
+    }
+
+    /// Check that `path` is within the root directory, if any.
+    fn check_confinement(&self, path: &Path) -> Result<()> {
+        match &self.root {
+            None => Ok(()),
+            Some(root) => {
+                let root = root.canonicalize()?;
+                let path = path.canonicalize()?;
+                if path.starts_with(&root) {
+                    Ok(())
+                } else {
+                    event!(
+                        Level::WARN,
+                        "Refusing to read {} outside of {}",
+                        path.display(),
+                        root.display()
+                    );
+                    Err(Error::new(
+                        ErrorKind::PermissionDenied,
+                        format!(
+                            "Refusing to read {} outside of {}",
+                            path.display(),
+                            root.display()
+                        ),
+                    ))
+                }
+            }
+        }
     }
 }
 
@@ -64,6 +109,7 @@ impl ResourceUrlHandler for FileResourceHandler {
                         "Reading from resource file {}",
                         path.display()
                     );
+                    self.check_confinement(&path)?;
                     let mut buffer = Vec::new();
                     File::open(&path)?
                         // Read a byte more than the limit differentiate an expected EOF from hitting the limit
@@ -122,18 +168,42 @@ mod tests {
     #[test]
     fn read_resource_obeys_size_limit() {
         let cwd = Url::from_directory_path(std::env::current_dir().unwrap()).unwrap();
-        let client = FileResourceHandler { read_limit: 10 };
+        let client = FileResourceHandler::new(10);
 
         let resource = cwd.join("../sample/rust-logo.svg").unwrap();
         let error = client.read_resource(&resource).unwrap_err().to_string();
         assert_eq!(error, format!("Contents of {resource} exceeded 10 bytes"));
     }
 
+    #[test]
+    fn read_resource_within_root() {
+        let cwd = Url::from_directory_path(std::env::current_dir().unwrap()).unwrap();
+        let client = FileResourceHandler::new(5_000_000).confined_to("../sample");
+
+        let resource = cwd.join("../sample/rust-logo.svg").unwrap();
+        let mime_type = client.read_resource(&resource).unwrap().mime_type;
+        assert_eq!(mime_type, Some(mime::IMAGE_SVG));
+    }
+
+    #[test]
+    fn read_resource_rejects_files_outside_of_root() {
+        let cwd = Url::from_directory_path(std::env::current_dir().unwrap()).unwrap();
+        let client = FileResourceHandler::new(5_000_000).confined_to("src");
+
+        let resource = cwd.join("src/../Cargo.toml").unwrap();
+        let error = client.read_resource(&resource).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+
+        let resource = cwd.join("../sample/rust-logo.svg").unwrap();
+        let error = client.read_resource(&resource).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+    }
+
     #[test]
     fn read_resource_ignores_http() {
         let url = Url::parse("https://example.com").unwrap();
 
-        let client = FileResourceHandler { read_limit: 10 };
+        let client = FileResourceHandler::new(10);
         let error = client.read_resource(&url).unwrap_err().to_string();
         assert_eq!(
             error,
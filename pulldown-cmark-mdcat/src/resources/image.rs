@@ -6,10 +6,12 @@
 
 //! Inline image handling
 
+use std::cell::RefCell;
 use std::io::Write;
 
 use url::Url;
 
+use crate::resources::MimeData;
 use crate::{ResourceUrlHandler, TerminalSize};
 
 /// An implementation of an inline image protocol.
@@ -66,3 +68,102 @@ pub fn downsize_to_columns(
         None
     }
 }
+
+/// A resource handler which remembers the last resource it read.
+///
+/// We use this handler to inspect the image data an image protocol read, without reading the image
+/// a second time.
+pub struct RecordingResourceHandler<'a> {
+    inner: &'a dyn ResourceUrlHandler,
+    last_resource: RefCell<Option<MimeData>>,
+}
+
+impl<'a> RecordingResourceHandler<'a> {
+    /// Record all resources read through `inner`.
+    pub fn new(inner: &'a dyn ResourceUrlHandler) -> Self {
+        Self {
+            inner,
+            last_resource: RefCell::new(None),
+        }
+    }
+
+    /// Take the last resource read through this handler.
+    pub fn take_last_resource(&self) -> Option<MimeData> {
+        self.last_resource.take()
+    }
+}
+
+impl ResourceUrlHandler for RecordingResourceHandler<'_> {
+    fn read_resource(&self, url: &Url) -> std::io::Result<MimeData> {
+        let data = self.inner.read_resource(url)?;
+        self.last_resource.replace(Some(data.clone()));
+        Ok(data)
+    }
+}
+
+/// Format a caption with the dimensions and the format of an image, e.g. `(640×480, PNG)`.
+#[cfg_attr(not(feature = "image-processing"), allow(dead_code))]
+pub fn format_image_caption(width: u32, height: u32, format: &str) -> String {
+    format!("({width}\u{d7}{height}, {format})")
+}
+
+/// Get a caption with the dimensions and format of the image in `mime_data`.
+///
+/// Only decode the image header to obtain the dimensions.  Return `None` if the image format is
+/// unknown or the image header is invalid, e.g. for SVG images.
+#[cfg(feature = "image-processing")]
+pub fn image_caption(mime_data: &MimeData) -> Option<String> {
+    use image::ImageReader;
+    use std::io::Cursor;
+    let reader = ImageReader::new(Cursor::new(&mime_data.data))
+        .with_guessed_format()
+        .ok()?;
+    let format = reader.format()?;
+    let (width, height) = reader.into_dimensions().ok()?;
+    Some(format_image_caption(
+        width,
+        height,
+        &format!("{format:?}").to_uppercase(),
+    ))
+}
+
+/// Get a caption with the dimensions and format of the image in `mime_data`.
+///
+/// Always return `None` because we cannot decode images without the `image-processing` feature.
+#[cfg(not(feature = "image-processing"))]
+pub fn image_caption(_mime_data: &MimeData) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_image_caption;
+
+    #[test]
+    fn image_caption_format() {
+        assert_eq!(format_image_caption(640, 480, "PNG"), "(640\u{d7}480, PNG)");
+    }
+
+    #[test]
+    #[cfg(feature = "image-processing")]
+    fn image_caption_of_png() {
+        let mime_data = crate::resources::MimeData {
+            mime_type: Some(mime::IMAGE_PNG),
+            data: std::fs::read("../sample/rust-logo-128x128.png").unwrap(),
+        };
+        assert_eq!(
+            super::image_caption(&mime_data).as_deref(),
+            Some("(128\u{d7}128, PNG)")
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "image-processing")]
+    fn no_image_caption_of_unknown_data() {
+        let mime_data = crate::resources::MimeData {
+            mime_type: None,
+            data: b"<svg></svg>".to_vec(),
+        };
+        assert_eq!(super::image_caption(&mime_data), None);
+    }
+}
@@ -0,0 +1,281 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Capture styled text segments instead of ANSI escape sequences.
+//!
+//! See [`SegmentWriter`].
+
+use std::io::{Result, Write};
+
+use anstyle::Style;
+
+use crate::sink::StyleSink;
+
+/// A style sink which turns rendered output into styled text segments.
+///
+/// This sink passes every piece of styled text along with its style to a callback, and all plain
+/// text with the default style.  It drops all escape sequences written to it, e.g. OSC 8 links,
+/// jump marks, or inline images.
+///
+/// Use this sink with [`crate::push_styled`] to integrate mdcat rendering into applications which
+/// do not write to a terminal directly, e.g. to build styled text for a TUI library.  Text
+/// segments contain newlines as rendered by mdcat; callers need to split lines themselves if
+/// required.
+///
+/// Render with [`crate::TerminalProgram::Ansi`] capabilities to get styled output, and flush the
+/// writer after rendering to process any remaining output.
+///
+/// ```
+/// # use pulldown_cmark::Parser;
+/// # use pulldown_cmark_mdcat::resources::NoopResourceHandler;
+/// # use pulldown_cmark_mdcat::segments::SegmentWriter;
+/// # use pulldown_cmark_mdcat::*;
+/// # use std::io::Write;
+/// # use syntect::parsing::SyntaxSet;
+/// let syntax_set = SyntaxSet::default();
+/// let settings = Settings::new(
+///     TerminalProgram::Ansi.capabilities(),
+///     TerminalSize::default(),
+///     &syntax_set,
+/// );
+/// let environment = Environment::for_local_directory(&std::env::current_dir()?)?;
+/// let mut segments = Vec::new();
+/// let mut writer = SegmentWriter::new(|style: &anstyle::Style, text: &str| {
+///     segments.push((*style, text.to_owned()));
+/// });
+/// push_styled(
+///     &settings,
+///     &environment,
+///     &NoopResourceHandler,
+///     &mut writer,
+///     Parser::new("Hello **World**"),
+/// )?;
+/// writer.flush()?;
+/// drop(writer);
+/// assert_eq!(segments, vec![
+///     (anstyle::Style::new(), "Hello".to_owned()),
+///     (anstyle::Style::new(), " ".to_owned()),
+///     (anstyle::Style::new().bold(), "World".to_owned()),
+///     (anstyle::Style::new(), "\n".to_owned()),
+/// ]);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct SegmentWriter<F> {
+    callback: F,
+    /// Bytes not yet processed, e.g. an incomplete escape sequence.
+    pending: Vec<u8>,
+}
+
+impl<F: FnMut(&Style, &str)> SegmentWriter<F> {
+    /// Create a new writer which passes styled text segments to `callback`.
+    pub fn new(callback: F) -> Self {
+        Self {
+            callback,
+            pending: Vec::new(),
+        }
+    }
+
+    fn emit_text(&mut self, text: &[u8]) {
+        if !text.is_empty() {
+            (self.callback)(&Style::new(), &String::from_utf8_lossy(text));
+        }
+    }
+
+    /// Process all pending bytes.
+    ///
+    /// If `complete` is false keep incomplete escape sequences and UTF-8 sequences at the end of
+    /// the pending bytes for later processing.
+    fn process(&mut self, complete: bool) {
+        let pending = std::mem::take(&mut self.pending);
+        let mut rest = pending.as_slice();
+        while !rest.is_empty() {
+            if rest[0] == 0x1b {
+                match escape_sequence_length(rest) {
+                    Some(length) => rest = &rest[length..],
+                    None if complete => rest = &[],
+                    None => break,
+                }
+            } else {
+                let end = rest.iter().position(|b| *b == 0x1b).unwrap_or(rest.len());
+                let text = &rest[..end];
+                let valid = if end == rest.len() && !complete {
+                    // Keep incomplete UTF-8 at the end for the next write.
+                    match std::str::from_utf8(text) {
+                        Err(error) if error.error_len().is_none() => error.valid_up_to(),
+                        _ => end,
+                    }
+                } else {
+                    end
+                };
+                self.emit_text(&text[..valid]);
+                rest = &rest[valid..];
+                if valid < end {
+                    break;
+                }
+            }
+        }
+        self.pending = rest.to_vec();
+    }
+}
+
+/// Get the length of the escape sequence at the start of `bytes`.
+///
+/// Return `None` if the escape sequence is incomplete.
+fn escape_sequence_length(bytes: &[u8]) -> Option<usize> {
+    match bytes.get(1)? {
+        // CSI sequences end with a byte in the range 0x40 to 0x7e
+        b'[' => bytes[2..]
+            .iter()
+            .position(|b| (0x40..=0x7e).contains(b))
+            .map(|i| i + 3),
+        // OSC, APC and DCS sequences end with ST or BEL
+        b']' | b'_' | b'P' => (2..bytes.len()).find_map(|i| match bytes[i] {
+            0x07 => Some(i + 1),
+            0x1b if bytes.get(i + 1) == Some(&b'\\') => Some(i + 2),
+            _ => None,
+        }),
+        // Terminology sequences end with NUL
+        b'}' => bytes.iter().position(|b| *b == 0).map(|i| i + 1),
+        _ => Some(2),
+    }
+}
+
+impl<F: FnMut(&Style, &str)> Write for SegmentWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.pending.extend_from_slice(buf);
+        self.process(false);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.process(true);
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&Style, &str)> StyleSink for SegmentWriter<F> {
+    fn write_styled(&mut self, style: &Style, text: &str) -> Result<()> {
+        // Emit all plain text written before, to keep segments in order
+        self.process(true);
+        if !text.is_empty() {
+            (self.callback)(style, text);
+        }
+        Ok(())
+    }
+
+    fn write_style_reset(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use anstyle::{AnsiColor, Style};
+    use pulldown_cmark::{Options, Parser};
+    use similar_asserts::assert_eq;
+
+    use super::SegmentWriter;
+    use crate::resources::NoopResourceHandler;
+    use crate::sink::StyleSink;
+    use crate::{push_styled, Environment, Settings, TerminalProgram};
+
+    fn segments(chunks: &[&[u8]]) -> Vec<(Style, String)> {
+        let mut segments = Vec::new();
+        let mut writer = SegmentWriter::new(|style: &Style, text: &str| {
+            segments.push((*style, text.to_owned()))
+        });
+        for chunk in chunks {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+        segments
+    }
+
+    #[test]
+    fn passes_styles_through_and_drops_escape_sequences() {
+        let mut segments = Vec::new();
+        let mut writer = SegmentWriter::new(|style: &Style, text: &str| {
+            segments.push((*style, text.to_owned()))
+        });
+        let blue = Style::new().fg_color(Some(AnsiColor::Blue.into()));
+        writer
+            .write_all(b"\x1b]8;;https://example.com\x1b\\")
+            .unwrap();
+        writer.write_styled(&blue, "link").unwrap();
+        writer.write_all(b"\x1b]8;;\x1b\\ \x1b[1mplain").unwrap();
+        writer.write_style_reset().unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+        assert_eq!(
+            segments,
+            vec![
+                (blue, "link".to_owned()),
+                (Style::new(), " ".to_owned()),
+                (Style::new(), "plain".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn handles_sequences_split_across_writes() {
+        assert_eq!(
+            segments(&[b"a\x1b[", b"1mb\xe2\x80", b"\xa2\x1b]8;;\x1b\\"]),
+            vec![
+                (Style::new(), "a".to_owned()),
+                (Style::new(), "b".to_owned()),
+                (Style::new(), "\u{2022}".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn render_markdown_to_segments() {
+        let settings = Settings {
+            terminal_capabilities: TerminalProgram::Ansi.capabilities(),
+            ..crate::tests::test_settings()
+        };
+        let env = Environment {
+            base_url: url::Url::parse("file:///").unwrap(),
+            hostname: "HOSTNAME".to_string(),
+        };
+        let mut segments = Vec::new();
+        let mut writer = SegmentWriter::new(|style: &Style, text: &str| {
+            segments.push((*style, text.to_owned()))
+        });
+        push_styled(
+            &settings,
+            &env,
+            &NoopResourceHandler,
+            &mut writer,
+            Parser::new_ext(
+                "Hello **World** ~~gone~~ `code`",
+                Options::ENABLE_STRIKETHROUGH,
+            ),
+        )
+        .unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+        assert_eq!(
+            segments,
+            vec![
+                (Style::new(), "Hello".to_owned()),
+                (Style::new(), " ".to_owned()),
+                (Style::new().bold(), "World".to_owned()),
+                (Style::new(), " ".to_owned()),
+                (Style::new().strikethrough(), "gone".to_owned()),
+                (Style::new(), " ".to_owned()),
+                (
+                    Style::new().fg_color(Some(AnsiColor::Yellow.into())),
+                    "code".to_owned()
+                ),
+                (Style::new(), "\n".to_owned()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,185 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Targets for styled output.
+//!
+//! See [`StyleSink`].
+
+use std::io::{Result, Write};
+
+use anstyle::Style;
+
+/// A writer which also applies styles to text.
+///
+/// mdcat writes all styled text through this trait, and all other output, e.g. plain text,
+/// newlines, or escape sequences for links and images, through [`Write`].
+///
+/// [`AnsiSink`] styles text with ANSI escape sequences; [`crate::push_tty`] renders into an
+/// [`AnsiSink`].  Implement this trait to capture styled text directly, e.g. for a TUI library,
+/// and render with [`crate::push_styled`].  See [`crate::segments::SegmentWriter`] for a sink which
+/// passes styled text to a callback.
+pub trait StyleSink: Write {
+    /// Write `text` with `style`.
+    fn write_styled(&mut self, style: &Style, text: &str) -> Result<()>;
+
+    /// Reset all styles which may still be active.
+    fn write_style_reset(&mut self) -> Result<()>;
+}
+
+impl<S: StyleSink + ?Sized> StyleSink for &mut S {
+    fn write_styled(&mut self, style: &Style, text: &str) -> Result<()> {
+        (**self).write_styled(style, text)
+    }
+
+    fn write_style_reset(&mut self) -> Result<()> {
+        (**self).write_style_reset()
+    }
+}
+
+/// A sink which styles text with ANSI escape sequences.
+///
+/// Write styled text to the underlying writer, wrapped in SGR sequences to set and reset its
+/// style.
+#[derive(Debug)]
+pub struct AnsiSink<W> {
+    writer: W,
+}
+
+impl<W: Write> AnsiSink<W> {
+    /// Create a new sink which writes to `writer`.
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Get the underlying writer back.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Write for AnsiSink<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        self.writer.write_all(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> StyleSink for AnsiSink<W> {
+    fn write_styled(&mut self, style: &Style, text: &str) -> Result<()> {
+        write!(
+            self.writer,
+            "{}{}{}",
+            style.render(),
+            text,
+            style.render_reset()
+        )
+    }
+
+    fn write_style_reset(&mut self) -> Result<()> {
+        write!(self.writer, "{}", anstyle::Reset.render())
+    }
+}
+
+/// A single piece of output recorded by [`RecordingSink`].
+#[derive(Debug)]
+enum Recorded {
+    Bytes(Vec<u8>),
+    Styled(Style, String),
+    Reset,
+}
+
+/// A sink which records output to replay it onto another sink later.
+#[derive(Debug, Default)]
+pub(crate) struct RecordingSink {
+    recorded: Vec<Recorded>,
+}
+
+impl RecordingSink {
+    /// Write all recorded output to `sink`.
+    pub(crate) fn replay<S: StyleSink>(self, sink: &mut S) -> Result<()> {
+        for recorded in self.recorded {
+            match recorded {
+                Recorded::Bytes(bytes) => sink.write_all(&bytes)?,
+                Recorded::Styled(style, text) => sink.write_styled(&style, &text)?,
+                Recorded::Reset => sink.write_style_reset()?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for RecordingSink {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self.recorded.last_mut() {
+            Some(Recorded::Bytes(bytes)) => bytes.extend_from_slice(buf),
+            _ => self.recorded.push(Recorded::Bytes(buf.to_vec())),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl StyleSink for RecordingSink {
+    fn write_styled(&mut self, style: &Style, text: &str) -> Result<()> {
+        self.recorded
+            .push(Recorded::Styled(*style, text.to_owned()));
+        Ok(())
+    }
+
+    fn write_style_reset(&mut self) -> Result<()> {
+        self.recorded.push(Recorded::Reset);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use anstyle::Style;
+    use similar_asserts::assert_eq;
+
+    use super::{AnsiSink, RecordingSink, StyleSink};
+
+    #[test]
+    fn ansi_sink_wraps_styled_text_in_sgr_sequences() {
+        let mut sink = AnsiSink::new(Vec::new());
+        write!(sink, "Hello ").unwrap();
+        sink.write_styled(&Style::new().bold(), "World").unwrap();
+        sink.write_style_reset().unwrap();
+        assert_eq!(
+            String::from_utf8(sink.into_inner()).unwrap(),
+            "Hello \x1b[1mWorld\x1b[0m\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn recording_sink_replays_output_in_order() {
+        let mut recording = RecordingSink::default();
+        write!(recording, "Hello").unwrap();
+        write!(recording, " ").unwrap();
+        recording
+            .write_styled(&Style::new().italic(), "World")
+            .unwrap();
+        recording.write_style_reset().unwrap();
+        let mut sink = AnsiSink::new(Vec::new());
+        recording.replay(&mut sink).unwrap();
+        assert_eq!(
+            String::from_utf8(sink.into_inner()).unwrap(),
+            "Hello \x1b[3mWorld\x1b[0m\x1b[0m"
+        );
+    }
+}
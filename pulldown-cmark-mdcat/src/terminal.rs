@@ -15,4 +15,4 @@ pub mod capabilities;
 mod detect;
 
 pub use self::detect::TerminalProgram;
-pub use self::size::TerminalSize;
+pub use self::size::{PixelSize, TerminalSize};
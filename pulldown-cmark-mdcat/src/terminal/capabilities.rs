@@ -6,8 +6,11 @@
 
 //! Capabilities of terminal emulators.
 
+use std::io::{Result, Write};
+
 use crate::resources::InlineImageProtocol;
 
+pub mod halfblock;
 pub mod iterm2;
 pub mod kitty;
 pub mod terminology;
@@ -19,6 +22,46 @@ pub enum StyleCapability {
     Ansi,
 }
 
+/// How to pass escape sequences through a terminal multiplexer to the outer terminal.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Passthrough {
+    /// Write escape sequences directly to the terminal.
+    #[default]
+    Direct,
+    /// Wrap OSC and APC sequences for tmux.
+    ///
+    /// tmux swallows OSC and APC sequences it doesn't know, e.g. iTerm2 and kitty images.  Wrap
+    /// these sequences in `ESC P tmux; … ESC \`, with all escape characters inside doubled, to
+    /// make tmux pass them on to the outer terminal.  Note that tmux 3.3 and newer only pass
+    /// sequences on with the `allow-passthrough` option.
+    Tmux,
+}
+
+impl Passthrough {
+    /// Write a complete escape `sequence` to `writer`, wrapped for the multiplexer if any.
+    pub(crate) fn write_sequence<W: Write + ?Sized>(
+        self,
+        writer: &mut W,
+        sequence: &[u8],
+    ) -> Result<()> {
+        match self {
+            Passthrough::Direct => writer.write_all(sequence),
+            Passthrough::Tmux => {
+                let mut wrapped = Vec::with_capacity(sequence.len() + 16);
+                wrapped.extend_from_slice(b"\x1bPtmux;");
+                for &byte in sequence {
+                    if byte == 0x1b {
+                        wrapped.push(0x1b);
+                    }
+                    wrapped.push(byte);
+                }
+                wrapped.extend_from_slice(b"\x1b\\");
+                writer.write_all(&wrapped)
+            }
+        }
+    }
+}
+
 /// The capability of the terminal to set marks.
 #[derive(Debug, Copy, Clone)]
 pub enum MarkCapability {
@@ -35,6 +78,8 @@ pub enum ImageCapability {
     ITerm2(iterm2::ITerm2Protocol),
     /// The terminal understands the kitty graphics protocol.
     Kitty(kitty::KittyGraphicsProtocol),
+    /// The terminal supports 24-bit colours, to draw images from Unicode half blocks.
+    HalfBlock(halfblock::HalfBlockProtocol),
 }
 
 impl ImageCapability {
@@ -43,6 +88,7 @@ impl ImageCapability {
             ImageCapability::Terminology(t) => t,
             ImageCapability::ITerm2(t) => t,
             ImageCapability::Kitty(t) => t,
+            ImageCapability::HalfBlock(t) => t,
         }
     }
 }
@@ -59,8 +105,12 @@ pub struct TerminalCapabilities {
     pub style: Option<StyleCapability>,
     /// How the terminal supports images.
     pub image: Option<ImageCapability>,
+    /// How to write images which the image capability fails to write, e.g. unsupported formats.
+    pub image_fallback: Option<ImageCapability>,
     /// How the terminal supports marks.
     pub marks: Option<MarkCapability>,
+    /// How to pass OSC and APC sequences, e.g. links and images, on to the terminal.
+    pub passthrough: Passthrough,
 }
 
 impl Default for TerminalCapabilities {
@@ -69,17 +119,123 @@ impl Default for TerminalCapabilities {
         TerminalCapabilities {
             style: None,
             image: None,
+            image_fallback: None,
             marks: None,
+            passthrough: Passthrough::Direct,
         }
     }
 }
 
 impl TerminalCapabilities {
+    /// Whether the terminal can show images inline.
+    ///
+    /// ```
+    /// # use pulldown_cmark_mdcat::TerminalProgram;
+    /// assert!(TerminalProgram::Kitty.capabilities().supports_images());
+    /// assert!(!TerminalProgram::Ansi.capabilities().supports_images());
+    /// ```
+    pub fn supports_images(&self) -> bool {
+        self.image.is_some()
+    }
+
+    /// Whether the terminal supports hyperlinks, with OSC 8 sequences.
+    ///
+    /// If the terminal does not support hyperlinks mdcat renders links as references after the
+    /// paragraph.
+    ///
+    /// ```
+    /// # use pulldown_cmark_mdcat::TerminalProgram;
+    /// assert!(TerminalProgram::Ansi.capabilities().supports_links());
+    /// assert!(!TerminalProgram::Dumb.capabilities().supports_links());
+    /// ```
+    pub fn supports_links(&self) -> bool {
+        matches!(self.style, Some(StyleCapability::Ansi))
+    }
+
     pub(crate) fn with_image_capability(mut self, cap: ImageCapability) -> Self {
         self.image = Some(cap);
         self
     }
 
+    /// Fall back to half block images if the terminal cannot show an image otherwise.
+    ///
+    /// If the terminal supports ANSI styles but no proper image protocol use
+    /// [`ImageCapability::HalfBlock`] to draw images from Unicode half blocks with 24-bit colours.
+    /// If the terminal has an image protocol, draw half blocks for all images which this protocol
+    /// fails to show, e.g. image formats which kitty cannot show without the `image-processing`
+    /// feature.
+    ///
+    /// The caller must make sure that the terminal supports 24-bit colours, see
+    /// [`halfblock::is_truecolor`].
+    pub fn with_half_block_image_fallback(self) -> Self {
+        let half_blocks = ImageCapability::HalfBlock(halfblock::HalfBlockProtocol);
+        match (self.style, self.image) {
+            (Some(StyleCapability::Ansi), None) => self.with_image_capability(half_blocks),
+            (Some(StyleCapability::Ansi), Some(_)) => TerminalCapabilities {
+                image_fallback: Some(half_blocks),
+                ..self
+            },
+            _ => self,
+        }
+    }
+
+    /// Use the given `placement` for images if the terminal supports the kitty graphics protocol.
+    ///
+    /// See [`kitty::KittyPlacement`].
+    pub fn with_kitty_placement(self, placement: kitty::KittyPlacement) -> Self {
+        match self.image {
+            Some(ImageCapability::Kitty(mut protocol)) => {
+                protocol.placement = placement;
+                self.with_image_capability(ImageCapability::Kitty(protocol))
+            }
+            _ => self,
+        }
+    }
+
+    /// Transfer images in chunks of `chunk_size` bytes if the terminal supports the kitty graphics
+    /// protocol.
+    ///
+    /// See [`kitty::KittyGraphicsProtocol::chunk_size`].
+    pub fn with_kitty_chunk_size(self, chunk_size: usize) -> Self {
+        match self.image {
+            Some(ImageCapability::Kitty(mut protocol)) => {
+                protocol.chunk_size = chunk_size;
+                self.with_image_capability(ImageCapability::Kitty(protocol))
+            }
+            _ => self,
+        }
+    }
+
+    /// Pass links, images, and marks through a terminal multiplexer with `passthrough`.
+    ///
+    /// This also applies to the image and mark capabilities of the terminal, so use this after
+    /// replacing these capabilities.
+    pub fn with_passthrough(self, passthrough: Passthrough) -> Self {
+        let image = match self.image {
+            Some(ImageCapability::ITerm2(mut protocol)) => {
+                protocol.passthrough = passthrough;
+                Some(ImageCapability::ITerm2(protocol))
+            }
+            Some(ImageCapability::Kitty(mut protocol)) => {
+                protocol.passthrough = passthrough;
+                Some(ImageCapability::Kitty(protocol))
+            }
+            image => image,
+        };
+        let marks = self.marks.map(|marks| match marks {
+            MarkCapability::ITerm2(mut protocol) => {
+                protocol.passthrough = passthrough;
+                MarkCapability::ITerm2(protocol)
+            }
+        });
+        TerminalCapabilities {
+            image,
+            marks,
+            passthrough,
+            ..self
+        }
+    }
+
     pub(crate) fn with_mark_capability(mut self, cap: MarkCapability) -> Self {
         self.marks = Some(cap);
         self
@@ -0,0 +1,467 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Images from Unicode half blocks.
+//!
+//! This module renders images as a grid of Unicode half blocks (`▀`), using 24-bit foreground and
+//! background colours for the upper and lower half of each cell.  This works on every terminal
+//! with true colour support, and serves as a fallback for terminals without a proper image protocol.
+
+use std::io::{Result, Write};
+
+use tracing::{event, instrument, Level};
+use url::Url;
+
+use crate::resources::InlineImageProtocol;
+use crate::{ResourceUrlHandler, TerminalSize};
+
+/// Whether the terminal advertises support for 24-bit colours.
+///
+/// Check whether `$COLORTERM` is either `truecolor` or `24bit`.
+pub fn is_truecolor() -> bool {
+    std::env::var("COLORTERM")
+        .map(|value| matches!(value.trim(), "truecolor" | "24bit"))
+        .unwrap_or(false)
+}
+
+/// Render images with Unicode half blocks and 24-bit colours.
+///
+/// Every terminal cell shows two vertically stacked pixels, by drawing the upper pixel as
+/// foreground colour of `▀` and the lower pixel as background colour.  Images are scaled down to
+/// fit into the available columns.
+///
+/// This protocol decodes PNG, JPEG, and GIF images itself, SVG images with the `svg` feature, and
+/// all other image formats with the `image-processing` feature.  It requires the `half-blocks`
+/// feature; without this feature it always fails with [`std::io::ErrorKind::Unsupported`].
+#[derive(Debug, Copy, Clone)]
+pub struct HalfBlockProtocol;
+
+#[cfg(feature = "half-blocks")]
+mod implementation {
+    use std::io::{Error, ErrorKind, Result, Write};
+
+    use anstyle::{RgbColor, Style};
+    use tracing::{event, Level};
+
+    use crate::resources::MimeData;
+
+    /// Pixels of an image, as RGBA values with 8 bits per channel.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Pixels {
+        pub width: u32,
+        pub height: u32,
+        pub rgba: Vec<u8>,
+    }
+
+    impl Pixels {
+        fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+            let index = (y as usize * self.width as usize + x as usize) * 4;
+            [
+                self.rgba[index],
+                self.rgba[index + 1],
+                self.rgba[index + 2],
+                self.rgba[index + 3],
+            ]
+        }
+
+        /// Scale these pixels down to at most `columns` pixels per row.
+        ///
+        /// Every column holds one pixel, and every line two pixels; since terminal cells are
+        /// roughly twice as high as wide this preserves the aspect ratio of the image.
+        ///
+        /// Average all source pixels which fall into a scaled pixel, weighted by their alpha, so
+        /// that transparent pixels do not darken the image.
+        pub fn scale_to_columns(self, columns: u32) -> Self {
+            let columns = columns.max(1);
+            if self.width <= columns || self.height == 0 {
+                return self;
+            }
+            let width = columns;
+            let height =
+                (u64::from(self.height) * u64::from(width) / u64::from(self.width)).max(1) as u32;
+            let span = |index: u32, scaled: u32, original: u32| {
+                let start = u64::from(index) * u64::from(original) / u64::from(scaled);
+                let end = u64::from(index + 1) * u64::from(original) / u64::from(scaled);
+                (start as u32, (end as u32).max(start as u32 + 1))
+            };
+            let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+            for y in 0..height {
+                let (top, bottom) = span(y, height, self.height);
+                for x in 0..width {
+                    let (left, right) = span(x, width, self.width);
+                    let mut colour = [0u64; 3];
+                    let mut alpha = 0u64;
+                    for source_y in top..bottom {
+                        for source_x in left..right {
+                            let [r, g, b, a] = self.pixel(source_x, source_y);
+                            colour[0] += u64::from(r) * u64::from(a);
+                            colour[1] += u64::from(g) * u64::from(a);
+                            colour[2] += u64::from(b) * u64::from(a);
+                            alpha += u64::from(a);
+                        }
+                    }
+                    let count = u64::from(bottom - top) * u64::from(right - left);
+                    let channel = |value: u64| value.checked_div(alpha).unwrap_or(0) as u8;
+                    rgba.extend_from_slice(&[
+                        channel(colour[0]),
+                        channel(colour[1]),
+                        channel(colour[2]),
+                        (alpha / count) as u8,
+                    ]);
+                }
+            }
+            Self {
+                width,
+                height,
+                rgba,
+            }
+        }
+    }
+
+    fn decode_png(data: &[u8]) -> Result<Pixels> {
+        let mut decoder = png::Decoder::new(data);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+        buffer.truncate(info.buffer_size());
+        let rgba = match info.color_type {
+            png::ColorType::Rgba => buffer,
+            png::ColorType::Rgb => buffer
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buffer
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => buffer.iter().flat_map(|g| [*g, *g, *g, 255]).collect(),
+            png::ColorType::Indexed => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Indexed PNG colours not expanded",
+                ))
+            }
+        };
+        Ok(Pixels {
+            width: info.width,
+            height: info.height,
+            rgba,
+        })
+    }
+
+    fn decode_jpeg(data: &[u8]) -> Result<Pixels> {
+        use zune_jpeg::zune_core::colorspace::ColorSpace;
+        use zune_jpeg::zune_core::options::DecoderOptions;
+
+        let options = DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::RGBA);
+        let mut decoder = zune_jpeg::JpegDecoder::new_with_options(data, options);
+        let rgba = decoder
+            .decode()
+            .map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+        let info = decoder
+            .info()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "JPEG image without headers"))?;
+        Ok(Pixels {
+            width: u32::from(info.width),
+            height: u32::from(info.height),
+            rgba,
+        })
+    }
+
+    /// Decode the first frame of a GIF image.
+    fn decode_gif(data: &[u8]) -> Result<Pixels> {
+        let invalid = |error: gif::DecodingError| Error::new(ErrorKind::InvalidData, error);
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(data).map_err(invalid)?;
+        let width = u32::from(decoder.width());
+        let height = u32::from(decoder.height());
+        let mut rgba = vec![0; width as usize * height as usize * 4];
+        if let Some(frame) = decoder.read_next_frame().map_err(invalid)? {
+            let left = usize::from(frame.left).min(width as usize);
+            let frame_width = usize::from(frame.width).min(width as usize - left);
+            let rows = frame
+                .buffer
+                .chunks_exact(usize::from(frame.width).max(1) * 4);
+            for (y, row) in (usize::from(frame.top)..height as usize).zip(rows) {
+                let start = (y * width as usize + left) * 4;
+                rgba[start..start + frame_width * 4].copy_from_slice(&row[..frame_width * 4]);
+            }
+        }
+        Ok(Pixels {
+            width,
+            height,
+            rgba,
+        })
+    }
+
+    #[cfg(feature = "image-processing")]
+    fn decode_other(mime_data: &MimeData) -> Result<Pixels> {
+        let image = match mime_data
+            .mime_type_essence()
+            .and_then(image::ImageFormat::from_mime_type)
+        {
+            Some(format) => image::load_from_memory_with_format(&mime_data.data, format),
+            None => image::load_from_memory(&mime_data.data),
+        }
+        .map_err(|error| Error::new(ErrorKind::InvalidData, error))?
+        .into_rgba8();
+        Ok(Pixels {
+            width: image.width(),
+            height: image.height(),
+            rgba: image.into_raw(),
+        })
+    }
+
+    #[cfg(not(feature = "image-processing"))]
+    fn decode_other(mime_data: &MimeData) -> Result<Pixels> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "Image data with mime type {:?} not supported without image-processing feature",
+                mime_data.mime_type
+            ),
+        ))
+    }
+
+    /// Decode `mime_data` into pixels, rendering SVG images to pixels first.
+    fn decode(mime_data: &MimeData) -> Result<Pixels> {
+        let data = mime_data.data.as_slice();
+        if let Some("image/svg+xml") = mime_data.mime_type_essence() {
+            decode_png(&crate::resources::svg::render_svg_to_png(data)?)
+        } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            decode_png(data)
+        } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+            decode_jpeg(data)
+        } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+            decode_gif(data)
+        } else {
+            decode_other(mime_data)
+        }
+    }
+
+    fn is_transparent(pixel: [u8; 4]) -> bool {
+        pixel[3] < 128
+    }
+
+    fn rgb(pixel: [u8; 4]) -> Option<anstyle::Color> {
+        Some(RgbColor(pixel[0], pixel[1], pixel[2]).into())
+    }
+
+    /// Write `pixels` as lines of half blocks to `writer`.
+    ///
+    /// Write the first line at the cursor, and every following line on a new line after `indent`.
+    /// Do not end the last line, to leave the cursor right after the image.
+    pub fn write_half_blocks(writer: &mut dyn Write, pixels: &Pixels, indent: u16) -> Result<()> {
+        let transparent = [0, 0, 0, 0];
+        for y in (0..pixels.height).step_by(2) {
+            if 0 < y {
+                write!(writer, "\n{}", " ".repeat(usize::from(indent)))?;
+            }
+            for x in 0..pixels.width {
+                let upper = pixels.pixel(x, y);
+                let lower = if y + 1 < pixels.height {
+                    pixels.pixel(x, y + 1)
+                } else {
+                    transparent
+                };
+                let (style, glyph) = match (is_transparent(upper), is_transparent(lower)) {
+                    (true, true) => (Style::new(), " "),
+                    (false, true) => (Style::new().fg_color(rgb(upper)), "\u{2580}"),
+                    (true, false) => (Style::new().fg_color(rgb(lower)), "\u{2584}"),
+                    (false, false) => (
+                        Style::new().fg_color(rgb(upper)).bg_color(rgb(lower)),
+                        "\u{2580}",
+                    ),
+                };
+                write!(writer, "{}{glyph}{}", style.render(), style.render_reset())?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_image(
+        writer: &mut dyn Write,
+        mime_data: MimeData,
+        columns: u16,
+        indent: u16,
+    ) -> Result<u16> {
+        let pixels = decode(&mime_data)?;
+        let (width, height) = (pixels.width, pixels.height);
+        let scaled = pixels.scale_to_columns(u32::from(columns));
+        event!(
+            Level::DEBUG,
+            "Writing image of size {}x{} as {}x{} half blocks",
+            width,
+            height,
+            scaled.width,
+            scaled.height.div_ceil(2)
+        );
+        write_half_blocks(writer, &scaled, indent)?;
+        // We scaled the image to at most `columns`, so its width fits into u16.
+        Ok(scaled.width as u16)
+    }
+}
+
+#[cfg(not(feature = "half-blocks"))]
+mod implementation {
+    use std::io::{Error, ErrorKind, Result, Write};
+
+    use crate::resources::MimeData;
+
+    pub fn write_image(
+        _writer: &mut dyn Write,
+        _mime_data: MimeData,
+        _columns: u16,
+        _indent: u16,
+    ) -> Result<u16> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "Half block images require the half-blocks feature",
+        ))
+    }
+}
+
+impl HalfBlockProtocol {
+    /// Write the image at `url` from half blocks, within the given number of `columns`.
+    ///
+    /// Write the first line of the image at the cursor, and indent every following line by
+    /// `indent`.  Leave the cursor right after the last line of the image, and return the width
+    /// of the image in columns.
+    #[instrument(skip(self, writer, resource_handler), fields(url = %url))]
+    pub fn write_image(
+        &self,
+        writer: &mut dyn Write,
+        resource_handler: &dyn ResourceUrlHandler,
+        url: &Url,
+        columns: u16,
+        indent: u16,
+    ) -> Result<u16> {
+        let mime_data = resource_handler.read_resource(url)?;
+        event!(
+            Level::DEBUG,
+            "Received data of mime type {:?}",
+            mime_data.mime_type
+        );
+        implementation::write_image(writer, mime_data, columns, indent)
+    }
+}
+
+impl InlineImageProtocol for HalfBlockProtocol {
+    fn write_inline_image(
+        &self,
+        writer: &mut dyn Write,
+        resource_handler: &dyn ResourceUrlHandler,
+        url: &Url,
+        terminal_size: TerminalSize,
+    ) -> Result<()> {
+        self.write_image(writer, resource_handler, url, terminal_size.columns, 0)?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "half-blocks"))]
+mod tests {
+    use similar_asserts::assert_eq;
+
+    use super::implementation::{write_half_blocks, write_image, Pixels};
+    use crate::resources::MimeData;
+
+    fn pixels(width: u32, height: u32, pixels: &[[u8; 4]]) -> Pixels {
+        Pixels {
+            width,
+            height,
+            rgba: pixels.concat(),
+        }
+    }
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+    const TRANSPARENT: [u8; 4] = [0, 0, 0, 0];
+
+    #[test]
+    fn write_half_blocks_uses_upper_and_lower_colours() {
+        let image = pixels(2, 3, &[RED, TRANSPARENT, RED, BLUE, RED, RED]);
+        let mut sink = Vec::new();
+        write_half_blocks(&mut sink, &image, 0).unwrap();
+        assert_eq!(
+            String::from_utf8(sink).unwrap(),
+            "\x1b[38;2;255;00;00m\x1b[48;2;255;00;00m\u{2580}\x1b[0m\
+\x1b[38;2;00;00;255m\u{2584}\x1b[0m\n\
+\x1b[38;2;255;00;00m\u{2580}\x1b[0m\
+\x1b[38;2;255;00;00m\u{2580}\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn write_half_blocks_indents_following_lines() {
+        let image = pixels(1, 4, &[RED, RED, BLUE, BLUE]);
+        let mut sink = Vec::new();
+        write_half_blocks(&mut sink, &image, 3).unwrap();
+        assert_eq!(
+            String::from_utf8(sink).unwrap(),
+            "\x1b[38;2;255;00;00m\x1b[48;2;255;00;00m\u{2580}\x1b[0m\n   \
+\x1b[38;2;00;00;255m\x1b[48;2;00;00;255m\u{2580}\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn scale_to_columns_averages_opaque_pixels() {
+        let image = pixels(4, 2, &[RED, TRANSPARENT, BLUE, BLUE, RED, RED, BLUE, BLUE]);
+        assert_eq!(
+            image.scale_to_columns(2),
+            pixels(2, 1, &[[255, 0, 0, 191], BLUE])
+        );
+    }
+
+    #[test]
+    fn scale_to_columns_keeps_small_images() {
+        let image = pixels(2, 1, &[RED, BLUE]);
+        assert_eq!(image.clone().scale_to_columns(80), image);
+    }
+
+    #[test]
+    fn write_png_image_within_columns() {
+        let mime_data = MimeData {
+            mime_type: Some(mime::IMAGE_PNG),
+            data: include_bytes!("../../../../sample/rust-logo-128x128.png").to_vec(),
+        };
+        let mut sink = Vec::new();
+        let width = write_image(&mut sink, mime_data, 16, 2).unwrap();
+        assert_eq!(width, 16);
+        let output = String::from_utf8(sink).unwrap();
+        // The image is square, so 16 columns make 8 lines of half blocks.
+        assert_eq!(output.lines().count(), 8);
+        assert!(output.lines().skip(1).all(|line| line.starts_with("  ")));
+        assert!(!output.ends_with('\n'));
+    }
+
+    #[test]
+    fn write_gif_image() {
+        let mut data = Vec::new();
+        let mut encoder = gif::Encoder::new(&mut data, 2, 1, &[255, 0, 0, 0, 0, 255]).unwrap();
+        encoder
+            .write_frame(&gif::Frame {
+                width: 2,
+                height: 1,
+                buffer: vec![0, 1].into(),
+                ..gif::Frame::default()
+            })
+            .unwrap();
+        drop(encoder);
+        let mime_data = MimeData {
+            mime_type: None,
+            data,
+        };
+        let mut sink = Vec::new();
+        assert_eq!(write_image(&mut sink, mime_data, 80, 0).unwrap(), 2);
+        assert_eq!(
+            String::from_utf8(sink).unwrap(),
+            "\x1b[38;2;255;00;00m\u{2580}\x1b[0m\x1b[38;2;00;00;255m\u{2580}\x1b[0m"
+        );
+    }
+}
@@ -16,17 +16,41 @@ use base64::Engine;
 use tracing::{event, instrument, Level};
 
 use crate::resources::{svg, InlineImageProtocol};
+use crate::terminal::capabilities::Passthrough;
 use crate::terminal::osc::write_osc;
 use crate::ResourceUrlHandler;
 
 /// Iterm2 terminal protocols.
-#[derive(Debug, Copy, Clone)]
-pub struct ITerm2Protocol;
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ITerm2Protocol {
+    /// How to pass marks and images on to the terminal.
+    pub passthrough: Passthrough,
+}
 
 impl ITerm2Protocol {
     /// Write an iterm2 mark command to the given `writer`.
     pub fn set_mark<W: Write>(self, writer: &mut W) -> io::Result<()> {
-        write_osc(writer, "1337;SetMark")
+        write_osc(writer, self.passthrough, "1337;SetMark")
+    }
+}
+
+/// The file name to tell iTerm2 for an image from `url`.
+///
+/// Take the last segment of the URL, without control characters and path separators.  Fall back
+/// to `image` if the URL has no path segments, e.g. `data:` URLs, or an empty last segment, e.g.
+/// `https://example.com/`.
+fn file_name(url: &url::Url) -> String {
+    let name = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !(c.is_control() || matches!(c, '/' | '\\')))
+        .collect::<String>();
+    if name.is_empty() {
+        "image".to_owned()
+    } else {
+        name
     }
 }
 
@@ -55,21 +79,11 @@ impl InlineImageProtocol for ITerm2Protocol {
             mime_data.mime_type
         );
 
-        // Determine the local file name to use, by taking the last segment of the URL.
-        // If the URL has no last segment do not tell iterm about a file name.
-        let name = url
-            .path_segments()
-            .and_then(|s| s.last())
-            .map(Cow::Borrowed);
+        let name = file_name(url);
         let (name, contents) = if let Some("image/svg+xml") = mime_data.mime_type_essence() {
             event!(Level::DEBUG, "Rendering SVG from {}", url);
             (
-                name.map(|n| {
-                    let mut name = String::new();
-                    name.push_str(&n);
-                    name.push_str(".png");
-                    Cow::Owned(name)
-                }),
+                format!("{name}.png"),
                 Cow::Owned(svg::render_svg_to_png(&mime_data.data)?),
             )
         } else {
@@ -79,17 +93,45 @@ impl InlineImageProtocol for ITerm2Protocol {
         let data = STANDARD.encode(contents.as_ref());
         write_osc(
             writer,
-            &name.map_or_else(
-                || format!("1337;File=size={};inline=1:{}", contents.len(), data),
-                |name| {
-                    format!(
-                        "1337;File=name={};size={};inline=1:{}",
-                        STANDARD.encode(name.as_bytes()),
-                        contents.len(),
-                        data
-                    )
-                },
+            self.passthrough,
+            &format!(
+                "1337;File=name={};size={};inline=1:{}",
+                STANDARD.encode(name.as_bytes()),
+                contents.len(),
+                data
             ),
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::file_name;
+
+    fn name_of(url: &str) -> String {
+        file_name(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn file_name_from_last_segment() {
+        assert_eq!(name_of("https://example.com/images/logo.png"), "logo.png");
+        assert_eq!(name_of("file:///home/user/logo.png"), "logo.png");
+    }
+
+    #[test]
+    fn file_name_without_path_segment() {
+        assert_eq!(name_of("https://example.com"), "image");
+        assert_eq!(name_of("https://example.com/"), "image");
+        assert_eq!(name_of("https://example.com/images/"), "image");
+        assert_eq!(name_of("data:image/png;base64,iVBORw0KGgo="), "image");
+    }
+
+    #[test]
+    fn sanitize_file_name() {
+        // Unlike special schemes like HTTP, other schemes keep backslashes in paths
+        assert_eq!(name_of("x-image:/images/a\\b.png"), "ab.png");
+        assert_eq!(name_of("x-image:/images/\\"), "image");
+    }
+}
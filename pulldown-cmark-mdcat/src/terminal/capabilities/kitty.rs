@@ -15,8 +15,9 @@
 
 //! Kitty terminal extensions.
 use std::fmt::Display;
-use std::io::{Error, ErrorKind, Write};
+use std::io::{Error, Write};
 use std::str;
+use std::sync::atomic::{AtomicU32, Ordering};
 
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
@@ -24,6 +25,7 @@ use tracing::{event, instrument, Level};
 
 use crate::resources::image::*;
 use crate::resources::MimeData;
+use crate::terminal::capabilities::Passthrough;
 use crate::terminal::size::{PixelSize, TerminalSize};
 
 /// An error which occurred while rendering or writing an image with the Kitty image protocol.
@@ -60,7 +62,7 @@ impl std::error::Error for KittyImageError {
 
 impl From<KittyImageError> for std::io::Error {
     fn from(value: KittyImageError) -> Self {
-        std::io::Error::new(ErrorKind::Other, value)
+        std::io::Error::other(value)
     }
 }
 
@@ -77,6 +79,81 @@ impl From<image::ImageError> for KittyImageError {
     }
 }
 
+/// Read the size of a PNG image from its `IHDR` chunk.
+fn png_size(data: &[u8]) -> Option<PixelSize> {
+    let header = data.get(..24)?;
+    if &header[..8] != b"\x89PNG\r\n\x1a\n" || &header[12..16] != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(header[16..20].try_into().ok()?);
+    let height = u32::from_be_bytes(header[20..24].try_into().ok()?);
+    Some(PixelSize::from_xy((width, height)))
+}
+
+/// The placeholder character for Unicode placeholder placement.
+const PLACEHOLDER: char = '\u{10EEEE}';
+
+/// Diacritics which encode row and column indexes of placeholder cells.
+///
+/// This is a prefix of the list of diacritics defined by kitty, see [Unicode placeholders][1].  We
+/// only need row diacritics, so this limits the number of rows of images with placeholder
+/// placement.
+///
+/// [1]: https://sw.kovidgoyal.net/kitty/graphics-protocol/#unicode-placeholders
+const DIACRITICS: [char; 69] = [
+    '\u{0305}', '\u{030D}', '\u{030E}', '\u{0310}', '\u{0312}', '\u{033D}', '\u{033E}', '\u{033F}',
+    '\u{0346}', '\u{034A}', '\u{034B}', '\u{034C}', '\u{0350}', '\u{0351}', '\u{0352}', '\u{0357}',
+    '\u{035B}', '\u{0363}', '\u{0364}', '\u{0365}', '\u{0366}', '\u{0367}', '\u{0368}', '\u{0369}',
+    '\u{036A}', '\u{036B}', '\u{036C}', '\u{036D}', '\u{036E}', '\u{036F}', '\u{0483}', '\u{0484}',
+    '\u{0485}', '\u{0486}', '\u{0487}', '\u{0592}', '\u{0593}', '\u{0594}', '\u{0595}', '\u{0597}',
+    '\u{0598}', '\u{0599}', '\u{059C}', '\u{059D}', '\u{059E}', '\u{059F}', '\u{05A0}', '\u{05A1}',
+    '\u{05A8}', '\u{05A9}', '\u{05AB}', '\u{05AC}', '\u{05AF}', '\u{05C4}', '\u{0610}', '\u{0611}',
+    '\u{0612}', '\u{0613}', '\u{0614}', '\u{0615}', '\u{0616}', '\u{0617}', '\u{0657}', '\u{0658}',
+    '\u{0659}', '\u{065A}', '\u{065B}', '\u{065D}', '\u{065E}',
+];
+
+/// Get a new ID for an image with Unicode placeholder placement.
+///
+/// Placeholders refer to images by ID, so IDs must not clash with images of other programs in the
+/// same terminal; we start from an offset derived from the process ID.  IDs have 24 bits, because
+/// placeholders encode the image ID in their 24-bit foreground colour.
+fn next_image_id() -> u32 {
+    static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+    let offset = std::process::id().wrapping_mul(2_654_435_761);
+    let id = offset.wrapping_add(NEXT_ID.fetch_add(1, Ordering::Relaxed)) & 0xff_ffff;
+    // Zero is not a valid image ID
+    id.max(1)
+}
+
+/// Write Unicode placeholders for the image with the given `id` and size in cells.
+///
+/// Each row starts with a placeholder with row and column diacritics; kitty infers the row and
+/// column of the following placeholders in the same row.  The foreground colour of each placeholder
+/// encodes the image ID.
+fn write_placeholders(
+    writer: &mut dyn Write,
+    id: u32,
+    columns: u16,
+    rows: u16,
+) -> Result<(), Error> {
+    let [_, r, g, b] = id.to_be_bytes();
+    for (row, diacritic) in DIACRITICS.iter().take(usize::from(rows)).enumerate() {
+        if 0 < row {
+            writeln!(writer)?;
+        }
+        write!(
+            writer,
+            "\x1b[38;2;{r};{g};{b}m{PLACEHOLDER}{diacritic}{}",
+            DIACRITICS[0]
+        )?;
+        for _ in 1..columns {
+            write!(writer, "{PLACEHOLDER}")?;
+        }
+        write!(writer, "\x1b[39m")?;
+    }
+    Ok(())
+}
+
 /// Image data for the kitty graphics protocol.
 ///
 /// See [Terminal graphics protocol][1] for a complete documentation.
@@ -131,6 +208,37 @@ impl KittyImageData {
         }
     }
 
+    /// Get the size of the image in pixels, if known.
+    ///
+    /// Unlike [`Self::size`] this also reads the size from the header of PNG data.
+    fn pixel_size(&self) -> Option<PixelSize> {
+        match self {
+            KittyImageData::Png(contents) => png_size(contents),
+            #[cfg(feature = "image-processing")]
+            KittyImageData::Rgb(size, _) | KittyImageData::Rgba(size, _) => Some(*size),
+        }
+    }
+
+    /// Get the number of columns and rows this image covers on a terminal of the given size.
+    ///
+    /// Scale images down to fit into the columns of the terminal, but never scale them up.
+    ///
+    /// Return `None` if the size of the image or the cell size of the terminal is unknown.
+    fn size_in_cells(&self, terminal_size: TerminalSize) -> Option<(u16, u16)> {
+        let size = self.pixel_size()?;
+        let cell = terminal_size.cell?;
+        if size.x == 0 || cell.x == 0 || cell.y == 0 {
+            return None;
+        }
+        let image_columns = f64::from(size.x) / f64::from(cell.x);
+        let scale = (f64::from(terminal_size.columns) / image_columns).min(1.0);
+        let rows = f64::from(size.y) * scale / f64::from(cell.y);
+        Some((
+            ((image_columns * scale).ceil() as u16).max(1),
+            (rows.ceil() as u16).max(1),
+        ))
+    }
+
     /// The width of the image for the `s` control data field.
     fn s_width(&self) -> u32 {
         self.size().map_or(0, |s| s.x)
@@ -143,12 +251,27 @@ impl KittyImageData {
 }
 
 impl KittyImageData {
-    fn write_to(&self, writer: &mut dyn Write) -> Result<(), Error> {
+    /// Write this image to `writer`.
+    ///
+    /// `placement` contains the control data fields which tell kitty how to place the image.
+    /// Split the data into chunks of `chunk_size` bytes; all chunks except the last must have a
+    /// size which is a multiple of 4, so we round `chunk_size` down accordingly.
+    ///
+    /// Write every chunk as separate sequence with the given `passthrough`.
+    fn write_to(
+        &self,
+        writer: &mut dyn Write,
+        placement: &str,
+        chunk_size: usize,
+        passthrough: Passthrough,
+    ) -> Result<(), Error> {
         let image_data = STANDARD.encode(self.data());
-        let image_data_chunks = image_data.as_bytes().chunks(4096);
+        let image_data_chunks = image_data.as_bytes().chunks((chunk_size / 4).max(1) * 4);
         let number_of_chunks = image_data_chunks.len();
 
+        let mut sequence = Vec::new();
         for (i, chunk_data) in image_data_chunks.enumerate() {
+            sequence.clear();
             let is_first_chunk = i == 0;
             // The value for the m field
             let m = if i < number_of_chunks - 1 { 1 } else { 0 };
@@ -160,9 +283,7 @@ impl KittyImageData {
                 //
                 // t=d tells kitty that we transfer image data inline in the escape code.
                 //
-                // I=1 tells kitty that we want to treat every image as unique and not have kitty
-                // reuse images.  At least wezterm requires this; otherwise past images disappear
-                // because wezterm seems to assume that we're reusing some image ID.
+                // placement tells kitty how to place the image, see KittyPlacement.
                 //
                 // f tells kitty about the data format.
                 //
@@ -176,23 +297,73 @@ impl KittyImageData {
                 let f = self.f_format_code();
                 let s = self.s_width();
                 let v = self.v_height();
-                write!(writer, "\x1b_Ga=T,t=d,I=1,f={f},s={s},v={v},m={m},q=2;")?;
+                write!(
+                    sequence,
+                    "\x1b_Ga=T,t=d,{placement},f={f},s={s},v={v},m={m},q=2;"
+                )?;
             } else {
                 // For follow up chunks we must not repeat the header, but only indicate whether we
                 // expect a response and whether more data is to follow.
-                write!(writer, "\x1b_Gm={m},q=2;")?;
+                write!(sequence, "\x1b_Gm={m},q=2;")?;
             }
-            writer.write_all(chunk_data)?;
-            write!(writer, "\x1b\\")?;
+            sequence.extend_from_slice(chunk_data);
+            sequence.extend_from_slice(b"\x1b\\");
+            passthrough.write_sequence(writer, &sequence)?;
         }
 
         Ok(())
     }
 }
 
+/// How to place images with the kitty graphics protocol.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum KittyPlacement {
+    /// Show images directly at the cursor position.
+    #[default]
+    Direct,
+    /// Place images with Unicode placeholder characters.
+    ///
+    /// Create a virtual placement for each image, and write placeholder characters which the
+    /// terminal replaces with the image.  The image then behaves like text: It scrolls with text
+    /// and survives reflowing text, e.g. when the terminal is resized.
+    ///
+    /// This placement requires the cell size of the terminal.  If the terminal does not report its
+    /// cell size, or if the image is too tall, fall back to direct placement.
+    UnicodePlaceholders,
+}
+
+/// The default size of chunks of image data, see [`KittyGraphicsProtocol::chunk_size`].
+pub const DEFAULT_CHUNK_SIZE: usize = 4096;
+
 /// Provides access to printing images for kitty.
 #[derive(Debug, Copy, Clone)]
-pub struct KittyGraphicsProtocol;
+pub struct KittyGraphicsProtocol {
+    /// How to place images.
+    pub placement: KittyPlacement,
+    /// The size of the chunks to transfer base64-encoded image data in, in bytes.
+    ///
+    /// The graphics protocol transfers larger images in multiple escape sequences.  Smaller chunks
+    /// help terminal multiplexers which limit the size of escape sequences they pass through, e.g.
+    /// tmux; larger chunks need fewer escape sequences and may transfer images faster to terminals
+    /// which accept them.  kitty itself accepts at most 4096 bytes per chunk, which is the
+    /// default.
+    ///
+    /// mdcat rounds the size down to a multiple of 4, as required by the protocol.
+    pub chunk_size: usize,
+    /// How to pass images on to the terminal.
+    pub passthrough: Passthrough,
+}
+
+impl Default for KittyGraphicsProtocol {
+    /// Direct placement in chunks of [`DEFAULT_CHUNK_SIZE`], without passthrough.
+    fn default() -> Self {
+        Self {
+            placement: KittyPlacement::default(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            passthrough: Passthrough::Direct,
+        }
+    }
+}
 
 impl KittyGraphicsProtocol {
     /// Render mime data obtained from `url` and wrap it in a `KittyImage`.
@@ -265,7 +436,7 @@ impl KittyGraphicsProtocol {
                     mime_data.mime_type
                 );
                 Err(std::io::Error::new(
-                    ErrorKind::Unsupported,
+                    std::io::ErrorKind::Unsupported,
                     format!(
                         "Image data with mime type {:?} not supported",
                         mime_data.mime_type
@@ -306,7 +477,8 @@ impl KittyGraphicsProtocol {
 /// Kitty's inline image protocol.
 ///
 /// Kitty's escape sequence is like: Put the command key/value pairs together like "{}={}(,*)"
-/// and write them along with the image bytes in 4096 bytes chunks to the stdout.
+/// and write them along with the image bytes in 4096 bytes chunks to the stdout; mdcat uses
+/// [`KittyGraphicsProtocol::chunk_size`] instead.
 ///
 /// Its documentation gives the following python example:
 ///
@@ -355,6 +527,167 @@ impl InlineImageProtocol for KittyGraphicsProtocol {
             mime_data.mime_type
         );
         let image = self.render(mime_data, terminal_size)?;
-        image.write_to(writer)
+        let cells = image.size_in_cells(terminal_size);
+        let placeholder_cells = match self.placement {
+            KittyPlacement::Direct => None,
+            KittyPlacement::UnicodePlaceholders => {
+                let placeholder_cells =
+                    cells.filter(|(_, rows)| usize::from(*rows) <= DIACRITICS.len());
+                if placeholder_cells.is_none() {
+                    event!(
+                        Level::DEBUG,
+                        "Cannot place image with placeholders, falling back to direct placement"
+                    );
+                }
+                placeholder_cells
+            }
+        };
+        match (placeholder_cells, cells) {
+            (Some((columns, rows)), _) => {
+                let id = next_image_id();
+                // U=1 creates a virtual placement of the given columns and rows for the image ID,
+                // which kitty then shows in place of the placeholders with the same ID.
+                image.write_to(
+                    writer,
+                    &format!("U=1,i={id},c={columns},r={rows}"),
+                    self.chunk_size,
+                    self.passthrough,
+                )?;
+                write_placeholders(writer, id, columns, rows)
+            }
+            // I=1 tells kitty that we want to treat every image as unique and not have kitty reuse
+            // images.  At least wezterm requires this; otherwise past images disappear because
+            // wezterm seems to assume that we're reusing some image ID.
+            //
+            // c and r tell kitty how many columns and rows the image covers, so that the cursor
+            // ends up right after the image and following text does not overlap the image.
+            (None, Some((columns, rows))) => image.write_to(
+                writer,
+                &format!("I=1,c={columns},r={rows}"),
+                self.chunk_size,
+                self.passthrough,
+            ),
+            // Without cell size let kitty compute the size of the image from its pixels.
+            (None, None) => image.write_to(writer, "I=1", self.chunk_size, self.passthrough),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use similar_asserts::assert_eq;
+
+    use super::*;
+    use crate::resources::FileResourceHandler;
+
+    fn write_sample_image(placement: KittyPlacement, cell: Option<PixelSize>) -> String {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/markdown/samples/image.png");
+        let url = url::Url::from_file_path(&path).unwrap();
+        let terminal_size = TerminalSize {
+            cell,
+            ..TerminalSize::default()
+        };
+        let mut sink = Vec::new();
+        KittyGraphicsProtocol {
+            placement,
+            ..KittyGraphicsProtocol::default()
+        }
+        .write_inline_image(
+            &mut sink,
+            &FileResourceHandler::new(1_000_000),
+            &url,
+            terminal_size,
+        )
+        .unwrap();
+        String::from_utf8(sink).unwrap()
+    }
+
+    #[test]
+    fn png_size_from_header() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/markdown/samples/image.png");
+        let data = std::fs::read(path).unwrap();
+        assert_eq!(png_size(&data), Some(PixelSize::from_xy((40, 20))));
+        assert_eq!(png_size(b"GIF89a"), None);
+    }
+
+    #[test]
+    fn write_in_chunks() {
+        // 3000 bytes encode to 4000 bytes of base64
+        let image = KittyImageData::Png(vec![0; 3000]);
+        let count_chunks = |chunk_size| {
+            let mut sink = Vec::new();
+            image
+                .write_to(&mut sink, "I=1", chunk_size, Passthrough::Direct)
+                .unwrap();
+            let output = String::from_utf8(sink).unwrap();
+            // Only the last chunk tells kitty that there's no more data
+            assert_eq!(output.matches("m=0,").count(), 1, "{output:?}");
+            output.matches("\x1b_G").count()
+        };
+        assert_eq!(count_chunks(DEFAULT_CHUNK_SIZE), 1);
+        assert_eq!(count_chunks(1000), 4);
+        assert_eq!(count_chunks(999), 5);
+        assert_eq!(count_chunks(3999), 2);
+        assert_eq!(count_chunks(0), 1000);
+    }
+
+    #[test]
+    fn write_placeholders_with_row_diacritics() {
+        let mut sink = Vec::new();
+        write_placeholders(&mut sink, 0x01_02_03, 2, 2).unwrap();
+        assert_eq!(
+            String::from_utf8(sink).unwrap(),
+            "\x1b[38;2;1;2;3m\u{10EEEE}\u{0305}\u{0305}\u{10EEEE}\x1b[39m\n\
+             \x1b[38;2;1;2;3m\u{10EEEE}\u{030D}\u{0305}\u{10EEEE}\x1b[39m"
+        );
+    }
+
+    #[test]
+    fn direct_placement_by_default() {
+        let output = write_sample_image(
+            KittyPlacement::default(),
+            Some(PixelSize::from_xy((10, 20))),
+        );
+        assert!(
+            output.starts_with("\x1b_Ga=T,t=d,I=1,c=4,r=1,f=100,"),
+            "{output:?}"
+        );
+        assert!(!output.contains(PLACEHOLDER), "{output:?}");
+    }
+
+    #[test]
+    fn direct_placement_scales_large_image_to_columns() {
+        let output = write_sample_image(KittyPlacement::Direct, Some(PixelSize::from_xy((1, 2))));
+        // 40x20 pixels on 80 columns of 1x2 pixels
+        assert!(output.contains(",c=40,r=10,"), "{output:?}");
+    }
+
+    #[test]
+    fn direct_placement_without_cell_size_omits_cells() {
+        let output = write_sample_image(KittyPlacement::Direct, None);
+        assert!(output.starts_with("\x1b_Ga=T,t=d,I=1,f=100,"), "{output:?}");
+    }
+
+    #[test]
+    fn unicode_placeholder_placement() {
+        let output = write_sample_image(
+            KittyPlacement::UnicodePlaceholders,
+            Some(PixelSize::from_xy((10, 20))),
+        );
+        assert!(output.starts_with("\x1b_Ga=T,t=d,U=1,i="), "{output:?}");
+        assert!(output.contains(",c=4,r=1,f=100,"), "{output:?}");
+        assert!(
+            output.ends_with("m\u{10EEEE}\u{0305}\u{0305}\u{10EEEE}\u{10EEEE}\u{10EEEE}\x1b[39m"),
+            "{output:?}"
+        );
+    }
+
+    #[test]
+    fn unicode_placeholder_placement_falls_back_without_cell_size() {
+        let output = write_sample_image(KittyPlacement::UnicodePlaceholders, None);
+        assert!(output.starts_with("\x1b_Ga=T,t=d,I=1,f=100,"), "{output:?}");
+        assert!(!output.contains(PLACEHOLDER), "{output:?}");
     }
 }
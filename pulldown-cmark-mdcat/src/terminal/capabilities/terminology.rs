@@ -66,6 +66,32 @@ fn get_image_dimensions(_url: &Url) -> Option<(u32, u32)> {
     None
 }
 
+/// Compute the size in cells of an image with the given pixel `dimensions`.
+///
+/// If the terminal reports its cell size, scale images down to fit into the columns of the
+/// terminal, but do not scale smaller images up, like for other terminals.  Otherwise draw the
+/// image across all columns, assuming that cells are twice as high as wide.
+///
+/// Without image dimensions cover all columns and half the rows of the terminal.
+///
+/// Return a pair of columns and lines.
+fn image_size_in_cells(dimensions: Option<(u32, u32)>, terminal_size: TerminalSize) -> (u16, u16) {
+    let columns = terminal_size.columns;
+    match (dimensions, terminal_size.cell) {
+        (Some((w, h)), Some(cell)) if 0 < w && 0 < cell.x && 0 < cell.y => {
+            let image_columns = f64::from(w) / f64::from(cell.x);
+            let scale = (f64::from(columns) / image_columns).min(1.0);
+            let lines = f64::from(h) * scale / f64::from(cell.y);
+            ((image_columns * scale).ceil() as u16, lines.ceil() as u16)
+        }
+        (Some((w, h)), _) if 0 < w => (
+            columns,
+            (f64::from(h) * f64::from(columns / 2) / f64::from(w)) as u16,
+        ),
+        _ => (columns, terminal_size.rows / 2),
+    }
+}
+
 /// The terminology image protocol
 ///
 /// Terminology escape sequences work like this: Set texture to path, then draw a rectangle of a
@@ -82,7 +108,8 @@ fn get_image_dimensions(_url: &Url) -> Option<(u32, u32)> {
 ///
 /// To determine the optimal size this implementation attempts to determine the image dimensions:
 /// If the URL refers to a local path it'll read the image header from the path and extracts the
-/// size information.
+/// size information.  Like for other terminals, it then scales oversized images down to the
+/// columns of the terminal.
 ///
 /// For remote URLs the implementation falls back to a rectangle covering half of the screen, and
 /// does not attempt to determine more precise dimensions, to avoid downloading the resource twice
@@ -95,11 +122,11 @@ impl InlineImageProtocol for Terminology {
         url: &Url,
         terminal_size: TerminalSize,
     ) -> Result<()> {
-        let columns = terminal_size.columns;
-        let lines = match get_image_dimensions(url) {
-            Some((w, h)) => ((h as f64) * (columns / 2) as f64 / (w as f64)) as usize,
-            None => terminal_size.rows as usize / 2,
-        };
+        let (columns, lines) = image_size_in_cells(get_image_dimensions(url), terminal_size);
+        event!(
+            Level::DEBUG,
+            "Drawing image across {columns} columns and {lines} lines"
+        );
 
         let mut command = format!("\x1b}}ic#{};{};{}\x00", columns, lines, url.as_str());
         for _ in 0..lines {
@@ -113,3 +140,67 @@ impl InlineImageProtocol for Terminology {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use similar_asserts::assert_eq;
+
+    use super::image_size_in_cells;
+    use crate::terminal::size::PixelSize;
+    use crate::TerminalSize;
+
+    fn terminal_size(cell: Option<PixelSize>) -> TerminalSize {
+        let size = TerminalSize::new(80, 24);
+        cell.map_or(size, |cell| size.with_cell_size(cell))
+    }
+
+    #[test]
+    fn small_image_keeps_its_size() {
+        let size = terminal_size(Some(PixelSize::from_xy((8, 16))));
+        assert_eq!(image_size_in_cells(Some((128, 128)), size), (16, 8));
+    }
+
+    #[test]
+    fn large_image_scaled_down_to_columns() {
+        let size = terminal_size(Some(PixelSize::from_xy((10, 20))));
+        assert_eq!(image_size_in_cells(Some((1600, 800)), size), (80, 20));
+    }
+
+    #[test]
+    fn image_across_all_columns_without_cell_size() {
+        assert_eq!(
+            image_size_in_cells(Some((40, 20)), terminal_size(None)),
+            (80, 20)
+        );
+    }
+
+    #[test]
+    fn half_screen_without_image_dimensions() {
+        let size = terminal_size(Some(PixelSize::from_xy((10, 20))));
+        assert_eq!(image_size_in_cells(None, size), (80, 12));
+    }
+
+    #[cfg(feature = "image-processing")]
+    #[test]
+    fn write_inline_image_with_size_of_local_image() {
+        use crate::resources::{InlineImageProtocol, NoopResourceHandler};
+
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/markdown/samples/image.png");
+        let url = url::Url::from_file_path(&path).unwrap();
+        let mut sink = Vec::new();
+        super::Terminology
+            .write_inline_image(
+                &mut sink,
+                &NoopResourceHandler,
+                &url,
+                terminal_size(Some(PixelSize::from_xy((8, 16)))),
+            )
+            .unwrap();
+        let expected_line = "\x1b}ib\x00#####\x1b}ie\x00\n";
+        assert_eq!(
+            String::from_utf8(sink).unwrap(),
+            format!("\x1b}}ic#5;2;{url}\x00{expected_line}{expected_line}")
+        );
+    }
+}
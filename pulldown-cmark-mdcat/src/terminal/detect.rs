@@ -50,6 +50,18 @@ pub enum TerminalProgram {
     ///
     /// See <https://mitchellh.com/ghostty> for more information.
     Ghostty,
+    /// Konsole, the terminal emulator of KDE.
+    ///
+    /// Since version 22.04 it supports images with the iTerm2 protocol.
+    ///
+    /// See <https://konsole.kde.org> for more information.
+    Konsole,
+    /// foot, a Wayland terminal emulator.
+    ///
+    /// foot supports hyperlinks, but mdcat does not support its image protocol (sixel).
+    ///
+    /// See <https://codeberg.org/dnkl/foot> for more information.
+    Foot,
 }
 
 impl Display for TerminalProgram {
@@ -63,6 +75,8 @@ impl Display for TerminalProgram {
             TerminalProgram::WezTerm => "WezTerm",
             TerminalProgram::VSCode => "vscode",
             TerminalProgram::Ghostty => "ghostty",
+            TerminalProgram::Konsole => "Konsole",
+            TerminalProgram::Foot => "foot",
         };
         write!(f, "{name}")
     }
@@ -81,26 +95,64 @@ fn get_term_program_major_minor_version() -> Option<(u16, u16)> {
 }
 
 impl TerminalProgram {
-    fn detect_term() -> Option<Self> {
-        match std::env::var("TERM").ok().as_deref() {
-            Some("wezterm") => Some(Self::WezTerm),
-            Some("xterm-kitty") => Some(Self::Kitty),
-            Some("xterm-ghostty") => Some(Self::Ghostty),
-            _ => None,
-        }
+    /// All terminal programs known to mdcat.
+    ///
+    /// Together with [`TerminalProgram::capabilities`] this allows to enumerate the capabilities
+    /// of all supported terminals, e.g. to build a compatibility matrix.
+    pub fn all() -> &'static [TerminalProgram] {
+        &[
+            TerminalProgram::Dumb,
+            TerminalProgram::Ansi,
+            TerminalProgram::ITerm2,
+            TerminalProgram::Terminology,
+            TerminalProgram::Kitty,
+            TerminalProgram::WezTerm,
+            TerminalProgram::VSCode,
+            TerminalProgram::Ghostty,
+            TerminalProgram::Konsole,
+            TerminalProgram::Foot,
+        ]
     }
 
-    fn detect_term_program() -> Option<Self> {
-        match std::env::var("TERM_PROGRAM").ok().as_deref() {
-            Some("WezTerm") => Some(Self::WezTerm),
-            Some("iTerm.app") => Some(Self::ITerm2),
-            Some("ghostty") => Some(Self::Ghostty),
-            Some("vscode")
+    fn detect_term() -> Option<(Self, &'static str, String)> {
+        let value = std::env::var("TERM").ok()?;
+        let terminal = match value.as_str() {
+            "wezterm" => Self::WezTerm,
+            "xterm-kitty" => Self::Kitty,
+            "xterm-ghostty" => Self::Ghostty,
+            "foot" | "foot-extra" => Self::Foot,
+            _ => return None,
+        };
+        Some((terminal, "TERM", value))
+    }
+
+    fn detect_term_program() -> Option<(Self, &'static str, String)> {
+        let value = std::env::var("TERM_PROGRAM").ok()?;
+        let terminal = match value.as_str() {
+            "WezTerm" => Self::WezTerm,
+            "iTerm.app" => Self::ITerm2,
+            "ghostty" => Self::Ghostty,
+            "vscode"
                 if get_term_program_major_minor_version()
-                    .map_or(false, |version| (1, 80) <= version) =>
+                    .is_some_and(|version| (1, 80) <= version) =>
             {
-                Some(Self::VSCode)
+                Self::VSCode
             }
+            _ => return None,
+        };
+        Some((terminal, "TERM_PROGRAM", value))
+    }
+
+    fn detect_konsole() -> Option<(Self, &'static str, String)> {
+        // Konsole encodes its version as a number of the form YYMMPP, e.g. 220401 for 22.04.1
+        let value = std::env::var("KONSOLE_VERSION").ok()?;
+        let version: u32 = value.parse().ok()?;
+        (220400 <= version).then_some((Self::Konsole, "KONSOLE_VERSION", value))
+    }
+
+    fn detect_terminology() -> Option<(Self, &'static str, String)> {
+        match std::env::var("TERMINOLOGY").ok().as_deref() {
+            Some("1") => Some((Self::Terminology, "TERMINOLOGY", "1".into())),
             _ => None,
         }
     }
@@ -109,8 +161,8 @@ impl TerminalProgram {
     ///
     /// This function looks at various environment variables to identify the terminal program.
     ///
-    /// It first looks at `$TERM` to determine the terminal program, then at `$TERM_PROGRAM`, and
-    /// finally at `$TERMINOLOGY`.
+    /// It first looks at `$TERM` to determine the terminal program, then at `$TERM_PROGRAM`, then at
+    /// `$KONSOLE_VERSION`, and finally at `$TERMINOLOGY`.
     ///
     /// If `$TERM` is set to anything other than `xterm-256colors` it's definitely accurate, since
     /// it points to the terminfo entry to use.  `$TERM` also propagates across most boundaries
@@ -129,17 +181,31 @@ impl TerminalProgram {
     /// - [`TerminalProgram::WezTerm`] if `$TERM_PROGRAM` is `WezTerm`.
     /// - [`TerminalProgram::ITerm2`] if `$TERM_PROGRAM` is `iTerm.app`.
     /// - [`TerminalProgram::Ghostty`] if `$TERM` is `xterm-ghostty`.
+    /// - [`TerminalProgram::Foot`] if `$TERM` is `foot` or `foot-extra`.
     /// - [`TerminalProgram::Ghostty`] if `$TERM_PROGRAM` is `ghostty`.
+    /// - [`TerminalProgram::Konsole`] if `$KONSOLE_VERSION` denotes version 22.04 or newer.
     /// - [`TerminalProgram::Terminology`] if `$TERMINOLOGY` is `1`.
     /// - [`TerminalProgram::Ansi`] otherwise.
     pub fn detect() -> Self {
-        Self::detect_term()
+        Self::detect_with_variable().0
+    }
+
+    /// Detect the terminal program like [`TerminalProgram::detect`], and tell how it was detected.
+    ///
+    /// Return the terminal program, along with the name and value of the environment variable
+    /// which identified the terminal program, or `None` if mdcat fell back to
+    /// [`TerminalProgram::Ansi`].
+    ///
+    /// This helps to debug why mdcat does not use the features of a terminal.
+    pub fn detect_with_variable() -> (Self, Option<(&'static str, String)>) {
+        match Self::detect_term()
             .or_else(Self::detect_term_program)
-            .or_else(|| match std::env::var("TERMINOLOGY").ok().as_deref() {
-                Some("1") => Some(Self::Terminology),
-                _ => None,
-            })
-            .unwrap_or(Self::Ansi)
+            .or_else(Self::detect_konsole)
+            .or_else(Self::detect_terminology)
+        {
+            Some((terminal, name, value)) => (terminal, Some((name, value))),
+            None => (Self::Ansi, None),
+        }
     }
 
     /// Get the capabilities of this terminal emulator.
@@ -147,26 +213,31 @@ impl TerminalProgram {
         let ansi = TerminalCapabilities {
             style: Some(StyleCapability::Ansi),
             image: None,
+            image_fallback: None,
             marks: None,
+            passthrough: Passthrough::Direct,
         };
         match self {
             TerminalProgram::Dumb => TerminalCapabilities::default(),
-            TerminalProgram::Ansi => ansi,
+            TerminalProgram::Ansi | TerminalProgram::Foot => ansi,
             TerminalProgram::ITerm2 => ansi
-                .with_mark_capability(MarkCapability::ITerm2(ITerm2Protocol))
-                .with_image_capability(ImageCapability::ITerm2(ITerm2Protocol)),
+                .with_mark_capability(MarkCapability::ITerm2(ITerm2Protocol::default()))
+                .with_image_capability(ImageCapability::ITerm2(ITerm2Protocol::default())),
             TerminalProgram::Terminology => {
                 ansi.with_image_capability(ImageCapability::Terminology(terminology::Terminology))
             }
-            TerminalProgram::Kitty => ansi
-                .with_image_capability(ImageCapability::Kitty(self::kitty::KittyGraphicsProtocol)),
-            TerminalProgram::WezTerm => ansi
-                .with_image_capability(ImageCapability::Kitty(self::kitty::KittyGraphicsProtocol)),
-            TerminalProgram::VSCode => {
-                ansi.with_image_capability(ImageCapability::ITerm2(ITerm2Protocol))
+            TerminalProgram::Kitty => ansi.with_image_capability(ImageCapability::Kitty(
+                self::kitty::KittyGraphicsProtocol::default(),
+            )),
+            TerminalProgram::WezTerm => ansi.with_image_capability(ImageCapability::Kitty(
+                self::kitty::KittyGraphicsProtocol::default(),
+            )),
+            TerminalProgram::VSCode | TerminalProgram::Konsole => {
+                ansi.with_image_capability(ImageCapability::ITerm2(ITerm2Protocol::default()))
             }
-            TerminalProgram::Ghostty => ansi
-                .with_image_capability(ImageCapability::Kitty(self::kitty::KittyGraphicsProtocol)),
+            TerminalProgram::Ghostty => ansi.with_image_capability(ImageCapability::Kitty(
+                self::kitty::KittyGraphicsProtocol::default(),
+            )),
         }
     }
 }
@@ -219,6 +290,7 @@ mod tests {
             vec![
                 ("TERM", Some("xterm-256color")),
                 ("TERM_PROGRAM", None),
+                ("KONSOLE_VERSION", None),
                 ("TERMINOLOGY", Some("1")),
             ],
             || assert_eq!(TerminalProgram::detect(), TerminalProgram::Terminology),
@@ -227,6 +299,7 @@ mod tests {
             vec![
                 ("TERM", Some("xterm-256color")),
                 ("TERM_PROGRAM", None),
+                ("KONSOLE_VERSION", None),
                 ("TERMINOLOGY", Some("0")),
             ],
             || assert_eq!(TerminalProgram::detect(), TerminalProgram::Ansi),
@@ -240,6 +313,15 @@ mod tests {
         })
     }
 
+    #[test]
+    pub fn detect_term_foot() {
+        for term in ["foot", "foot-extra"] {
+            with_vars(vec![("TERM", Some(term))], || {
+                assert_eq!(TerminalProgram::detect(), TerminalProgram::Foot)
+            })
+        }
+    }
+
     #[test]
     pub fn detect_term_program_ghostty() {
         with_vars(
@@ -251,18 +333,90 @@ mod tests {
         )
     }
 
+    #[test]
+    pub fn detect_konsole() {
+        with_vars(
+            vec![
+                ("TERM", Some("xterm-256color")),
+                ("TERM_PROGRAM", None),
+                ("KONSOLE_VERSION", Some("220401")),
+            ],
+            || assert_eq!(TerminalProgram::detect(), TerminalProgram::Konsole),
+        );
+    }
+
+    #[test]
+    pub fn detect_old_konsole_as_ansi() {
+        with_vars(
+            vec![
+                ("TERM", Some("xterm-256color")),
+                ("TERM_PROGRAM", None),
+                ("KONSOLE_VERSION", Some("211203")),
+                ("TERMINOLOGY", None),
+            ],
+            || assert_eq!(TerminalProgram::detect(), TerminalProgram::Ansi),
+        );
+    }
+
     #[test]
     pub fn detect_ansi() {
         with_vars(
             vec![
                 ("TERM", Some("xterm-256color")),
                 ("TERM_PROGRAM", None),
+                ("KONSOLE_VERSION", None),
                 ("TERMINOLOGY", None),
             ],
             || assert_eq!(TerminalProgram::detect(), TerminalProgram::Ansi),
         )
     }
 
+    #[test]
+    pub fn detect_with_variable() {
+        with_vars(
+            vec![
+                ("TERM", Some("xterm-256color")),
+                ("TERM_PROGRAM", Some("iTerm.app")),
+            ],
+            || {
+                assert_eq!(
+                    TerminalProgram::detect_with_variable(),
+                    (
+                        TerminalProgram::ITerm2,
+                        Some(("TERM_PROGRAM", "iTerm.app".to_owned()))
+                    )
+                )
+            },
+        );
+        with_vars(
+            vec![
+                ("TERM", Some("xterm-256color")),
+                ("TERM_PROGRAM", None),
+                ("KONSOLE_VERSION", None),
+                ("TERMINOLOGY", None),
+            ],
+            || {
+                assert_eq!(
+                    TerminalProgram::detect_with_variable(),
+                    (TerminalProgram::Ansi, None)
+                )
+            },
+        );
+    }
+
+    #[test]
+    pub fn all_programs_are_distinct() {
+        let all = TerminalProgram::all();
+        for (i, program) in all.iter().enumerate() {
+            assert!(
+                !all[i + 1..].contains(program),
+                "{program} listed more than once"
+            );
+        }
+        assert!(all.contains(&TerminalProgram::Dumb));
+        assert!(all.contains(&TerminalProgram::Ghostty));
+    }
+
     /// Regression test for <https://github.com/swsnr/mdcat/issues/230>
     #[test]
     #[allow(non_snake_case)]
@@ -6,23 +6,28 @@
 
 //! OSC commands on terminals.
 
+use std::borrow::Cow;
 use std::io::{Result, Write};
 
 use url::{Host, Url};
 
-/// Write an OSC `command` to this terminal.
+use crate::terminal::capabilities::Passthrough;
+
+/// Write an OSC `command` to this terminal, with the given `passthrough`.
 ///
 /// See <https://www.xfree86.org/current/ctlseqs.html> for format documentation.
-pub fn write_osc<W: Write + ?Sized>(writer: &mut W, command: &str) -> Result<()> {
-    writer.write_all(&[0x1b, 0x5d])?; // OSC
-    writer.write_all(command.as_bytes())?;
-    writer.write_all(&[0x1b, b'\\'])?; // ST
-    Ok(())
+pub fn write_osc<W: Write + ?Sized>(
+    writer: &mut W,
+    passthrough: Passthrough,
+    command: &str,
+) -> Result<()> {
+    let mut sequence = Vec::with_capacity(command.len() + 4);
+    sequence.extend_from_slice(&[0x1b, 0x5d]); // OSC
+    sequence.extend_from_slice(command.as_bytes());
+    sequence.extend_from_slice(&[0x1b, b'\\']); // ST
+    passthrough.write_sequence(writer, &sequence)
 }
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
-pub struct Osc8Links;
-
 /// Whether the given `url` needs to get an explicit host.
 ///
 /// [OSC 8] links require that `file://` URLs give an explicit hostname, as
@@ -57,24 +62,117 @@ fn url_needs_explicit_host(url: &Url) -> bool {
 /// local system instead to make `file://` URLs work properly over SSH.
 ///
 /// See <https://git.io/vd4ee#file-uris-and-the-hostname>.
-pub fn set_link_url<W: Write>(writer: &mut W, mut destination: Url, hostname: &str) -> Result<()> {
+pub fn set_link_url<W: Write>(
+    writer: &mut W,
+    passthrough: Passthrough,
+    mut destination: Url,
+    hostname: &str,
+) -> Result<()> {
     if url_needs_explicit_host(&destination) {
         destination.set_host(Some(hostname)).unwrap();
     }
-    set_link(writer, destination.as_str())
+    set_link(writer, passthrough, destination.as_str())
 }
 
 /// Clear the current link if any.
-pub fn clear_link<W: Write>(writer: &mut W) -> Result<()> {
-    set_link(writer, "")
+pub fn clear_link<W: Write>(writer: &mut W, passthrough: Passthrough) -> Result<()> {
+    set_link(writer, passthrough, "")
 }
 
-fn set_link<W: Write>(writer: &mut W, destination: &str) -> Result<()> {
-    write_osc(writer, &format!("8;;{destination}"))
+/// Percent-encode all bytes of `uri` which [OSC 8] does not permit in URIs.
+///
+/// OSC 8 only permits bytes from 32 to 126 in URIs; in particular control characters like BEL or
+/// ESC would terminate the OSC sequence early.  [`Url`] already percent-encodes these, so this is
+/// merely a safety net for the raw serialization of a URL.
+///
+/// [OSC 8]: https://git.io/vd4ee
+fn escape_uri(uri: &str) -> Cow<'_, str> {
+    if uri.bytes().all(|b| (32..=126).contains(&b)) {
+        Cow::Borrowed(uri)
+    } else {
+        let mut escaped = String::with_capacity(uri.len());
+        for b in uri.bytes() {
+            if (32..=126).contains(&b) {
+                escaped.push(char::from(b));
+            } else {
+                escaped.push_str(&format!("%{b:02X}"));
+            }
+        }
+        Cow::Owned(escaped)
+    }
+}
+
+fn set_link<W: Write>(writer: &mut W, passthrough: Passthrough, destination: &str) -> Result<()> {
+    write_osc(
+        writer,
+        passthrough,
+        &format!("8;;{}", escape_uri(destination)),
+    )
 }
 
 #[cfg(test)]
 mod tests {
+    use super::{clear_link, escape_uri, set_link, set_link_url, Url};
+    use crate::terminal::capabilities::Passthrough;
+
+    #[test]
+    fn escape_uri_percent_encodes_control_characters() {
+        similar_asserts::assert_eq!(
+            escape_uri("https://example.com/a\x07b\x1b\\c\u{9c}d"),
+            "https://example.com/a%07b%1B\\c%C2%9Cd"
+        );
+        similar_asserts::assert_eq!(
+            escape_uri("https://example.com/foo?bar=baz#qux"),
+            "https://example.com/foo?bar=baz#qux"
+        );
+    }
+
+    #[test]
+    fn set_link_url_with_control_characters() {
+        let url = Url::parse("https://example.com/a\x07b\x1b\\c").unwrap();
+        let mut sink = Vec::new();
+        set_link_url(&mut sink, Passthrough::Direct, url.clone(), "localhost").unwrap();
+        let osc = String::from_utf8(sink).unwrap();
+        similar_asserts::assert_eq!(osc, "\x1b]8;;https://example.com/a%07b%1B/c\x1b\\");
+        // The link still resolves to the same URL
+        let written = osc
+            .strip_prefix("\x1b]8;;")
+            .unwrap()
+            .strip_suffix("\x1b\\")
+            .unwrap();
+        similar_asserts::assert_eq!(Url::parse(written).unwrap(), url);
+    }
+
+    #[test]
+    fn set_link_escapes_raw_control_characters() {
+        let mut sink = Vec::new();
+        set_link(&mut sink, Passthrough::Direct, "x-raw:a\x07b\x1b\\c").unwrap();
+        similar_asserts::assert_eq!(
+            String::from_utf8(sink).unwrap(),
+            "\x1b]8;;x-raw:a%07b%1B\\c\x1b\\"
+        );
+    }
+
+    #[test]
+    fn set_link_with_tmux_passthrough() {
+        let mut sink = Vec::new();
+        set_link(&mut sink, Passthrough::Tmux, "https://example.com").unwrap();
+        similar_asserts::assert_eq!(
+            String::from_utf8(sink).unwrap(),
+            "\x1bPtmux;\x1b\x1b]8;;https://example.com\x1b\x1b\\\x1b\\"
+        );
+    }
+
+    #[test]
+    fn clear_link_with_tmux_passthrough() {
+        let mut sink = Vec::new();
+        clear_link(&mut sink, Passthrough::Tmux).unwrap();
+        similar_asserts::assert_eq!(
+            String::from_utf8(sink).unwrap(),
+            "\x1bPtmux;\x1b\x1b]8;;\x1b\x1b\\\x1b\\"
+        );
+    }
+
     #[test]
     fn url_needs_explicit_host() {
         let checks = [
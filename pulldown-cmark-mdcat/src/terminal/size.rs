@@ -18,7 +18,7 @@ use std::cmp::Ordering;
 pub struct PixelSize {
     /// The width of the window, in pixels.
     pub x: u32,
-    // The height of the window, in pixels.
+    /// The height of the window, in pixels.
     pub y: u32,
 }
 
@@ -124,24 +124,15 @@ mod implementation {
             );
             None
         } else {
-            let mut terminal_size = TerminalSize {
-                columns: winsize.ws_col,
-                rows: winsize.ws_row,
-                pixels: None,
-                cell: None,
-            };
+            let terminal_size = TerminalSize::new(winsize.ws_col, winsize.ws_row);
             if winsize.ws_xpixel != 0 && winsize.ws_ypixel != 0 {
-                let pixels = PixelSize {
+                Some(terminal_size.with_pixels(PixelSize {
                     x: winsize.ws_xpixel as u32,
                     y: winsize.ws_ypixel as u32,
-                };
-                terminal_size.pixels = Some(pixels);
-                terminal_size.cell = Some(PixelSize {
-                    x: pixels.x / terminal_size.columns as u32,
-                    y: pixels.y / terminal_size.rows as u32,
-                });
-            };
-            Some(terminal_size)
+                }))
+            } else {
+                Some(terminal_size)
+            }
         }
     }
 }
@@ -163,9 +154,57 @@ mod implementation {
 }
 
 impl TerminalSize {
+    /// Create a terminal size with the given `columns` and `rows`, without pixel size.
+    ///
+    /// Use [`TerminalSize::with_pixels`] or [`TerminalSize::with_cell_size`] to add pixel size, e.g.
+    /// to simulate a terminal which reports its pixel size:
+    ///
+    /// ```
+    /// # use pulldown_cmark_mdcat::terminal::{PixelSize, TerminalSize};
+    /// let size = TerminalSize::new(80, 24).with_pixels(PixelSize::from_xy((800, 480)));
+    /// assert_eq!(size.cell, Some(PixelSize::from_xy((10, 20))));
+    /// ```
+    pub fn new(columns: u16, rows: u16) -> Self {
+        Self {
+            columns,
+            rows,
+            pixels: None,
+            cell: None,
+        }
+    }
+
+    /// Set the size of the terminal in `pixels`.
+    ///
+    /// Also derive the size of a single cell from the number of columns and rows.
+    pub fn with_pixels(self, pixels: PixelSize) -> Self {
+        let cell = (0 < self.columns && 0 < self.rows).then(|| PixelSize {
+            x: pixels.x / u32::from(self.columns),
+            y: pixels.y / u32::from(self.rows),
+        });
+        Self {
+            pixels: Some(pixels),
+            cell,
+            ..self
+        }
+    }
+
+    /// Set the size of a single `cell` in pixels.
+    ///
+    /// Also derive the size of the terminal in pixels from the number of columns and rows.
+    pub fn with_cell_size(self, cell: PixelSize) -> Self {
+        Self {
+            pixels: Some(PixelSize {
+                x: cell.x * u32::from(self.columns),
+                y: cell.y * u32::from(self.rows),
+            }),
+            cell: Some(cell),
+            ..self
+        }
+    }
+
     /// Get terminal size from `$COLUMNS` and `$LINES`.
     ///
-    /// Do not assume any knowledge about window size.
+    /// Do not assume any knowledge about window size.  Return `None` if either variable is zero.
     pub fn from_env() -> Option<Self> {
         let columns = std::env::var("COLUMNS")
             .ok()
@@ -175,7 +214,7 @@ impl TerminalSize {
             .and_then(|value| value.parse::<u16>().ok());
 
         match (columns, rows) {
-            (Some(columns), Some(rows)) => Some(Self {
+            (Some(columns), Some(rows)) if 0 < columns && 0 < rows => Some(Self {
                 columns,
                 rows,
                 pixels: None,
@@ -206,13 +245,20 @@ impl TerminalSize {
 
     /// Shrink the terminal size to the given amount of maximum columns.
     ///
-    /// Also shrinks the pixel size accordingly.
+    /// Also scale the pixel width accordingly, so that images scaled to the pixel width fit into
+    /// the given columns.  Keep at least one column.
     pub fn with_max_columns(&self, max_columns: u16) -> Self {
+        let max_columns = max_columns.max(1);
         let pixels = match (self.pixels, self.cell) {
             (Some(pixels), Some(cell)) => Some(PixelSize {
                 x: cell.x * max_columns as u32,
                 y: pixels.y,
             }),
+            // Without a cell size scale the pixel width proportionally
+            (Some(pixels), None) if 0 < self.columns => Some(PixelSize {
+                x: (u64::from(pixels.x) * u64::from(max_columns) / u64::from(self.columns)) as u32,
+                y: pixels.y,
+            }),
             _ => None,
         };
         Self {
@@ -223,3 +269,66 @@ impl TerminalSize {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use temp_env::with_vars;
+
+    use super::{PixelSize, TerminalSize};
+
+    #[test]
+    fn from_env_rejects_zero_columns() {
+        with_vars([("COLUMNS", Some("0")), ("LINES", Some("24"))], || {
+            assert_eq!(TerminalSize::from_env(), None)
+        });
+    }
+
+    #[test]
+    fn from_env() {
+        with_vars([("COLUMNS", Some("42")), ("LINES", Some("24"))], || {
+            assert_eq!(
+                TerminalSize::from_env().map(|size| (size.columns, size.rows)),
+                Some((42, 24))
+            )
+        });
+    }
+
+    #[test]
+    fn builder() {
+        let size = TerminalSize::new(100, 50).with_pixels(PixelSize::from_xy((1000, 1000)));
+        assert_eq!(size.pixels, Some(PixelSize::from_xy((1000, 1000))));
+        assert_eq!(size.cell, Some(PixelSize::from_xy((10, 20))));
+        assert_eq!(
+            TerminalSize::new(100, 50).with_cell_size(PixelSize::from_xy((10, 20))),
+            size
+        );
+        assert_eq!(TerminalSize::new(80, 24).with_max_columns(40).columns, 40);
+    }
+
+    #[test]
+    fn with_max_columns_scales_pixel_width() {
+        let size = TerminalSize::new(100, 50)
+            .with_pixels(PixelSize::from_xy((1000, 1000)))
+            .with_max_columns(40);
+        assert_eq!(size.columns, 40);
+        assert_eq!(size.pixels, Some(PixelSize::from_xy((400, 1000))));
+        assert_eq!(size.cell, Some(PixelSize::from_xy((10, 20))));
+    }
+
+    #[test]
+    fn with_max_columns_scales_pixel_width_without_cell_size() {
+        let size = TerminalSize {
+            pixels: Some(PixelSize::from_xy((1000, 1000))),
+            ..TerminalSize::new(100, 50)
+        }
+        .with_max_columns(30);
+        assert_eq!(size.columns, 30);
+        assert_eq!(size.pixels, Some(PixelSize::from_xy((300, 1000))));
+        assert_eq!(size.cell, None);
+    }
+
+    #[test]
+    fn with_max_columns_keeps_one_column() {
+        assert_eq!(TerminalSize::default().with_max_columns(0).columns, 1);
+    }
+}
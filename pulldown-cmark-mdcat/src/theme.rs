@@ -10,25 +10,123 @@ use anstyle::{AnsiColor, Color, Style};
 
 /// A colour theme for mdcat.
 ///
-/// Currently you cannot create custom styles, but only use the default theme via [`Theme::default`].
+/// Start from the default theme via [`Theme::default`], and replace individual styles with the
+/// `with_*` methods.  Styles may include foreground and background colours as well as effects.
 #[derive(Debug, Clone)]
 pub struct Theme {
     /// Style for HTML blocks.
     pub(crate) html_block_style: Style,
     /// Style for inline HTML.
     pub(crate) inline_html_style: Style,
-    /// Style for code, unless the code is syntax-highlighted.
+    /// Style for code blocks, unless the code is syntax-highlighted.
     pub(crate) code_style: Style,
+    /// Style for inline code.
+    pub(crate) inline_code_style: Style,
+    /// Style for inline and display math.
+    pub(crate) math_style: Style,
     /// Style for links.
     pub(crate) link_style: Style,
     /// Color for image links (unless the image is rendered inline)
     pub(crate) image_link_style: Style,
+    /// Style for image descriptions, if mdcat can neither render an image nor link to it.
+    pub(crate) image_fallback_style: Style,
     /// Color for rulers.
     pub(crate) rule_color: Color,
     /// Color for borders around code blocks.
     pub(crate) code_block_border_color: Color,
+    /// How wide to draw borders around code blocks.
+    pub(crate) code_block_border_width: CodeBlockBorderWidth,
     /// Color for headings
     pub(crate) heading_style: Style,
+    /// Characters for bullets, rules, and other decorations.
+    pub(crate) glyphs: Glyphs,
+    /// The number of blank lines between blocks.
+    pub(crate) block_margin: u16,
+    /// Whether to strike through text with a combining overlay instead of SGR 9.
+    pub(crate) strikethrough_overlay: bool,
+    /// Styles for block quotes by depth, cycling for deeper quotes.
+    pub(crate) block_quote_styles: Vec<Style>,
+}
+
+/// Characters which mdcat uses to decorate text, e.g. for list bullets or rules.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Glyphs {
+    /// The bullet of items in unordered lists.
+    ///
+    /// mdcat assumes that the bullet is one column wide.
+    pub bullet: &'static str,
+    /// The character to draw horizontal rules with.
+    pub rule: &'static str,
+    /// The character to draw borders of code blocks with.
+    pub code_block_border: &'static str,
+    /// The character to draw borders of tables with.
+    pub table_border: &'static str,
+    /// The character to prefix headings with, once for every level.
+    pub heading: &'static str,
+    /// The marker of unchecked task list items.
+    pub task_unchecked: &'static str,
+    /// The marker of checked task list items.
+    pub task_checked: &'static str,
+    /// The ellipsis at the end of truncated text.
+    pub ellipsis: &'static str,
+    /// The marker of notices about diagrams which mdcat did not render.
+    pub diagram: &'static str,
+}
+
+impl Glyphs {
+    /// Unicode glyphs, including box-drawing characters.
+    pub const UNICODE: Glyphs = Glyphs {
+        bullet: "\u{2022}",
+        rule: "\u{2550}",
+        code_block_border: "\u{2500}",
+        table_border: "\u{2500}",
+        heading: "\u{2504}",
+        task_unchecked: "\u{2610}",
+        task_checked: "\u{2611}",
+        ellipsis: "\u{2026}",
+        diagram: "\u{2301}",
+    };
+
+    /// Only ASCII characters, for terminals or fonts without box-drawing characters.
+    pub const ASCII: Glyphs = Glyphs {
+        bullet: "*",
+        rule: "=",
+        code_block_border: "-",
+        table_border: "-",
+        heading: "#",
+        task_unchecked: "[ ]",
+        task_checked: "[x]",
+        ellipsis: "...",
+        diagram: "~",
+    };
+}
+
+/// How wide mdcat draws the borders above and below code blocks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CodeBlockBorderWidth {
+    /// A fixed number of columns, but at most the width of the terminal.
+    Fixed(u16),
+    /// The full width of the terminal.
+    FullWidth,
+    /// The width of the widest line of the code block, but at most the width of the terminal.
+    ///
+    /// [`crate::Renderer`] cannot look ahead at the contents of a code block, and uses the
+    /// default width instead.
+    Content,
+}
+
+impl Default for CodeBlockBorderWidth {
+    /// Twenty columns.
+    fn default() -> Self {
+        Self::Fixed(20)
+    }
+}
+
+impl Default for Glyphs {
+    /// Unicode glyphs.
+    fn default() -> Self {
+        Self::UNICODE
+    }
 }
 
 impl Default for Theme {
@@ -38,13 +136,151 @@ impl Default for Theme {
             html_block_style: Style::new().fg_color(Some(AnsiColor::Green.into())),
             inline_html_style: Style::new().fg_color(Some(AnsiColor::Green.into())),
             code_style: Style::new().fg_color(Some(AnsiColor::Yellow.into())),
+            inline_code_style: Style::new().fg_color(Some(AnsiColor::Yellow.into())),
+            math_style: Style::new().fg_color(Some(AnsiColor::Cyan.into())),
             link_style: Style::new().fg_color(Some(AnsiColor::Blue.into())),
             image_link_style: Style::new().fg_color(Some(AnsiColor::Magenta.into())),
+            image_fallback_style: Style::new().fg_color(Some(AnsiColor::Magenta.into())),
             rule_color: AnsiColor::Green.into(),
             code_block_border_color: AnsiColor::Green.into(),
+            code_block_border_width: CodeBlockBorderWidth::default(),
             heading_style: Style::new().fg_color(Some(AnsiColor::Blue.into())).bold(),
+            glyphs: Glyphs::default(),
+            block_margin: 1,
+            strikethrough_overlay: false,
+            block_quote_styles: Vec::new(),
+        }
+    }
+}
+
+impl Theme {
+    /// Use the given `style` for HTML blocks.
+    pub fn with_html_block_style(self, style: Style) -> Self {
+        Self {
+            html_block_style: style,
+            ..self
+        }
+    }
+
+    /// Use the given `style` for inline HTML.
+    pub fn with_inline_html_style(self, style: Style) -> Self {
+        Self {
+            inline_html_style: style,
+            ..self
+        }
+    }
+
+    /// Use the given `style` for code blocks which are not syntax-highlighted.
+    pub fn with_code_style(self, style: Style) -> Self {
+        Self {
+            code_style: style,
+            ..self
+        }
+    }
+
+    /// Use the given `style` for inline code.
+    pub fn with_inline_code_style(self, style: Style) -> Self {
+        Self {
+            inline_code_style: style,
+            ..self
+        }
+    }
+
+    /// Use the given `style` for inline and display math.
+    pub fn with_math_style(self, style: Style) -> Self {
+        Self {
+            math_style: style,
+            ..self
+        }
+    }
+
+    /// Use the given `style` for links.
+    pub fn with_link_style(self, style: Style) -> Self {
+        Self {
+            link_style: style,
+            ..self
         }
     }
+
+    /// Use the given `style` for links to images which mdcat does not render inline.
+    pub fn with_image_link_style(self, style: Style) -> Self {
+        Self {
+            image_link_style: style,
+            ..self
+        }
+    }
+
+    /// Use the given `style` for descriptions of images which mdcat can neither render inline nor
+    /// write as link, e.g. `Style::new().italic()`.
+    pub fn with_image_fallback_style(self, style: Style) -> Self {
+        Self {
+            image_fallback_style: style,
+            ..self
+        }
+    }
+
+    /// Use the given `style` for headings.
+    pub fn with_heading_style(self, style: Style) -> Self {
+        Self {
+            heading_style: style,
+            ..self
+        }
+    }
+
+    /// Draw borders of code blocks with the given `width`.
+    pub fn with_code_block_border_width(self, width: CodeBlockBorderWidth) -> Self {
+        Self {
+            code_block_border_width: width,
+            ..self
+        }
+    }
+
+    /// Use the given `glyphs` to decorate text.
+    pub fn with_glyphs(self, glyphs: Glyphs) -> Self {
+        Self { glyphs, ..self }
+    }
+
+    /// Separate blocks, e.g. paragraphs, by the given number of blank `lines`.
+    ///
+    /// The default theme separates blocks by one blank line; use `0` for compact output.
+    pub fn with_block_margin(self, lines: u16) -> Self {
+        Self {
+            block_margin: lines,
+            ..self
+        }
+    }
+
+    /// Whether to strike through text with a combining long stroke overlay (U+0336).
+    ///
+    /// By default mdcat strikes through text with the SGR 9 attribute, which some terminals do not
+    /// support.  If enabled, mdcat adds the combining overlay to every character of struck text
+    /// instead, which is visible in all terminals and fonts with combining characters.
+    pub fn with_strikethrough_overlay(self, overlay: bool) -> Self {
+        Self {
+            strikethrough_overlay: overlay,
+            ..self
+        }
+    }
+
+    /// Style block quotes by their depth with the given `styles`.
+    ///
+    /// Use the first style for top-level block quotes, the second style for block quotes within,
+    /// and so on, and start over with the first style if quotes nest deeper than `styles`.  Block
+    /// quotes are always italic; the styles only add colours and further effects.  By default
+    /// there are no styles, and block quotes at all depths look the same.
+    pub fn with_block_quote_styles(self, styles: Vec<Style>) -> Self {
+        Self {
+            block_quote_styles: styles,
+            ..self
+        }
+    }
+
+    /// The style for a block quote at the given `depth`, starting at 1 for top-level quotes.
+    pub(crate) fn block_quote_style(&self, depth: u16) -> Option<Style> {
+        let index = usize::from(depth.checked_sub(1)?);
+        (!self.block_quote_styles.is_empty())
+            .then(|| self.block_quote_styles[index % self.block_quote_styles.len()])
+    }
 }
 
 /// Combine styles.
@@ -66,3 +302,38 @@ impl CombineStyle for Style {
             .underline_color(self.get_underline_color().or(other.get_underline_color()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use anstyle::{AnsiColor, Style};
+    use similar_asserts::assert_eq;
+
+    use super::CombineStyle;
+
+    #[test]
+    fn on_top_of_prefers_own_background() {
+        let parent = Style::new().bg_color(Some(AnsiColor::Blue.into())).bold();
+        let child = Style::new()
+            .fg_color(Some(AnsiColor::Yellow.into()))
+            .bg_color(Some(AnsiColor::Black.into()));
+        assert_eq!(
+            child.on_top_of(&parent),
+            Style::new()
+                .fg_color(Some(AnsiColor::Yellow.into()))
+                .bg_color(Some(AnsiColor::Black.into()))
+                .bold()
+        );
+    }
+
+    #[test]
+    fn on_top_of_inherits_background() {
+        let parent = Style::new().bg_color(Some(AnsiColor::Blue.into()));
+        let child = Style::new().fg_color(Some(AnsiColor::Yellow.into()));
+        assert_eq!(
+            child.on_top_of(&parent),
+            Style::new()
+                .fg_color(Some(AnsiColor::Yellow.into()))
+                .bg_color(Some(AnsiColor::Blue.into()))
+        );
+    }
+}
@@ -15,14 +15,13 @@ use std::path::Path;
 use std::sync::OnceLock;
 
 use insta::{assert_snapshot, glob};
-use pulldown_cmark::{Options, Parser};
+use pulldown_cmark::Parser;
 use syntect::parsing::SyntaxSet;
 use url::Url;
 
 use pulldown_cmark_mdcat::resources::*;
 use pulldown_cmark_mdcat::terminal::{TerminalProgram, TerminalSize};
-use pulldown_cmark_mdcat::Settings;
-use pulldown_cmark_mdcat::{Environment, Theme};
+use pulldown_cmark_mdcat::{default_parser_options, Environment, Settings};
 
 static TEST_READ_LIMIT: u64 = 5_242_880;
 
@@ -40,10 +39,7 @@ fn resource_handler() -> DispatchingResourceHandler {
 
 fn render_to_string<P: AsRef<Path>>(markdown_file: P, settings: &Settings) -> String {
     let markdown = std::fs::read_to_string(&markdown_file).unwrap();
-    let parser = Parser::new_ext(
-        &markdown,
-        Options::ENABLE_TASKLISTS | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES,
-    );
+    let parser = Parser::new_ext(&markdown, default_parser_options());
     let abs_path = std::fs::canonicalize(&markdown_file).unwrap();
     let base_dir = abs_path
         .parent()
@@ -65,24 +61,21 @@ fn test_render() {
     root_url.set_host(Some("HOSTNAME")).unwrap();
     cwd_url.set_host(Some("HOSTNAME")).unwrap();
 
-    let dumb_settings = Settings {
-        terminal_capabilities: TerminalProgram::Dumb.capabilities(),
-        terminal_size: TerminalSize::default(),
-        theme: Theme::default(),
-        syntax_set: syntax_set(),
-    };
-    let ansi_settings = Settings {
-        terminal_capabilities: TerminalProgram::Ansi.capabilities(),
-        terminal_size: TerminalSize::default(),
-        theme: Theme::default(),
-        syntax_set: syntax_set(),
-    };
-    let iterm2_settings = Settings {
-        terminal_capabilities: TerminalProgram::ITerm2.capabilities(),
-        terminal_size: TerminalSize::default(),
-        theme: Theme::default(),
-        syntax_set: syntax_set(),
-    };
+    let dumb_settings = Settings::new(
+        TerminalProgram::Dumb.capabilities(),
+        TerminalSize::default(),
+        syntax_set(),
+    );
+    let ansi_settings = Settings::new(
+        TerminalProgram::Ansi.capabilities(),
+        TerminalSize::default(),
+        syntax_set(),
+    );
+    let iterm2_settings = Settings::new(
+        TerminalProgram::ITerm2.capabilities(),
+        TerminalSize::default(),
+        syntax_set(),
+    );
 
     glob!("markdown/**/*.md", |markdown_file| {
         let mut settings = insta::Settings::clone_current();
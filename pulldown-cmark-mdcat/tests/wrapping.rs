@@ -9,18 +9,15 @@
 #![deny(warnings, clippy::all)]
 
 use insta::glob;
-use pulldown_cmark::{Options, Parser};
+use pulldown_cmark::Parser;
 use syntect::parsing::SyntaxSet;
 
 use pulldown_cmark_mdcat::resources::NoopResourceHandler;
 use pulldown_cmark_mdcat::terminal::{TerminalProgram, TerminalSize};
-use pulldown_cmark_mdcat::{Environment, Settings, Theme};
+use pulldown_cmark_mdcat::{default_parser_options, Environment, Settings};
 
 fn render_to_string<S: AsRef<str>>(markdown: S, settings: &Settings) -> String {
-    let parser = Parser::new_ext(
-        markdown.as_ref(),
-        Options::ENABLE_TASKLISTS | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES,
-    );
+    let parser = Parser::new_ext(markdown.as_ref(), default_parser_options());
     let mut sink = Vec::new();
     let env = Environment {
         hostname: "HOSTNAME".to_string(),
@@ -35,12 +32,12 @@ fn render_to_string<S: AsRef<str>>(markdown: S, settings: &Settings) -> String {
 fn lines_are_below_column_width_of_terminal() {
     glob!("markdown/wrapping/*.md", |markdown_file| {
         let markdown = std::fs::read_to_string(markdown_file).unwrap();
-        let settings = Settings {
-            terminal_capabilities: TerminalProgram::Ansi.capabilities(),
-            terminal_size: TerminalSize::default(),
-            theme: Theme::default(),
-            syntax_set: &SyntaxSet::load_defaults_newlines(),
-        };
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let settings = Settings::new(
+            TerminalProgram::Ansi.capabilities(),
+            TerminalSize::default(),
+            &syntax_set,
+        );
         let rendered = render_to_string(markdown, &settings);
         for line in rendered.lines() {
             let width = textwrap::core::display_width(line);
@@ -6,6 +6,9 @@
 
 use clap::ValueHint;
 use clap_complete::Shell;
+use syntect::highlighting::{Theme, ThemeSet};
+
+use crate::config::MarkdownConfig;
 
 fn after_help() -> &'static str {
     "See 'man 1 mdcat' for more information.
@@ -16,7 +19,7 @@ for automatic pagination.
 Report issues to <https://github.com/swsnr/mdcat>."
 }
 
-fn long_version() -> &'static str {
+pub fn long_version() -> &'static str {
     concat!(
         env!("CARGO_PKG_VERSION"),
         "
@@ -37,7 +40,7 @@ pub struct Args {
 
 #[derive(Debug, clap::Subcommand)]
 pub enum Command {
-    #[command(version, about, after_help = after_help(), long_version = long_version())]
+    #[command(version, about, after_help = after_help(), disable_version_flag = true)]
     Mdcat {
         #[command(flatten)]
         args: CommonArgs,
@@ -48,7 +51,7 @@ pub enum Command {
         #[arg(short = 'P', long)]
         no_pager: bool,
     },
-    #[command(version, about, after_help = after_help(), long_version = long_version())]
+    #[command(version, about, after_help = after_help(), disable_version_flag = true)]
     Mdless {
         #[command(flatten)]
         args: CommonArgs,
@@ -62,12 +65,21 @@ pub enum Command {
 }
 
 impl Command {
+    /// The name of the binary for this command.
+    pub fn binary_name(&self) -> &'static str {
+        match self {
+            Command::Mdcat { .. } => "mdcat",
+            Command::Mdless { .. } => "mdless",
+        }
+    }
+
     pub fn paginate(&self) -> bool {
         match *self {
             // In both cases look at the option indicating the non-default
             // behaviour; the overrides above are configured accordingly.
-            Command::Mdcat { paginate, .. } => paginate,
-            Command::Mdless { no_pager, .. } => !no_pager,
+            // Never paginate in watch mode, because the pager would block re-rendering.
+            Command::Mdcat { paginate, .. } => paginate && !self.watch,
+            Command::Mdless { no_pager, .. } => !no_pager && !self.watch,
         }
     }
 }
@@ -83,6 +95,114 @@ impl std::ops::Deref for Command {
     }
 }
 
+/// Validate a pager command.
+///
+/// Make sure that the command splits into a non-empty list of words.
+fn parse_pager(command: &str) -> Result<String, String> {
+    match shell_words::split(command) {
+        Ok(words) if words.is_empty() => Err("Empty pager command".into()),
+        Ok(_) => Ok(command.to_owned()),
+        Err(error) => Err(format!("Failed to parse pager command: {error}")),
+    }
+}
+
+/// Validate a hostname for file links.
+fn parse_hostname(hostname: &str) -> Result<String, String> {
+    url::Host::parse(hostname)
+        .map(|host| host.to_string())
+        .map_err(|error| format!("Invalid hostname: {error}"))
+}
+
+/// An image protocol to use instead of the protocol of the detected terminal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum ImageProtocol {
+    /// The kitty graphics protocol.
+    Kitty,
+    /// The inline images protocol of iTerm2.
+    #[value(name = "iterm2")]
+    ITerm2,
+    /// The image protocol of Terminology.
+    Terminology,
+    /// Unicode half blocks with 24-bit colours.
+    #[value(name = "halfblock")]
+    HalfBlock,
+    /// Do not show images.
+    None,
+}
+
+/// How to show the URLs of links.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum LinkStyle {
+    /// Write numbered references to link URLs, unless the terminal supports hyperlinks.
+    Reference,
+    /// Write link URLs in parentheses right after the link text.
+    Inline,
+    /// Only write the link text, and hide the URL.
+    Hidden,
+}
+
+/// Find a built-in syntax theme by its `name`.
+pub fn parse_syntax_theme(name: &str) -> Result<Theme, String> {
+    let mut themes = ThemeSet::load_defaults().themes;
+    themes.remove(name).ok_or_else(|| {
+        let names = themes.keys().map(String::as_str).collect::<Vec<_>>();
+        format!(
+            "Unknown syntax theme {name}, available themes are: {}",
+            names.join(", ")
+        )
+    })
+}
+
+/// A range of lines in a document, counting from 1.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LineRange {
+    /// The first line to include.
+    pub start: usize,
+    /// The last line to include, or `None` to include all lines up to the end.
+    pub end: Option<usize>,
+}
+
+impl LineRange {
+    /// Whether this range contains the line with the given `number`.
+    pub fn contains(&self, number: usize) -> bool {
+        self.start <= number && self.end.is_none_or(|end| number <= end)
+    }
+}
+
+impl std::fmt::Display for LineRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.end {
+            Some(end) => write!(f, "{}-{}", self.start, end),
+            None => write!(f, "{}-", self.start),
+        }
+    }
+}
+
+/// Parse a line range, i.e. `START-END` or `START-`.
+fn parse_line_range(range: &str) -> Result<LineRange, String> {
+    let (start, end) = range
+        .split_once('-')
+        .ok_or_else(|| format!("Expected START-END, got {range}"))?;
+    let start = start
+        .parse::<usize>()
+        .map_err(|error| format!("Invalid start line {start}: {error}"))?;
+    let end = if end.is_empty() {
+        None
+    } else {
+        Some(
+            end.parse::<usize>()
+                .map_err(|error| format!("Invalid end line {end}: {error}"))?,
+        )
+    };
+    if start == 0 {
+        Err("Lines count from 1".into())
+    } else if end.is_some_and(|end| end < start) {
+        Err(format!("End line before start line in {range}"))
+    } else {
+        Ok(LineRange { start, end })
+    }
+}
+
 #[derive(Debug, clap::Args)]
 // #[command(author, version, about, after_help = after_help(), long_version = long_version())]
 pub struct CommonArgs {
@@ -92,18 +212,142 @@ pub struct CommonArgs {
     /// Disable all colours and other styles.
     #[arg(short = 'c', long, aliases=["nocolour", "no-color", "nocolor"])]
     pub no_colour: bool,
+    /// Render plain text without any formatting.  Default if standard output is not a terminal.
+    #[arg(long, conflicts_with_all = ["force_colour", "ansi_only"])]
+    pub plain: bool,
+    /// Use colours and other formatting even if standard output is not a terminal.
+    #[arg(long, aliases=["force-color"], conflicts_with = "no_colour")]
+    pub force_colour: bool,
+    /// The pager command to use with --paginate, instead of $MDCAT_PAGER or $PAGER.
+    #[arg(long, value_name = "COMMAND", value_parser = parse_pager)]
+    pub pager: Option<String>,
     /// Maximum number of columns to use for output.
-    #[arg(long)]
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
     pub columns: Option<u16>,
+    /// Maximum number of columns to use for output in a pager, unless --columns is given; 0 to use
+    /// the full width of the terminal.  Defaults to 80.
+    #[arg(long, value_name = "COLUMNS")]
+    pub pager_columns: Option<u16>,
+    /// Expand tabs in code blocks to the given number of columns.
+    #[arg(long)]
+    pub tab_width: Option<u16>,
+    /// Highlight code with the given built-in syntax theme, e.g. "Solarized (dark)".
+    #[arg(long, value_name = "NAME", value_parser = parse_syntax_theme)]
+    pub syntax_theme: Option<Theme>,
+    /// Indent every line of output by the given number of columns.
+    #[arg(long, value_name = "COLUMNS", default_value_t = 0)]
+    pub indent: u16,
+    /// Only render the given lines of each input file, e.g. 10-40, or 10- for all lines from 10.
+    #[arg(long, value_name = "START-END", value_parser = parse_line_range)]
+    pub lines: Option<LineRange>,
+    /// Fail to render input files larger than the given number of bytes.
+    #[arg(long, value_name = "BYTES")]
+    pub max_input_size: Option<u64>,
+    /// Parse $inline$ and $$display$$ math, and highlight the math source.
+    #[arg(long, overrides_with = "no_math")]
+    pub math: bool,
+    /// Do not parse math, even if enabled in the configuration file.
+    #[arg(long)]
+    pub no_math: bool,
+    /// Turn bare URLs like https://example.com into links.
+    #[arg(long, overrides_with = "no_autolinks")]
+    pub autolinks: bool,
+    /// Do not turn bare URLs into links, even if enabled in the configuration file.
+    #[arg(long)]
+    pub no_autolinks: bool,
+    /// Write code blocks in the given comma-separated languages, e.g. mermaid, as plain text
+    /// with a notice that mdcat does not render diagrams.
+    #[arg(long, value_name = "LANGUAGES", value_delimiter = ',')]
+    pub diagram_languages: Vec<String>,
+    /// Hide YAML or TOML front matter at the start of documents.
+    #[arg(long, overrides_with = "no_front_matter")]
+    pub front_matter: bool,
+    /// Do not hide front matter, even if enabled in the configuration file.
+    #[arg(long, conflicts_with = "front_matter_title")]
+    pub no_front_matter: bool,
+    /// Render the title of front matter as a heading; implies --front-matter.
+    #[arg(long)]
+    pub front_matter_title: bool,
+    /// Only render a table of contents, i.e. a nested list of all headings.
+    #[arg(long)]
+    pub toc: bool,
+    /// Write all link references at the end of the document, instead of after each section.
+    #[arg(long)]
+    pub references_at_end: bool,
+    /// How to show link URLs: as numbered references, inline after the link text, or not at all.
+    #[arg(long, value_name = "STYLE", value_enum, default_value_t = LinkStyle::Reference)]
+    pub link_style: LinkStyle,
+    /// The hostname to use in links to local files, instead of the hostname of this system.
+    #[arg(long, value_parser = parse_hostname, value_hint = ValueHint::Hostname)]
+    pub hostname: Option<String>,
     /// Do not load remote resources like images.
-    #[arg(short, long = "local")]
+    #[arg(short, long = "local", overrides_with = "remote")]
     pub local_only: bool,
+    /// Load remote resources, even if the configuration file sets local. Overrides an earlier --local.
+    #[arg(long)]
+    pub remote: bool,
+    /// Only load remote resources over HTTPS.
+    #[arg(long)]
+    pub https_only: bool,
+    /// With --local, allow reading local files outside the directory of the document.
+    #[arg(long, overrides_with = "confined")]
+    pub unconfined: bool,
+    /// With --local, only read local files within the directory of the document, even if the
+    /// configuration file sets unconfined.
+    #[arg(long)]
+    pub confined: bool,
+    /// Render files again whenever they change, until interrupted.  Implies --no-pager.
+    #[arg(long, conflicts_with_all = ["events_json", "dump_events", "list_resources"])]
+    pub watch: bool,
     /// Exit immediately if any error occurs processing an input file.
     #[arg(long = "fail")]
     pub fail_fast: bool,
     /// Print detected terminal name and exit.
     #[arg(long = "detect-terminal")]
     pub detect_and_exit: bool,
+    /// Print version and exit.  With --verbose also print compiled features and the detected terminal.
+    #[arg(short = 'V', long)]
+    pub version: bool,
+    /// Print more details, e.g. all terminal capabilities with --detect-terminal.
+    #[arg(long)]
+    pub verbose: bool,
+    /// Only use ASCII characters for bullets, rules, and other decorations.
+    #[arg(long)]
+    pub ascii: bool,
+    /// Remove OSC sequences, e.g. links and images, from output, but keep colours.
+    #[arg(long)]
+    pub strip_osc: bool,
+    /// Do not wrap links and images for tmux, even if mdcat runs inside tmux.
+    #[arg(long)]
+    pub no_tmux_passthrough: bool,
+    /// Do not add blank lines between blocks, e.g. paragraphs.
+    #[arg(long)]
+    pub compact: bool,
+    /// Write the dimensions and format of rendered images below the image.
+    #[arg(long)]
+    pub image_dimensions: bool,
+    /// Keep line breaks within paragraphs instead of reflowing paragraphs.
+    #[arg(long)]
+    pub preserve_breaks: bool,
+    /// Use the given image protocol instead of the protocol of the detected terminal.
+    #[arg(long, value_name = "PROTOCOL", value_enum)]
+    pub image_protocol: Option<ImageProtocol>,
+    /// Render HTML <img> and <a> tags as images and links.
+    #[arg(long)]
+    pub render_html_tags: bool,
+    /// Read a JSON list of markdown events instead of markdown.
+    #[arg(long, conflicts_with = "lines")]
+    pub events_json: bool,
+    /// Write markdown events as JSON list instead of rendering markdown.
+    #[arg(long)]
+    pub dump_events: bool,
+    /// Only list the URLs of all images and links, and whether mdcat may read them, instead of
+    /// rendering markdown.
+    #[arg(long, conflicts_with_all = ["dump_events", "toc"])]
+    pub list_resources: bool,
+    /// Print a note about unknown languages of code blocks to standard error.
+    #[arg(long)]
+    pub warn_unknown_languages: bool,
     /// Skip terminal detection and only use ANSI formatting.
     #[arg(long = "ansi", conflicts_with = "no_colour")]
     pub ansi_only: bool,
@@ -113,32 +357,121 @@ pub struct CommonArgs {
 }
 
 /// What resources mdcat may access.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ResourceAccess {
     /// Only allow local resources.
     LocalOnly,
     /// Allow remote resources
     Remote,
+    /// Only allow remote resources over HTTPS.
+    RemoteHttpsOnly,
+}
+
+/// Resolve a pair of flags which turn a setting on and off, falling back to `default`.
+///
+/// The flags override each other, so at most one of them is set.
+fn flag(on: bool, off: bool, default: bool) -> bool {
+    on || (default && !off)
 }
 
 impl CommonArgs {
     /// Whether remote resource access is permitted.
-    pub fn resource_access(&self) -> ResourceAccess {
-        if self.local_only {
+    ///
+    /// Only permit local access if `local` is the default, unless `--remote` is given.
+    pub fn resource_access(&self, local: bool) -> ResourceAccess {
+        if flag(self.local_only, self.remote, local) {
             ResourceAccess::LocalOnly
+        } else if self.https_only {
+            ResourceAccess::RemoteHttpsOnly
         } else {
             ResourceAccess::Remote
         }
     }
+
+    /// Whether to confine local file access to the directory of the document.
+    ///
+    /// `unconfined` is the default from the configuration.
+    pub fn confined(&self, unconfined: bool) -> bool {
+        !flag(self.unconfined, self.confined, unconfined)
+    }
+
+    /// Apply the markdown extensions from the command line to the `defaults` from the
+    /// configuration.
+    pub fn markdown(&self, defaults: MarkdownConfig) -> MarkdownConfig {
+        MarkdownConfig {
+            math: flag(self.math, self.no_math, defaults.math),
+            autolinks: flag(self.autolinks, self.no_autolinks, defaults.autolinks),
+            front_matter: self.front_matter_title
+                || flag(
+                    self.front_matter,
+                    self.no_front_matter,
+                    defaults.front_matter,
+                ),
+            ..defaults
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Args;
-    use clap::CommandFactory;
+    use super::{parse_line_range, parse_syntax_theme, Args, LineRange, ResourceAccess};
+    use crate::config::MarkdownConfig;
+    use clap::{CommandFactory, Parser};
 
     #[test]
     fn verify_app() {
         Args::command().debug_assert();
     }
+
+    #[test]
+    fn line_range() {
+        assert_eq!(
+            parse_line_range("10-40"),
+            Ok(LineRange {
+                start: 10,
+                end: Some(40)
+            })
+        );
+        assert_eq!(
+            parse_line_range("10-"),
+            Ok(LineRange {
+                start: 10,
+                end: None
+            })
+        );
+        for invalid in ["", "10", "-40", "0-10", "40-10", "a-b"] {
+            assert!(parse_line_range(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn later_flags_override_earlier_flags() {
+        let Args { command } = Args::try_parse_from([
+            "mdcat",
+            "--math",
+            "--no-math",
+            "--no-autolinks",
+            "--autolinks",
+        ])
+        .unwrap();
+        let markdown = command.markdown(MarkdownConfig {
+            math: true,
+            autolinks: false,
+            ..MarkdownConfig::default()
+        });
+        assert!(!markdown.math);
+        assert!(markdown.autolinks);
+        let Args { command } = Args::try_parse_from(["mdcat", "--remote", "--local"]).unwrap();
+        assert_eq!(command.resource_access(false), ResourceAccess::LocalOnly);
+        let Args { command } = Args::try_parse_from(["mdcat", "--local", "--remote"]).unwrap();
+        assert_eq!(command.resource_access(true), ResourceAccess::Remote);
+    }
+
+    #[test]
+    fn syntax_theme() {
+        let theme = parse_syntax_theme("Solarized (dark)").unwrap();
+        assert_eq!(theme.name.as_deref(), Some("Solarized (dark)"));
+        let error = parse_syntax_theme("Solarised").unwrap_err();
+        assert!(error.contains("InspiredGitHub"), "{error}");
+    }
 }
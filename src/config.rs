@@ -0,0 +1,220 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use pulldown_cmark::Options;
+use pulldown_cmark_mdcat::default_parser_options;
+use serde::Deserialize;
+use tracing::{event, Level};
+
+/// Markdown extensions to enable.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MarkdownConfig {
+    /// Enable GFM task lists.
+    pub tasklists: bool,
+    /// Enable GFM strikethrough.
+    pub strikethrough: bool,
+    /// Enable GFM tables.
+    pub tables: bool,
+    /// Enable inline and display math, i.e. `$...$` and `$$...$$`.
+    pub math: bool,
+    /// Turn bare URLs into links, like GFM autolink literals.
+    pub autolinks: bool,
+    /// Parse YAML or TOML front matter at the start of documents, to hide it.
+    pub front_matter: bool,
+}
+
+impl Default for MarkdownConfig {
+    /// Enable the extensions of [`default_parser_options`].
+    fn default() -> Self {
+        let defaults = default_parser_options();
+        Self {
+            tasklists: defaults.contains(Options::ENABLE_TASKLISTS),
+            strikethrough: defaults.contains(Options::ENABLE_STRIKETHROUGH),
+            tables: defaults.contains(Options::ENABLE_TABLES),
+            math: defaults.contains(Options::ENABLE_MATH),
+            autolinks: false,
+            front_matter: defaults.contains(Options::ENABLE_YAML_STYLE_METADATA_BLOCKS),
+        }
+    }
+}
+
+impl MarkdownConfig {
+    /// Get the parser options for this configuration.
+    pub fn parser_options(&self) -> Options {
+        let mut options = Options::empty();
+        options.set(Options::ENABLE_TASKLISTS, self.tasklists);
+        options.set(Options::ENABLE_STRIKETHROUGH, self.strikethrough);
+        options.set(Options::ENABLE_TABLES, self.tables);
+        options.set(Options::ENABLE_MATH, self.math);
+        options.set(
+            Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
+                | Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS,
+            self.front_matter,
+        );
+        options
+    }
+}
+
+/// Persistent defaults for mdcat.
+///
+/// Command line arguments take precedence over all values in the configuration.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// Maximum number of columns to use for output.
+    pub columns: Option<u16>,
+    /// Maximum number of columns to use for output in a pager; 0 to use the full terminal width.
+    pub pager_columns: Option<u16>,
+    /// Expand tabs in code blocks to the given number of columns.
+    pub tab_width: Option<u16>,
+    /// The name of a built-in syntax theme to highlight code with.
+    pub syntax_theme: Option<String>,
+    /// The pager command, subject to shell-like word splitting.
+    ///
+    /// `$MDCAT_PAGER` and `$PAGER` take precedence over this command.
+    pub pager: Option<String>,
+    /// Fail to render input files larger than the given number of bytes.
+    pub max_input_size: Option<u64>,
+    /// Do not load remote resources like images.
+    pub local: bool,
+    /// Allow reading local files outside the directory of the document, even if `local` is set.
+    pub unconfined: bool,
+    /// Markdown extensions.
+    pub markdown: MarkdownConfig,
+}
+
+impl Config {
+    /// Get the path of the configuration file.
+    ///
+    /// Use `$MDCAT_CONFIG` if set, and `mdcat/config.toml` in the XDG config directory otherwise,
+    /// i.e. `$XDG_CONFIG_HOME` or `$HOME/.config`.
+    ///
+    /// Return `None` if `$MDCAT_CONFIG` is empty, or if neither `$XDG_CONFIG_HOME` nor `$HOME`
+    /// are set.
+    pub fn path() -> Option<PathBuf> {
+        match std::env::var_os("MDCAT_CONFIG") {
+            Some(path) if path.is_empty() => None,
+            Some(path) => Some(PathBuf::from(path)),
+            None => std::env::var_os("XDG_CONFIG_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+                .map(|dir| dir.join("mdcat").join("config.toml")),
+        }
+    }
+
+    /// Parse a configuration from the given TOML `contents`.
+    pub fn parse(contents: &str) -> Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    /// Load the configuration from `path`.
+    ///
+    /// Return the default configuration if `path` does not exist.
+    pub fn load_from(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                event!(
+                    Level::DEBUG,
+                    "Loading configuration from {}",
+                    path.display()
+                );
+                Self::parse(&contents)
+                    .with_context(|| format!("Failed to parse configuration {}", path.display()))
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                event!(Level::DEBUG, "No configuration at {}", path.display());
+                Ok(Self::default())
+            }
+            Err(error) => Err(error)
+                .with_context(|| format!("Failed to read configuration {}", path.display())),
+        }
+    }
+
+    /// Load the configuration from the default [`Config::path`].
+    pub fn load() -> Result<Self> {
+        Self::path().map_or_else(|| Ok(Self::default()), |path| Self::load_from(&path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pulldown_cmark::Options;
+    use similar_asserts::assert_eq;
+
+    use super::{Config, MarkdownConfig};
+
+    #[test]
+    fn empty_config_is_default() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(
+            config.markdown.parser_options(),
+            Options::ENABLE_TASKLISTS | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES
+        );
+        assert_eq!(
+            config.markdown.parser_options(),
+            pulldown_cmark_mdcat::default_parser_options()
+        );
+    }
+
+    #[test]
+    fn parse_all_fields() {
+        let config = Config::parse(
+            r#"columns = 100
+pager-columns = 0
+tab-width = 4
+syntax-theme = "Solarized (dark)"
+pager = "less -R --quit-if-one-screen"
+local = true
+
+[markdown]
+tables = false
+math = true
+autolinks = true
+front-matter = true
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                columns: Some(100),
+                pager_columns: Some(0),
+                tab_width: Some(4),
+                syntax_theme: Some("Solarized (dark)".into()),
+                pager: Some("less -R --quit-if-one-screen".into()),
+                max_input_size: None,
+                local: true,
+                unconfined: false,
+                markdown: MarkdownConfig {
+                    tasklists: true,
+                    strikethrough: true,
+                    tables: false,
+                    math: true,
+                    autolinks: true,
+                    front_matter: true,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn reject_unknown_fields() {
+        let error = Config::parse("colums = 100").unwrap_err();
+        assert!(error.to_string().contains("colums"), "{error}");
+    }
+
+    #[test]
+    fn load_missing_file_returns_default() {
+        let config = Config::load_from("/does/not/exist/config.toml".as_ref()).unwrap();
+        assert_eq!(config, Config::default());
+    }
+}
@@ -12,103 +12,495 @@
 #![deny(warnings, missing_docs, clippy::all)]
 #![forbid(unsafe_code)]
 
+use std::cell::OnceCell;
 use std::fs::File;
 use std::io::stdin;
 use std::io::{prelude::*, BufWriter};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Result};
-use pulldown_cmark::{Options, Parser};
+use anyhow::{anyhow, bail, Context, Result};
+use pulldown_cmark::{Event, LinkType, Parser, Tag};
+use pulldown_cmark_mdcat::autolinks::autolink_literals;
 use pulldown_cmark_mdcat::resources::{
     DispatchingResourceHandler, FileResourceHandler, ResourceUrlHandler,
 };
 use pulldown_cmark_mdcat::{Environment, Settings};
-use resources::CurlResourceHandler;
+use resources::{CurlResourceHandler, ResourceHandlerConfig};
+use syntect::parsing::SyntaxSet;
 use tracing::{event, instrument, Level};
+use url::Url;
 
-use args::ResourceAccess;
-use output::Output;
+use args::{LineRange, ResourceAccess};
+use config::MarkdownConfig;
+use output::{Output, StripOsc};
+use toc::table_of_contents;
 
 /// Argument parsing for mdcat.
 #[allow(missing_docs)]
 pub mod args;
+/// Configuration file for mdcat.
+pub mod config;
 /// Output handling for mdcat.
 pub mod output;
 /// Resource handling for mdca.
 pub mod resources;
+/// Terminal size detection for mdcat.
+pub mod size;
+/// Table of contents for documents.
+pub mod toc;
+/// Watch files for changes.
+pub mod watch;
 
 /// Default read size limit for resources.
 pub static DEFAULT_RESOURCE_READ_LIMIT: u64 = 104_857_600;
 
+/// Default size limit for input files.
+pub static DEFAULT_INPUT_SIZE_LIMIT: u64 = 104_857_600;
+
+/// Decode the raw `contents` of `filename`.
+///
+/// Strip a leading UTF-8 byte order mark, and replace invalid UTF-8 sequences with the unicode
+/// replacement character.
+///
+/// Normalize CRLF and CR line endings to LF, to avoid stray carriage returns in rendered text.
+///
+/// Return the decoded contents, and whether `contents` contained invalid UTF-8.
+fn decode_input(filename: &str, contents: Vec<u8>) -> (String, bool) {
+    let contents = match contents.strip_prefix(b"\xef\xbb\xbf") {
+        Some(stripped) => stripped.to_vec(),
+        None => contents,
+    };
+    let (contents, invalid_utf8) = match String::from_utf8(contents) {
+        Ok(contents) => (contents, false),
+        Err(error) => {
+            event!(Level::WARN, %error, "{} is not valid UTF-8", filename);
+            (String::from_utf8_lossy(error.as_bytes()).into_owned(), true)
+        }
+    };
+    if contents.contains('\r') {
+        (
+            contents.replace("\r\n", "\n").replace('\r', "\n"),
+            invalid_utf8,
+        )
+    } else {
+        (contents, invalid_utf8)
+    }
+}
+
 /// Read input for `filename`.
 ///
 /// If `filename` is `-` read from standard input, otherwise try to open and
 /// read the given file.
-pub fn read_input<T: AsRef<str>>(filename: T) -> Result<(PathBuf, String)> {
+///
+/// pulldown-cmark requires the entire document in memory, so this function reads the entire input
+/// at once.  To bound memory usage fail if the input exceeds `size_limit` bytes.
+///
+/// Strip a leading byte order mark, lossily convert invalid UTF-8 input, and normalize line
+/// endings.
+///
+/// Return the base directory of the input, its contents, and whether the input contained invalid
+/// UTF-8, to let the caller warn about replaced bytes.
+pub fn read_input<T: AsRef<str>>(filename: T, size_limit: u64) -> Result<(PathBuf, String, bool)> {
     let cd = std::env::current_dir()?;
-    let mut buffer = String::new();
-
-    if filename.as_ref() == "-" {
-        stdin().read_to_string(&mut buffer)?;
-        Ok((cd, buffer))
+    let (base_dir, source): (PathBuf, Box<dyn Read>) = if filename.as_ref() == "-" {
+        (cd, Box::new(stdin()))
     } else {
-        let mut source = File::open(filename.as_ref())?;
-        source.read_to_string(&mut buffer)?;
+        let source = File::open(filename.as_ref())?;
         let base_dir = cd
             .join(filename.as_ref())
             .parent()
             .map(|p| p.to_path_buf())
             .unwrap_or(cd);
-        Ok((base_dir, buffer))
+        (base_dir, Box::new(source))
+    };
+    let mut buffer = Vec::new();
+    // Read a byte more than the limit to differentiate an expected EOF from hitting the limit
+    source
+        .take(size_limit.saturating_add(1))
+        .read_to_end(&mut buffer)?;
+    if size_limit < buffer.len() as u64 {
+        bail!("Input exceeds size limit of {size_limit} bytes");
+    }
+    let (contents, invalid_utf8) = decode_input(filename.as_ref(), buffer);
+    Ok((base_dir, contents, invalid_utf8))
+}
+
+/// The fence of a fenced code block, i.e. the fence character and its length.
+type Fence = (char, usize);
+
+/// Get the fence of a code block if `line` starts or ends a fenced code block.
+///
+/// Only recognize top-level fences, i.e. fences with at most three spaces of indentation.
+fn code_fence(line: &str) -> Option<(Fence, &str)> {
+    let unindented = line.trim_start_matches(' ');
+    if 3 < line.len() - unindented.len() {
+        return None;
+    }
+    let fence_char = unindented
+        .chars()
+        .next()
+        .filter(|c| *c == '`' || *c == '~')?;
+    let rest = unindented.trim_start_matches(fence_char);
+    let length = unindented.len() - rest.len();
+    (3 <= length).then_some(((fence_char, length), rest.trim()))
+}
+
+/// Select the given `lines` of `input`.
+///
+/// If the first selected line is inside a fenced code block, start the selection with the
+/// opening fence of this block.  If the selection ends inside a fenced code block close it.
+///
+/// Return the selected lines, and whether the selection cut a code block.
+fn select_lines(input: &str, lines: LineRange) -> (String, bool) {
+    let mut selected = String::new();
+    let mut cut_code_block = false;
+    // The fence and opening line of the current code block, if any
+    let mut open_block: Option<(Fence, &str)> = None;
+    for (index, line) in input.split_inclusive('\n').enumerate() {
+        let number = index + 1;
+        if lines.end.is_some_and(|end| end < number) {
+            break;
+        }
+        if number == lines.start {
+            if let Some((_, opening_line)) = open_block {
+                selected.push_str(opening_line);
+                cut_code_block = true;
+            }
+        }
+        if lines.contains(number) {
+            selected.push_str(line);
+        }
+        open_block = match (open_block, code_fence(line)) {
+            (None, Some((fence, _))) => Some((fence, line)),
+            // A closing fence has at least as many fence characters, and no info string
+            (Some(((c, length), _)), Some(((closing_c, closing_length), "")))
+                if c == closing_c && length <= closing_length =>
+            {
+                None
+            }
+            (open_block, _) => open_block,
+        };
+    }
+    if let Some(((c, length), _)) = open_block {
+        if lines.start <= input.lines().count() {
+            if !selected.is_empty() && !selected.ends_with('\n') {
+                selected.push('\n');
+            }
+            selected.push_str(&c.to_string().repeat(length));
+            selected.push('\n');
+            cut_code_block = true;
+        }
+    }
+    (selected, cut_code_block)
+}
+
+/// Syntax definitions to highlight code blocks with, loaded on first use.
+///
+/// Loading the default syntax definitions takes a noticeable amount of time, which is wasted on
+/// documents without any code blocks.
+#[derive(Debug, Default)]
+pub struct LazySyntaxSet {
+    empty: SyntaxSet,
+    defaults: OnceCell<SyntaxSet>,
+}
+
+impl LazySyntaxSet {
+    /// An empty syntax set, for documents without code to highlight.
+    pub fn empty(&self) -> &SyntaxSet {
+        &self.empty
+    }
+
+    /// The default syntax set, loaded on first access.
+    pub fn defaults(&self) -> &SyntaxSet {
+        self.defaults.get_or_init(SyntaxSet::load_defaults_newlines)
+    }
+}
+
+/// Whether `input` may contain a fenced code block.
+///
+/// This does not parse markdown, and thus may have false positives, but no false negatives.
+pub fn may_contain_fenced_code(input: &str) -> bool {
+    input.contains("```") || input.contains("~~~")
+}
+
+/// Options for processing a file.
+#[derive(Debug, Clone)]
+pub struct ProcessOptions {
+    /// Only render a table of contents of all headings in the document, see
+    /// [`table_of_contents`].
+    pub toc: bool,
+    /// Remove all OSC sequences, e.g. links and images, from the output.
+    pub strip_osc: bool,
+    /// Read a JSON list of markdown events instead of markdown, see [`read_events_json`].
+    pub events_json: bool,
+    /// Write the markdown events as JSON list instead of rendering them, see
+    /// [`write_events_json`].
+    pub dump_events: bool,
+    /// Only write the URLs of all images and links, and whether the resource configuration
+    /// permits reading them, see [`write_resource_list`].
+    pub list_resources: bool,
+    /// Write a note which lists unknown languages of code blocks to standard error after
+    /// rendering, see [`pulldown_cmark_mdcat::RenderSummary::unknown_languages`].
+    pub warn_unknown_languages: bool,
+    /// Only render these lines of the document.
+    ///
+    /// If the range cuts a fenced code block, open or close the block, and warn about it.
+    pub lines: Option<LineRange>,
+    /// Fail if the input exceeds this number of bytes.
+    pub input_size_limit: u64,
+    /// The hostname for `file://` links, instead of the hostname of the local system.
+    pub hostname: Option<String>,
+}
+
+impl Default for ProcessOptions {
+    /// Render markdown without any of the optional processing, up to the default size limit.
+    fn default() -> Self {
+        Self {
+            toc: false,
+            strip_osc: false,
+            events_json: false,
+            dump_events: false,
+            list_resources: false,
+            warn_unknown_languages: false,
+            lines: None,
+            input_size_limit: DEFAULT_INPUT_SIZE_LIMIT,
+            hostname: None,
+        }
     }
 }
 
 /// Process a single file.
 ///
-/// Read from `filename` and render the contents to `output`.
-#[instrument(skip(output, settings, resource_handler), level = "debug")]
-pub fn process_file(
+/// Read `filename`, parse it with the extensions enabled in `markdown`, and render the contents
+/// to `output`, according to `options`.
+///
+/// Configure resource access for the document with `resource_config`, from the base directory of
+/// the document, and build the resource handler from this configuration, see
+/// [`build_resource_handler`].
+///
+/// Highlight code blocks with the default syntax definitions from `syntax_set`, but skip loading
+/// these if the document has no fenced code blocks.
+///
+/// `list_resources` takes precedence over `dump_events`.
+#[instrument(skip(output, settings, syntax_set, resource_config), level = "debug")]
+pub fn process_file<'a, F>(
     filename: &str,
-    settings: &Settings,
-    resource_handler: &dyn ResourceUrlHandler,
+    settings: &mut Settings<'a>,
+    syntax_set: &'a LazySyntaxSet,
+    markdown: &MarkdownConfig,
+    options: &ProcessOptions,
+    resource_config: F,
     output: &mut Output,
-) -> Result<()> {
-    let (base_dir, input) = read_input(filename)?;
+) -> Result<()>
+where
+    F: FnOnce(&Path) -> ResourceHandlerConfig,
+{
+    let ProcessOptions {
+        toc,
+        strip_osc,
+        events_json,
+        dump_events,
+        list_resources,
+        warn_unknown_languages,
+        lines,
+        input_size_limit,
+        ref hostname,
+    } = *options;
+    let (base_dir, input, invalid_utf8) = read_input(filename, input_size_limit)?;
+    if invalid_utf8 {
+        eprintln!("Warning: {filename}: Invalid UTF-8, replacing invalid bytes");
+    }
     event!(
         Level::TRACE,
         "Read input, using {} as base directory",
         base_dir.display()
     );
-    let parser = Parser::new_ext(
-        &input,
-        Options::ENABLE_TASKLISTS | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES,
-    );
+    let input = match lines {
+        Some(lines) => {
+            let (selected, cut_code_block) = select_lines(&input, lines);
+            if cut_code_block {
+                event!(Level::WARN, %lines, "Lines cut a fenced code block");
+                eprintln!("Warning: {filename}: Lines {lines} cut a fenced code block");
+            }
+            selected
+        }
+        None => input,
+    };
+    // We can't cheaply tell whether events or indented code need highlighting, so always load
+    // syntax definitions for these.
+    settings.syntax_set =
+        if events_json || settings.infer_indented_code_syntax || may_contain_fenced_code(&input) {
+            syntax_set.defaults()
+        } else {
+            event!(
+                Level::TRACE,
+                "No fenced code blocks, skip loading syntax definitions"
+            );
+            syntax_set.empty()
+        };
+    let resource_config = resource_config(&base_dir);
+    let events: Box<dyn Iterator<Item = Event>> = if events_json {
+        Box::new(read_events_json(&input)?.into_iter())
+    } else {
+        Box::new(Parser::new_ext(&input, markdown.parser_options()))
+    };
+    let events: Box<dyn Iterator<Item = Event>> = if markdown.autolinks {
+        Box::new(autolink_literals(events))
+    } else {
+        events
+    };
+    let events: Box<dyn Iterator<Item = Event>> = if toc {
+        Box::new(table_of_contents(events).into_iter())
+    } else {
+        events
+    };
     let env = Environment::for_local_directory(&base_dir)?;
+    let env = match hostname {
+        Some(hostname) => env.with_hostname(hostname)?,
+        None => env,
+    };
 
-    let mut sink = BufWriter::new(output.writer());
-    pulldown_cmark_mdcat::push_tty(settings, &env, resource_handler, &mut sink, parser)
-        .and_then(|_| {
-            event!(Level::TRACE, "Finished rendering, flushing output");
-            sink.flush()
-        })
-        .or_else(|error| {
-            if error.kind() == std::io::ErrorKind::BrokenPipe {
-                event!(Level::TRACE, "Ignoring broken pipe");
-                Ok(())
-            } else {
-                event!(Level::ERROR, ?error, "Failed to process file: {:#}", error);
-                Err(error)
-            }
-        })?;
+    let mut stripped;
+    let writer = if strip_osc {
+        stripped = StripOsc::new(output.writer());
+        &mut stripped as &mut dyn Write
+    } else {
+        output.writer()
+    };
+    let mut sink = BufWriter::new(writer);
+    let mut unknown_languages = Vec::new();
+    let result = if list_resources {
+        write_resource_list(&mut sink, events, &env, &resource_config)
+    } else if dump_events {
+        write_events_json(&mut sink, events)
+    } else {
+        let resource_handler = build_resource_handler(&resource_config)?;
+        pulldown_cmark_mdcat::push_tty(settings, &env, &resource_handler, &mut sink, events)
+            .map(|summary| unknown_languages = summary.unknown_languages)
+    }
+    .and_then(|_| {
+        event!(Level::TRACE, "Finished rendering, flushing output");
+        sink.flush()
+    });
+    pulldown_cmark_mdcat::ignore_broken_pipe(result).inspect_err(|error| {
+        event!(Level::ERROR, ?error, "Failed to process file: {:#}", error);
+    })?;
+    if warn_unknown_languages && !unknown_languages.is_empty() {
+        eprintln!(
+            "Unknown languages of code blocks: {}",
+            unknown_languages.join(", ")
+        );
+    }
     Ok(())
 }
 
-/// Create the resource handler for mdcat.
-pub fn create_resource_handler(access: ResourceAccess) -> Result<DispatchingResourceHandler> {
-    let mut resource_handlers: Vec<Box<dyn ResourceUrlHandler>> = vec![Box::new(
-        FileResourceHandler::new(DEFAULT_RESOURCE_READ_LIMIT),
-    )];
-    if let ResourceAccess::Remote = access {
+/// Read a JSON list of markdown events from `input`.
+///
+/// Events use the serde representation of [`pulldown_cmark::Event`], e.g.
+/// `{"Start":"Paragraph"}`, `{"Text":"Hello"}`, or `"SoftBreak"`.
+///
+/// Fail if start and end events do not match up, because the renderer expects events from a
+/// parser.
+pub fn read_events_json(input: &str) -> Result<Vec<Event<'_>>> {
+    let events: Vec<Event<'_>> = serde_json::from_str(input)
+        .map_err(|error| anyhow!("Failed to read markdown events from JSON: {error}"))?;
+    let mut open_tags = Vec::new();
+    for (index, event) in events.iter().enumerate() {
+        match event {
+            Event::Start(tag) => open_tags.push(tag.to_end()),
+            Event::End(end) => match open_tags.pop() {
+                Some(expected) if expected == *end => {}
+                Some(expected) => {
+                    bail!("Event {index} ends {end:?}, but expected end of {expected:?}")
+                }
+                None => bail!("Event {index} ends {end:?}, but no element is open"),
+            },
+            _ => {}
+        }
+    }
+    if let Some(end) = open_tags.pop() {
+        bail!("Events end before the end of {end:?}");
+    }
+    Ok(events)
+}
+
+/// Write `events` as JSON list to `writer`, with one event per line.
+///
+/// See [`read_events_json`] for the format of events.
+pub fn write_events_json<'a, W: Write, I: Iterator<Item = Event<'a>>>(
+    writer: &mut W,
+    events: I,
+) -> std::io::Result<()> {
+    write!(writer, "[")?;
+    for (index, event) in events.enumerate() {
+        if 0 < index {
+            write!(writer, ",")?;
+        }
+        writeln!(writer)?;
+        serde_json::to_writer(&mut *writer, &event)?;
+    }
+    writeln!(writer, "\n]")
+}
+
+/// Write the URLs of all images and links in `events` to `writer`, one per line.
+///
+/// Resolve relative URLs against the base URL of `env`, and prefix every URL with `allowed` or
+/// `denied`, depending on whether `config` permits reading the URL.  This does not read any
+/// resource.  Prefix invalid URLs which mdcat cannot resolve with `denied` as well.
+pub fn write_resource_list<'a, W: Write, I: Iterator<Item = Event<'a>>>(
+    writer: &mut W,
+    events: I,
+    env: &Environment,
+    config: &ResourceHandlerConfig,
+) -> std::io::Result<()> {
+    for event in events {
+        let (link_type, dest_url) = match event {
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                ..
+            })
+            | Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                ..
+            }) => (link_type, dest_url),
+            _ => continue,
+        };
+        let url = if link_type == LinkType::Email {
+            Url::parse(&format!("mailto:{dest_url}")).ok()
+        } else {
+            env.resolve_reference(&dest_url)
+        };
+        match url {
+            Some(url) if config.permits(&url) => writeln!(writer, "allowed {url}")?,
+            Some(url) => writeln!(writer, "denied {url}")?,
+            None => writeln!(writer, "denied {dest_url}")?,
+        }
+    }
+    Ok(())
+}
+
+/// Build the resource handler for mdcat from `config`.
+///
+/// Always read local files, and read remote resources over curl if `config.access` permits.
+pub fn build_resource_handler(
+    config: &ResourceHandlerConfig,
+) -> Result<DispatchingResourceHandler> {
+    let file_handler = match &config.file_root {
+        Some(root) => {
+            event!(
+                target: "mdcat::main",
+                Level::DEBUG,
+                "Confining local file access to {}",
+                root.display()
+            );
+            FileResourceHandler::new(config.file_read_limit).confined_to(root)
+        }
+        None => FileResourceHandler::new(config.file_read_limit),
+    };
+    let mut resource_handlers: Vec<Box<dyn ResourceUrlHandler>> = vec![Box::new(file_handler)];
+    if let ResourceAccess::Remote | ResourceAccess::RemoteHttpsOnly = config.access {
         let user_agent = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
         event!(
             target: "mdcat::main",
@@ -116,9 +508,191 @@ pub fn create_resource_handler(access: ResourceAccess) -> Result<DispatchingReso
             "Remote resource access permitted, creating HTTP client with user agent {}",
             user_agent
         );
-        let client = CurlResourceHandler::create(DEFAULT_RESOURCE_READ_LIMIT, user_agent)
-            .with_context(|| "Failed to build HTTP client".to_string())?;
+        let client = CurlResourceHandler::create(config.remote_read_limit, user_agent)
+            .and_then(|client| client.with_timeouts(config.timeout, config.connect_timeout))
+            .with_context(|| "Failed to build HTTP client".to_string())?
+            .with_schemes(config.remote_schemes.clone())
+            .with_retries(config.retries);
+        let client = match config.access {
+            ResourceAccess::RemoteHttpsOnly => client.https_only(),
+            _ => client,
+        };
         resource_handlers.push(Box::new(client));
     }
     Ok(DispatchingResourceHandler::new(resource_handlers))
 }
+
+/// Create the resource handler for mdcat.
+///
+/// Read at most `file_read_limit` bytes from local files, and at most `remote_read_limit` bytes
+/// from remote resources, if `access` permits remote resources at all.
+///
+/// If `file_root` is given only read local files within this directory.
+///
+/// Use the defaults of [`ResourceHandlerConfig`] for everything else, see
+/// [`build_resource_handler`].
+pub fn create_resource_handler(
+    access: ResourceAccess,
+    file_read_limit: u64,
+    remote_read_limit: u64,
+    file_root: Option<&Path>,
+) -> Result<DispatchingResourceHandler> {
+    build_resource_handler(&ResourceHandlerConfig {
+        access,
+        file_read_limit,
+        file_root: file_root.map(Path::to_path_buf),
+        remote_read_limit,
+        ..ResourceHandlerConfig::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use similar_asserts::assert_eq;
+
+    use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+
+    use super::{
+        decode_input, may_contain_fenced_code, read_events_json, select_lines, write_events_json,
+    };
+    use crate::args::LineRange;
+
+    #[test]
+    fn decode_input_strips_bom() {
+        assert_eq!(
+            decode_input("-", b"\xef\xbb\xbf# Hello".to_vec()),
+            ("# Hello".to_owned(), false)
+        );
+    }
+
+    #[test]
+    fn decode_input_normalizes_line_endings() {
+        assert_eq!(
+            decode_input("-", b"# Hello\r\n\r\nWorld\rand\r\nmore".to_vec()),
+            ("# Hello\n\nWorld\nand\nmore".to_owned(), false)
+        );
+    }
+
+    fn lines(start: usize, end: Option<usize>) -> LineRange {
+        LineRange { start, end }
+    }
+
+    #[test]
+    fn select_lines_in_range() {
+        let input = "one\ntwo\nthree\nfour";
+        assert_eq!(
+            select_lines(input, lines(2, Some(3))),
+            ("two\nthree\n".to_owned(), false)
+        );
+        assert_eq!(
+            select_lines(input, lines(3, None)),
+            ("three\nfour".to_owned(), false)
+        );
+        assert_eq!(select_lines(input, lines(10, None)), (String::new(), false));
+    }
+
+    #[test]
+    fn select_lines_closes_cut_code_block() {
+        let input = "Text\n\n````rust\nfn main() {\n```\n}\n````\n\nMore";
+        assert_eq!(
+            select_lines(input, lines(1, Some(4))),
+            ("Text\n\n````rust\nfn main() {\n````\n".to_owned(), true)
+        );
+    }
+
+    #[test]
+    fn select_lines_opens_cut_code_block() {
+        let input = "Text\n\n~~~ rust\nfn main() {\n}\n~~~\n\nMore";
+        assert_eq!(
+            select_lines(input, lines(4, None)),
+            ("~~~ rust\nfn main() {\n}\n~~~\n\nMore".to_owned(), true)
+        );
+        assert_eq!(
+            select_lines(input, lines(4, Some(4))),
+            ("~~~ rust\nfn main() {\n~~~\n".to_owned(), true)
+        );
+    }
+
+    #[test]
+    fn select_lines_keeps_complete_code_block() {
+        let input = "Text\n\n```\ncode\n```\n\nMore";
+        assert_eq!(
+            select_lines(input, lines(3, Some(5))),
+            ("```\ncode\n```\n".to_owned(), false)
+        );
+    }
+
+    #[test]
+    fn fenced_code_in_input() {
+        assert!(may_contain_fenced_code(
+            "Text\n\n```rust\nfn main() {}\n```\n"
+        ));
+        assert!(may_contain_fenced_code("> ~~~\n> code\n> ~~~\n"));
+        assert!(!may_contain_fenced_code(
+            "# Heading\n\nSome `code` and text.\n"
+        ));
+    }
+
+    #[test]
+    fn decode_input_replaces_invalid_utf8() {
+        assert_eq!(
+            decode_input("-", b"Hello \xff World".to_vec()),
+            ("Hello \u{fffd} World".to_owned(), true)
+        );
+    }
+
+    #[test]
+    fn write_events_as_json() {
+        let mut sink = Vec::new();
+        write_events_json(&mut sink, Parser::new("Hello *\"World\"*")).unwrap();
+        assert_eq!(
+            String::from_utf8(sink).unwrap(),
+            r#"[
+{"Start":"Paragraph"},
+{"Text":"Hello "},
+{"Start":"Emphasis"},
+{"Text":"\"World\""},
+{"End":"Emphasis"},
+{"End":"Paragraph"}
+]
+"#
+        );
+    }
+
+    #[test]
+    fn read_events_from_json() {
+        assert_eq!(
+            read_events_json(r#"[{"Start":"Paragraph"},{"Text":"Hello \"World\""},"SoftBreak",{"End":"Paragraph"}]"#)
+                .unwrap(),
+            vec![
+                Event::Start(Tag::Paragraph),
+                Event::Text("Hello \"World\"".into()),
+                Event::SoftBreak,
+                Event::End(TagEnd::Paragraph),
+            ]
+        );
+    }
+
+    #[test]
+    fn roundtrip_events_through_json() {
+        let markdown = "# Heading\n\n* [link](https://example.com \"title\")\n* `code`\n\n```rust\nfn main() {}\n```\n";
+        let mut sink = Vec::new();
+        write_events_json(&mut sink, Parser::new(markdown)).unwrap();
+        let json = String::from_utf8(sink).unwrap();
+        assert_eq!(
+            read_events_json(&json).unwrap(),
+            Parser::new(markdown).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn read_invalid_events_json() {
+        let error = read_events_json(r#"[{"Start":"Paragraph"}"#).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("Failed to read markdown events from JSON: "),
+            "{error}"
+        );
+    }
+}
@@ -9,18 +9,96 @@
 
 //! Show CommonMark documents on TTYs.
 
+use std::io::IsTerminal;
+use std::path::Path;
+
 use clap::{CommandFactory, Parser};
 use clap_complete::generate;
-use mdcat::{create_resource_handler, process_file};
+use mdcat::{process_file, LazySyntaxSet, ProcessOptions, DEFAULT_INPUT_SIZE_LIMIT};
+use pulldown_cmark_mdcat::terminal::capabilities::halfblock::HalfBlockProtocol;
+use pulldown_cmark_mdcat::terminal::capabilities::iterm2::ITerm2Protocol;
+use pulldown_cmark_mdcat::terminal::capabilities::kitty::KittyGraphicsProtocol;
+use pulldown_cmark_mdcat::terminal::capabilities::terminology::Terminology;
+use pulldown_cmark_mdcat::terminal::capabilities::{
+    halfblock, ImageCapability, MarkCapability, Passthrough, TerminalCapabilities,
+};
 use pulldown_cmark_mdcat::terminal::{TerminalProgram, TerminalSize};
-use pulldown_cmark_mdcat::{Settings, Theme};
-use syntect::parsing::SyntaxSet;
+use pulldown_cmark_mdcat::{Glyphs, LinkReferencePlacement, Settings, Theme, SYNTECT_REGEX_ENGINE};
 use tracing::{event, Level};
 use tracing_subscriber::filter::LevelFilter;
 use tracing_subscriber::EnvFilter;
 
-use mdcat::args::Args;
-use mdcat::output::Output;
+use mdcat::args::{
+    long_version, parse_syntax_theme, Args, ImageProtocol, LinkStyle, ResourceAccess,
+};
+use mdcat::config::Config;
+use mdcat::output::{is_tmux, Output};
+use mdcat::resources::ResourceHandlerConfig;
+use mdcat::size::{max_columns, TerminalSizeCache, DEFAULT_PAGER_COLUMNS, DEFAULT_SIZE_MAX_AGE};
+use mdcat::watch::{watch_files, CLEAR_SCREEN};
+
+/// Print details about the capabilities of the terminal, and how mdcat detected the terminal.
+fn print_terminal_details(detected_from: &str, capabilities: &TerminalCapabilities) {
+    let yes_no = |supported| if supported { "yes" } else { "no" };
+    let image = |capability| match capability {
+        None => "none".to_owned(),
+        Some(ImageCapability::Terminology(_)) => "Terminology".to_owned(),
+        Some(ImageCapability::ITerm2(_)) => "iTerm2".to_owned(),
+        Some(ImageCapability::Kitty(kitty)) => format!("kitty ({:?} placement)", kitty.placement),
+        Some(ImageCapability::HalfBlock(_)) => "half blocks".to_owned(),
+    };
+    let marks = match capabilities.marks {
+        None => "none",
+        Some(MarkCapability::ITerm2(_)) => "iTerm2",
+    };
+    println!("Detected from: {detected_from}");
+    println!("Styles: {}", yes_no(capabilities.style.is_some()));
+    println!("Links: {}", yes_no(capabilities.supports_links()));
+    println!("Images: {}", image(capabilities.image));
+    println!("Marks: {marks}");
+    println!("Image fallback: {}", image(capabilities.image_fallback));
+    match TerminalSize::detect() {
+        Some(size) => println!("Size: {} columns, {} rows", size.columns, size.rows),
+        None => println!("Size: unknown"),
+    }
+}
+
+/// The image capability for an image `protocol` from the command line.
+fn image_capability(protocol: ImageProtocol) -> Option<ImageCapability> {
+    match protocol {
+        ImageProtocol::Kitty => Some(ImageCapability::Kitty(KittyGraphicsProtocol::default())),
+        ImageProtocol::ITerm2 => Some(ImageCapability::ITerm2(ITerm2Protocol::default())),
+        ImageProtocol::Terminology => Some(ImageCapability::Terminology(Terminology)),
+        ImageProtocol::HalfBlock => Some(ImageCapability::HalfBlock(HalfBlockProtocol)),
+        ImageProtocol::None => None,
+    }
+}
+
+/// Convert a link `style` argument into the corresponding rendering setting.
+fn link_style(style: LinkStyle) -> pulldown_cmark_mdcat::LinkStyle {
+    match style {
+        LinkStyle::Reference => pulldown_cmark_mdcat::LinkStyle::Reference,
+        LinkStyle::Inline => pulldown_cmark_mdcat::LinkStyle::Inline,
+        LinkStyle::Hidden => pulldown_cmark_mdcat::LinkStyle::Hidden,
+    }
+}
+
+/// Print the features mdcat was built with.
+fn print_features() {
+    let features = [
+        ("svg", cfg!(feature = "svg")),
+        ("image-processing", cfg!(feature = "image-processing")),
+        ("half-blocks", cfg!(feature = "half-blocks")),
+        ("regex-fancy", cfg!(feature = "regex-fancy")),
+        ("regex-onig", cfg!(feature = "regex-onig")),
+    ];
+    let features = features
+        .iter()
+        .map(|(name, enabled)| format!("{}{name}", if *enabled { '+' } else { '-' }))
+        .collect::<Vec<_>>();
+    println!("Features: {}", features.join(" "));
+    println!("Regex engine: {SYNTECT_REGEX_ENGINE}");
+}
 
 fn main() {
     // Initialize curl for remote resources
@@ -43,47 +121,155 @@ fn main() {
     event!(target: "mdcat::main", Level::TRACE, ?args, "mdcat arguments");
 
     if let Some(shell) = args.completions {
-        let binary = match args {
-            mdcat::args::Command::Mdcat { .. } => "mdcat",
-            mdcat::args::Command::Mdless { .. } => "mdless",
-        };
+        let binary = args.binary_name();
         let mut command = Args::command();
         let subcommand = command.find_subcommand_mut(binary).unwrap();
         generate(shell, subcommand, binary, &mut std::io::stdout());
         std::process::exit(0);
     }
 
-    let terminal = if args.no_colour {
-        TerminalProgram::Dumb
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("Error: {error:#}");
+            std::process::exit(1);
+        }
+    };
+    event!(target: "mdcat::main", Level::TRACE, ?config, "mdcat configuration");
+    let syntax_theme = match (&args.syntax_theme, &config.syntax_theme) {
+        (Some(theme), _) => Some(theme.clone()),
+        (None, Some(name)) => match parse_syntax_theme(name) {
+            Ok(theme) => Some(theme),
+            Err(error) => {
+                eprintln!("Error: Invalid syntax theme in configuration: {error}");
+                std::process::exit(1);
+            }
+        },
+        (None, None) => None,
+    };
+
+    // Render plain text if we do not write to a terminal, unless explicitly asked for formatting.
+    // --version and --detect-terminal describe the terminal, so never assume plain text for them.
+    let implicit_plain = !(args.force_colour
+        || args.ansi_only
+        || args.paginate()
+        || args.version
+        || args.detect_and_exit
+        || std::io::stdout().is_terminal());
+    let plain = args.plain || implicit_plain;
+    let detected_terminal = !(args.no_colour || plain || args.paginate() || args.ansi_only);
+    let (terminal, detected_from) = if args.no_colour {
+        (TerminalProgram::Dumb, "--no-colour".to_owned())
+    } else if plain {
+        let reason = if args.plain {
+            "--plain"
+        } else {
+            "standard output is not a terminal"
+        };
+        (TerminalProgram::Dumb, reason.to_owned())
     } else if args.paginate() || args.ansi_only {
         // A pager won't support any terminal-specific features
-        TerminalProgram::Ansi
+        let reason = if args.ansi_only { "--ansi" } else { "pager" };
+        (TerminalProgram::Ansi, reason.to_owned())
+    } else {
+        let (terminal, variable) = TerminalProgram::detect_with_variable();
+        let detected_from = variable.map_or_else(
+            || "no known terminal variable".to_owned(),
+            |(name, value)| format!("${name}={value}"),
+        );
+        (terminal, detected_from)
+    };
+    let mut terminal_capabilities = if detected_terminal && halfblock::is_truecolor() {
+        // Draw images with half blocks if the terminal lacks a proper image protocol
+        terminal.capabilities().with_half_block_image_fallback()
     } else {
-        TerminalProgram::detect()
+        terminal.capabilities()
     };
+    // Only replace the image protocol, and keep all other detected capabilities.  Only force
+    // images upon a terminal we detected though, and not upon pagers or plain output.
+    if let Some(protocol) = args.image_protocol.filter(|_| detected_terminal) {
+        terminal_capabilities.image = image_capability(protocol);
+    }
+    // Wrap links and images for tmux, to pass them on to the outer terminal, unless we strip them
+    // anyway.
+    let tmux_passthrough = !args.no_tmux_passthrough && !args.strip_osc && is_tmux();
+    event!(target: "mdcat::main", Level::DEBUG, tmux_passthrough, "tmux passthrough");
+    if tmux_passthrough {
+        terminal_capabilities = terminal_capabilities.with_passthrough(Passthrough::Tmux);
+    }
 
-    if args.detect_and_exit {
+    if args.version {
+        println!("{} {}", args.binary_name(), long_version());
+        if args.verbose {
+            println!();
+            print_features();
+            println!("Terminal: {terminal}");
+            print_terminal_details(&detected_from, &terminal_capabilities);
+        }
+    } else if args.detect_and_exit {
         println!("Terminal: {terminal}");
+        if args.verbose {
+            print_terminal_details(&detected_from, &terminal_capabilities);
+        }
     } else {
         // Enable Ansi color processing on Windows
         #[cfg(windows)]
         anstyle_query::windows::enable_ansi_colors();
 
-        let terminal_size = TerminalSize::detect().unwrap_or_default();
-        let terminal_size = if let Some(max_columns) = args.columns {
-            terminal_size.with_max_columns(max_columns)
-        } else {
-            terminal_size
-        };
-
-        let exit_code = match Output::new(args.paginate()) {
+        let exit_code = match Output::new(
+            args.paginate(),
+            args.pager.as_deref(),
+            config.pager.as_deref(),
+        ) {
             Ok(mut output) => {
-                let settings = Settings {
-                    terminal_capabilities: terminal.capabilities(),
-                    terminal_size,
-                    syntax_set: &SyntaxSet::load_defaults_newlines(),
-                    theme: Theme::default(),
-                };
+                // Pagers may not show lines as wide as the terminal, so wrap more conservatively
+                let pager_columns = output
+                    .is_pager()
+                    .then(|| {
+                        args.pager_columns
+                            .or(config.pager_columns)
+                            .unwrap_or(DEFAULT_PAGER_COLUMNS)
+                    })
+                    .filter(|columns| 0 < *columns);
+                // Detect the size for every file, to follow the terminal if it's resized in between
+                let mut terminal_size =
+                    TerminalSizeCache::new(TerminalSize::detect, DEFAULT_SIZE_MAX_AGE)
+                        .with_max_columns(max_columns(args.columns, config.columns, pager_columns));
+                let syntax_set = LazySyntaxSet::default();
+                let diagram_languages = args
+                    .diagram_languages
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>();
+                let mut settings = Settings::new(
+                    terminal_capabilities,
+                    terminal_size.get(),
+                    syntax_set.empty(),
+                )
+                .with_theme(
+                    Theme::default()
+                        .with_glyphs(if args.ascii {
+                            Glyphs::ASCII
+                        } else {
+                            Glyphs::UNICODE
+                        })
+                        .with_block_margin(if args.compact { 0 } else { 1 }),
+                )
+                .with_tab_width(args.tab_width.or(config.tab_width))
+                .with_link_reference_placement(if args.references_at_end {
+                    LinkReferencePlacement::DocumentEnd
+                } else {
+                    LinkReferencePlacement::PerSection
+                })
+                .with_image_dimensions(args.image_dimensions)
+                .with_render_html_images_and_links(args.render_html_tags)
+                .with_quiet_image_fallbacks(true)
+                .with_preserve_line_breaks(args.preserve_breaks)
+                .with_syntax_theme(syntax_theme.as_ref())
+                .with_render_front_matter_title(args.front_matter_title)
+                .with_diagram_languages(&diagram_languages)
+                .with_link_style(link_style(args.link_style))
+                .with_indent(args.indent);
                 event!(
                     target: "mdcat::main",
                     Level::TRACE,
@@ -91,12 +277,45 @@ fn main() {
                     ?settings.terminal_capabilities,
                     "settings"
                 );
-                // TODO: Handle this error properly
-                let resource_handler = create_resource_handler(args.resource_access()).unwrap();
-                args.filenames
-                    .iter()
-                    .try_fold(0, |code, filename| {
-                        process_file(filename, &settings, &resource_handler, &mut output)
+                let resource_access = args.resource_access(config.local);
+                // Confine local access to the directory of each document unless permitted
+                let confine_files = matches!(resource_access, ResourceAccess::LocalOnly)
+                    && args.confined(config.unconfined);
+                let input_size_limit = args
+                    .max_input_size
+                    .or(config.max_input_size)
+                    .unwrap_or(DEFAULT_INPUT_SIZE_LIMIT);
+                let markdown = args.markdown(config.markdown);
+                let options = ProcessOptions {
+                    toc: args.toc,
+                    strip_osc: args.strip_osc,
+                    events_json: args.events_json,
+                    dump_events: args.dump_events,
+                    list_resources: args.list_resources,
+                    warn_unknown_languages: args.warn_unknown_languages,
+                    lines: args.lines,
+                    input_size_limit,
+                    hostname: args.hostname.clone(),
+                };
+                let resource_config = |base_dir: &Path| ResourceHandlerConfig {
+                    access: resource_access,
+                    file_root: confine_files.then(|| base_dir.to_path_buf()),
+                    ..ResourceHandlerConfig::default()
+                };
+                let mut render_files = |output: &mut Output| {
+                    args.filenames
+                        .iter()
+                        .try_fold(0, |code, filename| {
+                            settings.terminal_size = terminal_size.get();
+                            process_file(
+                                filename,
+                                &mut settings,
+                                &syntax_set,
+                                &markdown,
+                                &options,
+                                resource_config,
+                                output,
+                            )
                             .map(|_| code)
                             .or_else(|error| {
                                 eprintln!("Error: {filename}: {error}");
@@ -106,8 +325,29 @@ fn main() {
                                     Ok(1)
                                 }
                             })
-                    })
-                    .unwrap_or(1)
+                        })
+                        .unwrap_or(1)
+                };
+                let exit_code = render_files(&mut output);
+                if args.watch {
+                    // Watching only ever returns if it fails
+                    let result = watch_files(&args.filenames, || {
+                        let writer = output.writer();
+                        let _ = writer
+                            .write_all(CLEAR_SCREEN.as_bytes())
+                            .and_then(|_| writer.flush());
+                        render_files(&mut output);
+                    });
+                    match result {
+                        Ok(()) => exit_code,
+                        Err(error) => {
+                            eprintln!("Error: {error:#}");
+                            1
+                        }
+                    }
+                } else {
+                    exit_code
+                }
             }
             Err(error) => {
                 eprintln!("Error: {error:#}");
@@ -40,21 +40,37 @@ fn parse_env_var(name: &str) -> Result<Option<Vec<String>>> {
     }
 }
 
-fn pager_from_env() -> Result<Vec<String>> {
-    for envvar in ["MDCAT_PAGER", "PAGER"] {
-        event!(Level::TRACE, envvar, "looking for pager in environment");
-        match parse_env_var(envvar) {
-            // Continue looking
-            Ok(None) => {}
-            Ok(Some(command)) => {
-                event!(Level::INFO, envvar, "Using {:?} as pager", command);
-                return Ok(command);
-            }
-            Err(error) => {
-                event!(Level::ERROR, envvar, %error, "Parsing failed: {:#}", error);
-                return Err(error);
-            }
+/// Get the pager command.
+///
+/// Take the explicit `pager`, then the pager from `$MDCAT_PAGER`, then from `$PAGER`, then the
+/// `configured` pager.
+fn pager_command(pager: Option<&str>, configured: Option<&str>) -> Result<Vec<String>> {
+    if let Some(pager) = pager {
+        let command = shell_words::split(pager)
+            .with_context(|| format!("Failed to parse pager command {pager}"))?;
+        if command.is_empty() {
+            bail!("Empty pager command");
         }
+        event!(Level::INFO, "Using {:?} as pager from --pager", command);
+        return Ok(command);
+    }
+    if let Some(command) = parse_env_var("MDCAT_PAGER")? {
+        event!(
+            Level::INFO,
+            "Using {:?} as pager from $MDCAT_PAGER",
+            command
+        );
+        return Ok(command);
+    }
+    if let Some(command) = parse_env_var("PAGER")? {
+        event!(Level::INFO, "Using {:?} as pager from $PAGER", command);
+        return Ok(command);
+    }
+    if let Some(configured) = configured {
+        let command = shell_words::split(configured)
+            .with_context(|| format!("Failed to parse configured pager {configured}"))?;
+        event!(Level::INFO, "Using configured pager {:?}", command);
+        return Ok(command);
     }
     event!(Level::DEBUG, "Falling back to default pager less -r");
     Ok(vec!["less".into(), "-r".into()])
@@ -71,17 +87,27 @@ impl Output {
         }
     }
 
+    /// Whether this output goes to a pager.
+    pub fn is_pager(&self) -> bool {
+        matches!(self, Output::Pager(_))
+    }
+
     /// Create a new output.
     ///
     /// If `try_paginate` is `true` try to output to a pager.  If stdout is not a TTY, that is, if
     /// there's no terminal to paginate on, print to stdout nonetheless.
     ///
-    /// Take the pager command from `$MDCAT_PAGER` or `$PAGER`, and default to `less -R` if both are
-    /// unset.  If any of the variables is empty use stdout (assuming that the user
-    /// wanted to disabled paging explicitly).
-    pub fn new(try_paginate: bool) -> Result<Output> {
+    /// Take the pager command from `pager`, then from `$MDCAT_PAGER` or `$PAGER`, then from the
+    /// `configured_pager`, and default to `less -R` if all are unset.  Fail if `pager` is empty;
+    /// if any of the others is empty use stdout (assuming that the user wanted to disable paging
+    /// explicitly).
+    pub fn new(
+        try_paginate: bool,
+        pager: Option<&str>,
+        configured_pager: Option<&str>,
+    ) -> Result<Output> {
         if try_paginate {
-            match pager_from_env()?.split_first() {
+            match pager_command(pager, configured_pager)?.split_first() {
                 None => {
                     event!(
                         Level::WARN,
@@ -111,3 +137,133 @@ impl Output {
         }
     }
 }
+
+/// State of [`StripOsc`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum StripState {
+    /// Regular output.
+    Text,
+    /// After an escape character in regular output.
+    Escape,
+    /// Inside an OSC or APC sequence.
+    Command,
+    /// After an escape character inside an OSC or APC sequence.
+    CommandEscape,
+}
+
+/// A writer which removes OSC and APC sequences from output.
+///
+/// This removes OSC 8 links, iTerm2 marks and images, and kitty images, but keeps SGR sequences,
+/// i.e. colours and other text styles.
+pub struct StripOsc<W> {
+    inner: W,
+    state: StripState,
+}
+
+impl<W: Write> StripOsc<W> {
+    /// Remove OSC and APC sequences from everything written to `inner`.
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            state: StripState::Text,
+        }
+    }
+}
+
+impl<W: Write> Write for StripOsc<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        const ESC: u8 = 0x1b;
+        const BEL: u8 = 0x07;
+        let mut output = Vec::with_capacity(buf.len());
+        for &byte in buf {
+            self.state = match (self.state, byte) {
+                (StripState::Text, ESC) => StripState::Escape,
+                (StripState::Text, _) => {
+                    output.push(byte);
+                    StripState::Text
+                }
+                // OSC and APC sequences
+                (StripState::Escape, b']' | b'_') => StripState::Command,
+                (StripState::Escape, ESC) => {
+                    output.push(ESC);
+                    StripState::Escape
+                }
+                (StripState::Escape, _) => {
+                    output.extend_from_slice(&[ESC, byte]);
+                    StripState::Text
+                }
+                (StripState::Command, BEL) => StripState::Text,
+                (StripState::Command, ESC) => StripState::CommandEscape,
+                (StripState::Command, _) => StripState::Command,
+                // ST, i.e. ESC \
+                (StripState::CommandEscape, b'\\') => StripState::Text,
+                (StripState::CommandEscape, ESC) => StripState::CommandEscape,
+                (StripState::CommandEscape, _) => StripState::Command,
+            };
+        }
+        self.inner.write_all(&output)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Whether mdcat runs inside tmux, according to `$TMUX` or `$TERM`.
+pub fn is_tmux() -> bool {
+    std::env::var_os("TMUX").is_some_and(|value| !value.is_empty())
+        || std::env::var("TERM").is_ok_and(|term| term.starts_with("tmux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use similar_asserts::assert_eq;
+
+    use super::StripOsc;
+
+    fn strip(chunks: &[&[u8]]) -> String {
+        let mut sink = Vec::new();
+        let mut writer = StripOsc::new(&mut sink);
+        for chunk in chunks {
+            writer.write_all(chunk).unwrap();
+        }
+        String::from_utf8(sink).unwrap()
+    }
+
+    #[test]
+    fn strip_osc_keeps_sgr() {
+        assert_eq!(
+            strip(&[b"\x1b[1mbold\x1b[0m and \x1b[34mblue\x1b[0m"]),
+            "\x1b[1mbold\x1b[0m and \x1b[34mblue\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn strip_osc_removes_links_and_images() {
+        assert_eq!(
+            strip(&[
+                b"A \x1b]8;;https://example.com\x1b\\\x1b[34mlink\x1b[0m\x1b]8;;\x1b\\, ",
+                b"an \x1b]1337;File=inline=1:AAAA\x07image, ",
+                b"and a \x1b_Ga=T,f=100;AAAA\x1b\\kitty image",
+            ]),
+            "A \x1b[34mlink\x1b[0m, an image, and a kitty image"
+        );
+    }
+
+    #[test]
+    fn strip_osc_across_writes() {
+        assert_eq!(
+            strip(&[
+                b"A \x1b",
+                b"]8;;https://exa",
+                b"mple.com\x1b",
+                b"\\link\x1b",
+                b"[0m"
+            ]),
+            "A link\x1b[0m"
+        );
+    }
+}
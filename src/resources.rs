@@ -4,6 +4,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use std::path::{Path, PathBuf};
 use std::{cell::RefCell, time::Duration};
 
 use curl::easy::{Easy2, Handler, WriteError};
@@ -15,6 +16,62 @@ use pulldown_cmark_mdcat::{
 use tracing::{event, instrument, Level};
 use url::Url;
 
+use crate::args::ResourceAccess;
+use crate::DEFAULT_RESOURCE_READ_LIMIT;
+
+/// URL schemes which mdcat reads remote resources from by default.
+///
+/// See <https://curl.se/docs/url-syntax.html> for all schemes curl supports; we omit the more
+/// exotic ones.
+pub const DEFAULT_REMOTE_SCHEMES: &[&str] = &["http", "https", "ftp", "ftps", "smb"];
+
+/// The default timeout for reading remote resources.
+///
+/// We use somewhat aggressive timeouts to avoid blocking rendering for long; we have graceful
+/// fallbacks since we have to support terminals without image capabilities anyways.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How mdcat reads resources, e.g. images.
+///
+/// See [`crate::build_resource_handler`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceHandlerConfig {
+    /// What resources mdcat may access.
+    pub access: ResourceAccess,
+    /// The maximum number of bytes to read from local files.
+    pub file_read_limit: u64,
+    /// Only read local files within this directory, if set.
+    pub file_root: Option<PathBuf>,
+    /// The maximum number of bytes to read from remote resources.
+    pub remote_read_limit: u64,
+    /// The maximum time to read a remote resource, including connecting.
+    pub timeout: Duration,
+    /// The maximum time to connect to a remote server.
+    pub connect_timeout: Duration,
+    /// The URL schemes to read remote resources from.
+    pub remote_schemes: Vec<String>,
+    /// How often to retry reading a remote resource after connection failures or timeouts.
+    pub retries: u32,
+}
+
+impl Default for ResourceHandlerConfig {
+    fn default() -> Self {
+        Self {
+            access: ResourceAccess::LocalOnly,
+            file_read_limit: DEFAULT_RESOURCE_READ_LIMIT,
+            file_root: None,
+            remote_read_limit: DEFAULT_RESOURCE_READ_LIMIT,
+            timeout: DEFAULT_TIMEOUT,
+            connect_timeout: DEFAULT_TIMEOUT,
+            remote_schemes: DEFAULT_REMOTE_SCHEMES
+                .iter()
+                .map(|scheme| (*scheme).to_owned())
+                .collect(),
+            retries: 0,
+        }
+    }
+}
+
 /// Handle curl data by writing into a buffer.
 #[derive(Debug, Clone, Default)]
 pub struct CollectBuffer {
@@ -35,9 +92,42 @@ impl Handler for CollectBuffer {
     }
 }
 
+impl ResourceHandlerConfig {
+    /// Whether this configuration permits mdcat to read `url`.
+    ///
+    /// Check the scheme of `url` against the resource `access` and the remote `remote_schemes`,
+    /// and whether a local file is within `file_root`, without reading anything.
+    pub fn permits(&self, url: &Url) -> bool {
+        if url.scheme() == "file" {
+            match (&self.file_root, url.to_file_path()) {
+                (_, Err(())) => false,
+                (None, Ok(_)) => true,
+                (Some(root), Ok(path)) => {
+                    let canonical = |path: &Path| path.canonicalize().unwrap_or(path.to_owned());
+                    canonical(&path).starts_with(canonical(root))
+                }
+            }
+        } else {
+            let scheme_permitted = match self.access {
+                ResourceAccess::LocalOnly => false,
+                ResourceAccess::RemoteHttpsOnly => url.scheme() == "https",
+                ResourceAccess::Remote => true,
+            };
+            scheme_permitted
+                && self
+                    .remote_schemes
+                    .iter()
+                    .any(|scheme| scheme == url.scheme())
+        }
+    }
+}
+
 /// A [`curl`]-based resource handler for [`pulldown-cmark-mdcat`].
 pub struct CurlResourceHandler {
     easy: RefCell<Easy2<CollectBuffer>>,
+    https_only: bool,
+    schemes: Vec<String>,
+    retries: u32,
 }
 
 impl CurlResourceHandler {
@@ -50,11 +140,8 @@ impl CurlResourceHandler {
             buffer: Vec::new(),
             read_limit,
         });
-        // Use somewhat aggressive timeouts to avoid blocking rendering for long; we have graceful
-        // fallbacks since we have to support terminals without image capabilities anyways.
-
-        easy.timeout(Duration::from_secs(1))?;
-        easy.connect_timeout(Duration::from_secs(1))?;
+        easy.timeout(DEFAULT_TIMEOUT)?;
+        easy.connect_timeout(DEFAULT_TIMEOUT)?;
         easy.follow_location(true)?;
         easy.fail_on_error(true)?;
         easy.tcp_nodelay(true)?;
@@ -66,6 +153,60 @@ impl CurlResourceHandler {
     pub fn new(easy: Easy2<CollectBuffer>) -> Self {
         Self {
             easy: RefCell::new(easy),
+            https_only: false,
+            schemes: DEFAULT_REMOTE_SCHEMES
+                .iter()
+                .map(|scheme| (*scheme).to_owned())
+                .collect(),
+            retries: 0,
+        }
+    }
+
+    /// Give up reading a resource after `timeout`, or if connecting takes longer than
+    /// `connect_timeout`.
+    pub fn with_timeouts(
+        self,
+        timeout: Duration,
+        connect_timeout: Duration,
+    ) -> std::io::Result<Self> {
+        {
+            let mut easy = self.easy.borrow_mut();
+            easy.timeout(timeout)?;
+            easy.connect_timeout(connect_timeout)?;
+        }
+        Ok(self)
+    }
+
+    /// Only read resources with the given URL `schemes`.
+    pub fn with_schemes(self, schemes: Vec<String>) -> Self {
+        Self { schemes, ..self }
+    }
+
+    /// Retry reading a resource up to `retries` times after connection failures or timeouts.
+    pub fn with_retries(self, retries: u32) -> Self {
+        Self { retries, ..self }
+    }
+
+    /// Only read resources over HTTPS.
+    ///
+    /// Refuse to read resources with any other scheme, and resources which redirect to other
+    /// schemes, with [`std::io::ErrorKind::PermissionDenied`].
+    pub fn https_only(self) -> Self {
+        Self {
+            https_only: true,
+            ..self
+        }
+    }
+
+    /// Check whether we may read `url`.
+    fn check_policy(&self, url: &str) -> std::io::Result<()> {
+        if self.https_only && !url.starts_with("https://") {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("Refusing to read {url}: only HTTPS is permitted"),
+            ))
+        } else {
+            Ok(())
         }
     }
 }
@@ -76,12 +217,51 @@ impl ResourceUrlHandler for CurlResourceHandler {
         &self,
         url: &Url,
     ) -> std::io::Result<pulldown_cmark_mdcat::resources::MimeData> {
-        // See https://curl.se/docs/url-syntax.html for all schemas curl supports
-        // We omit the more exotic ones :)
-        filter_schemes(&["http", "https", "ftp", "ftps", "smb"], url).and_then(|url| {
+        let schemes = self.schemes.iter().map(String::as_str).collect::<Vec<_>>();
+        filter_schemes(&schemes, url).and_then(|url| {
+            self.check_policy(url.as_str())?;
             let mut easy = self.easy.borrow_mut();
+            easy.get_mut().buffer.clear();
             easy.url(url.as_str())?;
-            easy.perform()?;
+            let mut result = easy.perform();
+            let mut attempt = 0;
+            while let Err(error) = &result {
+                let transient = error.is_couldnt_connect()
+                    || error.is_operation_timedout()
+                    || error.is_recv_error()
+                    || error.is_send_error()
+                    || error.is_got_nothing();
+                if !transient || self.retries <= attempt {
+                    break;
+                }
+                attempt += 1;
+                event!(Level::DEBUG, %url, attempt, "Retrying after error: {}", error);
+                easy.get_mut().buffer.clear();
+                result = easy.perform();
+            }
+            if let Err(error) = result {
+                let received = easy.get_ref().buffer.len();
+                easy.get_mut().buffer.clear();
+                // curl fails with a rather obscure message if the server sent less data than it
+                // advertised in Content-Length, so let's be more explicit.
+                return if error.is_partial_file() {
+                    let advertised = easy.content_length_download()?;
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        format!("Server advertised {advertised} bytes but sent {received}"),
+                    ))
+                } else {
+                    Err(error.into())
+                };
+            }
+
+            // curl may have followed a redirect to another scheme
+            if let Some(effective_url) = easy.effective_url()? {
+                if let Err(error) = self.check_policy(effective_url) {
+                    easy.get_mut().buffer.clear();
+                    return Err(error);
+                }
+            }
 
             let mime_type = easy.content_type()?.and_then(|content_type| {
                 event!(
@@ -98,3 +278,135 @@ impl ResourceUrlHandler for CurlResourceHandler {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    use pulldown_cmark_mdcat::resources::UnsupportedScheme;
+    use pulldown_cmark_mdcat::ResourceUrlHandler;
+    use url::Url;
+
+    use super::{CurlResourceHandler, ResourceHandlerConfig};
+    use crate::args::ResourceAccess;
+
+    /// Serve a single HTTP response with `headers` and `body` on a local port.
+    fn serve_once(headers: &'static str, body: &'static [u8]) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            // Read the request up to the empty line which ends the headers
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            stream.write_all(headers.as_bytes()).unwrap();
+            stream.write_all(body).unwrap();
+        });
+        url
+    }
+
+    #[test]
+    fn read_resource() {
+        let url = serve_once(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\n",
+            b"Hello",
+        );
+        let handler = CurlResourceHandler::create(100, "mdcat-test").unwrap();
+        let data = handler.read_resource(&url).unwrap();
+        assert_eq!(data.mime_type_essence(), Some("text/plain"));
+        assert_eq!(data.data, b"Hello");
+    }
+
+    #[test]
+    fn https_only_rejects_http() {
+        let url = Url::parse("http://example.com/image.png").unwrap();
+        let handler = CurlResourceHandler::create(100, "mdcat-test")
+            .unwrap()
+            .https_only();
+        let error = handler.read_resource(&url).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+        assert_eq!(
+            error.to_string(),
+            "Refusing to read http://example.com/image.png: only HTTPS is permitted"
+        );
+    }
+
+    #[test]
+    fn only_read_given_schemes() {
+        let url = Url::parse("http://example.com/image.png").unwrap();
+        let handler = CurlResourceHandler::create(100, "mdcat-test")
+            .unwrap()
+            .with_schemes(vec!["https".to_owned()]);
+        let error = handler.read_resource(&url).unwrap_err();
+        assert!(UnsupportedScheme::is_unsupported_scheme(&error), "{error}");
+    }
+
+    #[test]
+    fn retry_after_empty_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        std::thread::spawn(move || {
+            // Close the first connection without any response
+            drop(listener.accept().unwrap());
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello")
+                .unwrap();
+        });
+        let handler = CurlResourceHandler::create(100, "mdcat-test")
+            .unwrap()
+            .with_retries(1);
+        let data = handler.read_resource(&url).unwrap();
+        assert_eq!(data.data, b"Hello");
+    }
+
+    #[test]
+    fn server_sends_less_than_content_length() {
+        let url = serve_once(
+            "HTTP/1.1 200 OK\r\nContent-Length: 100\r\nConnection: close\r\n\r\n",
+            b"Hello",
+        );
+        let handler = CurlResourceHandler::create(1000, "mdcat-test").unwrap();
+        let error = handler.read_resource(&url).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert_eq!(error.to_string(), "Server advertised 100 bytes but sent 5");
+    }
+
+    #[test]
+    fn config_permits() {
+        let url = |s: &str| Url::parse(s).unwrap();
+        let cwd = std::env::current_dir().unwrap();
+        let local = Url::from_file_path(cwd.join("src/lib.rs")).unwrap();
+
+        let config = ResourceHandlerConfig::default();
+        assert!(config.permits(&local));
+        assert!(!config.permits(&url("https://example.com/image.png")));
+
+        let config = ResourceHandlerConfig {
+            access: ResourceAccess::RemoteHttpsOnly,
+            file_root: Some(cwd.join("src")),
+            ..ResourceHandlerConfig::default()
+        };
+        assert!(config.permits(&local));
+        assert!(!config.permits(&Url::from_file_path(cwd.join("Cargo.toml")).unwrap()));
+        assert!(config.permits(&url("https://example.com/image.png")));
+        assert!(!config.permits(&url("http://example.com/image.png")));
+
+        let config = ResourceHandlerConfig {
+            access: ResourceAccess::Remote,
+            ..ResourceHandlerConfig::default()
+        };
+        assert!(config.permits(&url("http://example.com/image.png")));
+        assert!(!config.permits(&url("gopher://example.com/image.png")));
+        assert!(!config.permits(&url("mailto:jane@example.com")));
+    }
+}
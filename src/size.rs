@@ -0,0 +1,146 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::time::{Duration, Instant};
+
+use pulldown_cmark_mdcat::terminal::TerminalSize;
+
+/// How long to reuse a detected terminal size before detecting it again.
+pub const DEFAULT_SIZE_MAX_AGE: Duration = Duration::from_millis(500);
+
+/// The default maximum number of columns when paginating output, unless given explicitly.
+pub const DEFAULT_PAGER_COLUMNS: u16 = 80;
+
+/// The maximum number of columns for output.
+///
+/// Explicit `columns`, e.g. from `--columns`, always take precedence.  Otherwise use the smaller of
+/// `configured_columns` from the configuration file and `pager_columns`, if any.  `pager_columns`
+/// should only be set when paginating output, because the pager may show output at a different
+/// width than the terminal reports.
+pub fn max_columns(
+    columns: Option<u16>,
+    configured_columns: Option<u16>,
+    pager_columns: Option<u16>,
+) -> Option<u16> {
+    columns.or(match (configured_columns, pager_columns) {
+        (Some(configured), Some(pager)) => Some(configured.min(pager)),
+        (configured, pager) => configured.or(pager),
+    })
+}
+
+/// Detect the terminal size, and cache it for a short interval.
+///
+/// mdcat detects the terminal size for every document, to wrap each document to the current size
+/// of the terminal, even if the terminal was resized in between.  Detecting the size issues an
+/// ioctl, so this type reuses the last size for a short while, to avoid this cost if mdcat
+/// renders many small documents in a row.
+pub struct TerminalSizeCache<F> {
+    detect: F,
+    max_age: Duration,
+    max_columns: Option<u16>,
+    cached: Option<(Instant, TerminalSize)>,
+}
+
+impl<F: FnMut() -> Option<TerminalSize>> TerminalSizeCache<F> {
+    /// Detect the terminal size with `detect`, and reuse it for `max_age`.
+    pub fn new(detect: F, max_age: Duration) -> Self {
+        Self {
+            detect,
+            max_age,
+            max_columns: None,
+            cached: None,
+        }
+    }
+
+    /// Shrink detected sizes to at most `max_columns`.
+    pub fn with_max_columns(self, max_columns: Option<u16>) -> Self {
+        Self {
+            max_columns,
+            ..self
+        }
+    }
+
+    /// Get the current terminal size.
+    ///
+    /// Return the cached size if it's recent enough, otherwise detect the size again.  Use the
+    /// default size if detection fails.
+    pub fn get(&mut self) -> TerminalSize {
+        match self.cached {
+            Some((detected_at, size)) if detected_at.elapsed() < self.max_age => size,
+            _ => {
+                let size = (self.detect)().unwrap_or_default();
+                let size = match self.max_columns {
+                    Some(max_columns) => size.with_max_columns(max_columns),
+                    None => size,
+                };
+                self.cached = Some((Instant::now(), size));
+                size
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::time::Duration;
+
+    use pulldown_cmark_mdcat::terminal::TerminalSize;
+
+    use super::{max_columns, TerminalSizeCache};
+
+    #[test]
+    fn reuses_recent_size() {
+        let calls = Cell::new(0);
+        let mut cache = TerminalSizeCache::new(
+            || {
+                calls.set(calls.get() + 1);
+                Some(TerminalSize::new(100, 40))
+            },
+            Duration::from_secs(3600),
+        );
+        assert_eq!(cache.get().columns, 100);
+        assert_eq!(cache.get().columns, 100);
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn detects_size_again_after_max_age() {
+        let columns = Cell::new(80);
+        let mut cache = TerminalSizeCache::new(
+            || {
+                columns.set(columns.get() + 10);
+                Some(TerminalSize::new(columns.get(), 24))
+            },
+            Duration::ZERO,
+        );
+        assert_eq!(cache.get().columns, 90);
+        assert_eq!(cache.get().columns, 100);
+    }
+
+    #[test]
+    fn applies_max_columns() {
+        let mut cache = TerminalSizeCache::new(|| Some(TerminalSize::new(120, 24)), Duration::ZERO)
+            .with_max_columns(Some(60));
+        assert_eq!(cache.get().columns, 60);
+    }
+
+    #[test]
+    fn falls_back_to_default_size() {
+        let mut cache = TerminalSizeCache::new(|| None, Duration::ZERO);
+        assert_eq!(cache.get(), TerminalSize::default());
+    }
+
+    #[test]
+    fn max_columns_when_paginating() {
+        assert_eq!(max_columns(None, None, None), None);
+        assert_eq!(max_columns(None, Some(100), None), Some(100));
+        assert_eq!(max_columns(None, None, Some(80)), Some(80));
+        assert_eq!(max_columns(None, Some(100), Some(80)), Some(80));
+        assert_eq!(max_columns(None, Some(60), Some(80)), Some(60));
+        assert_eq!(max_columns(Some(120), Some(60), Some(80)), Some(120));
+    }
+}
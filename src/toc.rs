@@ -0,0 +1,134 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pulldown_cmark::{Event, HeadingLevel, Tag, TagEnd};
+
+/// Turn the headings in `events` into a table of contents.
+///
+/// Drop all events except headings, and return events for a nested list of all headings, where
+/// each level of headings adds a level of nesting.  Keep inline markup in headings, except for
+/// images, which become their plain description.
+pub fn table_of_contents<'a, I>(events: I) -> Vec<Event<'a>>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    let mut toc = Vec::new();
+    // The heading levels of all open lists
+    let mut levels: Vec<HeadingLevel> = Vec::new();
+    let mut in_heading = false;
+    for event in events {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                while levels.last().is_some_and(|open| level < *open) {
+                    toc.push(Event::End(TagEnd::Item));
+                    toc.push(Event::End(TagEnd::List(false)));
+                    levels.pop();
+                }
+                if levels.last() == Some(&level) {
+                    toc.push(Event::End(TagEnd::Item));
+                } else {
+                    toc.push(Event::Start(Tag::List(None)));
+                    levels.push(level);
+                }
+                toc.push(Event::Start(Tag::Item));
+                in_heading = true;
+            }
+            Event::End(TagEnd::Heading(_)) => in_heading = false,
+            Event::Start(Tag::Image { .. }) | Event::End(TagEnd::Image) => {}
+            event if in_heading => toc.push(event),
+            _ => {}
+        }
+    }
+    for _ in levels {
+        toc.push(Event::End(TagEnd::Item));
+        toc.push(Event::End(TagEnd::List(false)));
+    }
+    toc
+}
+
+#[cfg(test)]
+mod tests {
+    use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+    use similar_asserts::assert_eq;
+
+    use super::table_of_contents;
+
+    fn toc(markdown: &str) -> Vec<Event<'_>> {
+        table_of_contents(Parser::new(markdown))
+    }
+
+    #[test]
+    fn no_headings() {
+        assert_eq!(toc("Some text\n\n* A list\n"), vec![]);
+    }
+
+    #[test]
+    fn nested_headings() {
+        let markdown = "# One\n\nText\n\n## *Two*\n\n### Three\n\n## Four\n\n# Five\n";
+        assert_eq!(
+            toc(markdown),
+            vec![
+                Event::Start(Tag::List(None)),
+                Event::Start(Tag::Item),
+                Event::Text("One".into()),
+                Event::Start(Tag::List(None)),
+                Event::Start(Tag::Item),
+                Event::Start(Tag::Emphasis),
+                Event::Text("Two".into()),
+                Event::End(TagEnd::Emphasis),
+                Event::Start(Tag::List(None)),
+                Event::Start(Tag::Item),
+                Event::Text("Three".into()),
+                Event::End(TagEnd::Item),
+                Event::End(TagEnd::List(false)),
+                Event::End(TagEnd::Item),
+                Event::Start(Tag::Item),
+                Event::Text("Four".into()),
+                Event::End(TagEnd::Item),
+                Event::End(TagEnd::List(false)),
+                Event::End(TagEnd::Item),
+                Event::Start(Tag::Item),
+                Event::Text("Five".into()),
+                Event::End(TagEnd::Item),
+                Event::End(TagEnd::List(false)),
+            ]
+        );
+    }
+
+    #[test]
+    fn start_with_lower_level() {
+        assert_eq!(
+            toc("## Two\n\n# One\n"),
+            vec![
+                Event::Start(Tag::List(None)),
+                Event::Start(Tag::Item),
+                Event::Text("Two".into()),
+                Event::End(TagEnd::Item),
+                Event::End(TagEnd::List(false)),
+                Event::Start(Tag::List(None)),
+                Event::Start(Tag::Item),
+                Event::Text("One".into()),
+                Event::End(TagEnd::Item),
+                Event::End(TagEnd::List(false)),
+            ]
+        );
+    }
+
+    #[test]
+    fn images_in_headings() {
+        assert_eq!(
+            toc("# ![Logo](logo.png) Title\n"),
+            vec![
+                Event::Start(Tag::List(None)),
+                Event::Start(Tag::Item),
+                Event::Text("Logo".into()),
+                Event::Text(" Title".into()),
+                Event::End(TagEnd::Item),
+                Event::End(TagEnd::List(false)),
+            ]
+        );
+    }
+}
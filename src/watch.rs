@@ -0,0 +1,118 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use notify::{EventKind, RecursiveMode, Watcher};
+use tracing::{event, Level};
+
+/// Clear the screen and move the cursor to the top left corner.
+pub const CLEAR_SCREEN: &str = "\x1b[H\x1b[2J";
+
+/// How long to wait for more changes before rendering again.
+///
+/// Editors often write files in multiple steps; wait until they're done.
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+/// The paths to watch for the given `filenames`.
+///
+/// Return the directories to watch, and the absolute paths of all files.
+///
+/// We watch the directories rather than the files themselves, because editors often save files
+/// atomically, by writing a temporary file and renaming it over the original file.  A watch on
+/// the original file would end with the first save.
+fn watched_paths<T: AsRef<str>>(filenames: &[T]) -> Result<(HashSet<PathBuf>, HashSet<PathBuf>)> {
+    let mut directories = HashSet::new();
+    let mut files = HashSet::new();
+    for filename in filenames {
+        let filename = filename.as_ref();
+        if filename == "-" {
+            bail!("Cannot watch standard input");
+        }
+        let path = Path::new(filename);
+        let name = path
+            .file_name()
+            .with_context(|| format!("Cannot watch {filename}: Not a file"))?;
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let directory = directory
+            .canonicalize()
+            .with_context(|| format!("Cannot watch {filename}"))?;
+        files.insert(directory.join(name));
+        directories.insert(directory);
+    }
+    Ok((directories, files))
+}
+
+/// Watch `filenames`, and call `render` whenever any of these files changes.
+///
+/// Only return if watching fails.
+pub fn watch_files<T: AsRef<str>, F: FnMut()>(filenames: &[T], mut render: F) -> Result<()> {
+    let (directories, files) = watched_paths(filenames)?;
+    let (sender, receiver) = channel();
+    let mut watcher =
+        notify::recommended_watcher(sender).context("Failed to watch files for changes")?;
+    for directory in &directories {
+        watcher
+            .watch(directory, RecursiveMode::NonRecursive)
+            .with_context(|| format!("Failed to watch {}", directory.display()))?;
+    }
+
+    let is_change = |event: &notify::Event| {
+        // Rendering reads files, so we must ignore access or we'd render forever
+        !matches!(event.kind, EventKind::Access(_))
+            && event.paths.iter().any(|path| files.contains(path))
+    };
+    loop {
+        let event = receiver
+            .recv()
+            .context("Stopped watching files for changes")?
+            .context("Failed to watch files for changes")?;
+        if is_change(&event) {
+            event!(target: "mdcat::watch", Level::DEBUG, ?event, "File changed");
+            // Wait until changes settle down
+            loop {
+                match receiver.recv_timeout(SETTLE_TIME) {
+                    Ok(_) => continue,
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        bail!("Stopped watching files for changes")
+                    }
+                }
+            }
+            render();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::watched_paths;
+
+    #[test]
+    fn watched_paths_refuses_stdin() {
+        assert!(watched_paths(&["-"]).is_err());
+    }
+
+    #[test]
+    fn watched_paths_watches_directories() {
+        let cwd = Path::new(".").canonicalize().unwrap();
+        let (directories, files) = watched_paths(&["README.md", "src/lib.rs"]).unwrap();
+        let mut directories = directories.into_iter().collect::<Vec<_>>();
+        directories.sort();
+        assert_eq!(directories, vec![cwd.clone(), cwd.join("src")]);
+        assert!(files.contains(&cwd.join("README.md")));
+        assert!(files.contains(&cwd.join("src").join("lib.rs")));
+    }
+}
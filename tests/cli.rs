@@ -14,7 +14,12 @@ mod cli {
     use std::process::{Command, Output, Stdio};
 
     fn cargo_mdcat() -> Command {
-        Command::new(env!("CARGO_BIN_EXE_mdcat"))
+        let mut command = Command::new(env!("CARGO_BIN_EXE_mdcat"));
+        // Do not pick up any configuration of the user running the tests
+        command.env("MDCAT_CONFIG", "");
+        // Don't wrap links for tmux if the tests run inside tmux
+        command.env_remove("TMUX");
+        command
     }
 
     fn run_cargo_mdcat<I, S>(args: I) -> Output
@@ -53,6 +58,151 @@ mod cli {
         );
     }
 
+    #[test]
+    fn verbose_version_includes_features_and_terminal() {
+        let output = cargo_mdcat()
+            .env("TERM", "xterm-kitty")
+            .args(["--version", "--verbose"])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.starts_with("mdcat "), "{stdout}");
+        let feature = |name, enabled| format!("{}{name}", if enabled { '+' } else { '-' });
+        let features = [
+            feature("svg", cfg!(feature = "svg")),
+            feature("image-processing", cfg!(feature = "image-processing")),
+            feature("half-blocks", cfg!(feature = "half-blocks")),
+            feature("regex-fancy", cfg!(feature = "regex-fancy")),
+            feature("regex-onig", cfg!(feature = "regex-onig")),
+        ];
+        let engine = if cfg!(feature = "regex-onig") {
+            "onig"
+        } else {
+            "fancy-regex"
+        };
+        assert!(
+            stdout.contains(&format!(
+                "\nFeatures: {}\nRegex engine: {engine}\nTerminal: kitty\n",
+                features.join(" ")
+            )),
+            "{stdout}"
+        );
+        assert!(
+            stdout.contains("\nDetected from: $TERM=xterm-kitty\n"),
+            "{stdout}"
+        );
+    }
+
+    #[test]
+    fn detect_terminal_verbose() {
+        let output = cargo_mdcat()
+            .env("TERM", "xterm-kitty")
+            .args(["--detect-terminal", "--verbose"])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            stdout.starts_with(
+                "Terminal: kitty
+Detected from: $TERM=xterm-kitty
+Styles: yes
+Links: yes
+Images: kitty (Direct placement)
+Marks: none
+"
+            ),
+            "{stdout}"
+        );
+    }
+
+    #[test]
+    fn image_protocol_overrides_detected_protocol() {
+        let output = cargo_mdcat()
+            .env("TERM", "xterm-kitty")
+            .args([
+                "--detect-terminal",
+                "--verbose",
+                "--image-protocol",
+                "iterm2",
+            ])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            stdout.starts_with(
+                "Terminal: kitty
+Detected from: $TERM=xterm-kitty
+Styles: yes
+Links: yes
+Images: iTerm2
+Marks: none
+"
+            ),
+            "{stdout}"
+        );
+    }
+
+    #[test]
+    fn image_protocol_halfblock() {
+        let output = cargo_mdcat()
+            .env("TERM", "xterm-kitty")
+            .env_remove("COLORTERM")
+            .args([
+                "--detect-terminal",
+                "--verbose",
+                "--image-protocol",
+                "halfblock",
+            ])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            stdout.starts_with(
+                "Terminal: kitty
+Detected from: $TERM=xterm-kitty
+Styles: yes
+Links: yes
+Images: half blocks
+Marks: none
+Image fallback: none
+"
+            ),
+            "{stdout}"
+        );
+    }
+
+    #[test]
+    fn image_protocol_ignored_in_pager() {
+        let output = cargo_mdcat()
+            .env("TERM", "xterm-kitty")
+            .args([
+                "--paginate",
+                "--detect-terminal",
+                "--verbose",
+                "--image-protocol",
+                "iterm2",
+            ])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            stdout.starts_with(
+                "Terminal: ansi
+Detected from: pager
+Styles: yes
+Links: yes
+Images: none
+"
+            ),
+            "{stdout}"
+        );
+    }
+
     #[test]
     fn file_list_fail_late() {
         let output = run_cargo_mdcat(["does-not-exist", "sample/common-mark.md"]);
@@ -80,6 +230,641 @@ mod cli {
         assert!(output.stdout.is_empty());
     }
 
+    #[test]
+    fn crlf_line_endings() {
+        let mut child = cargo_mdcat()
+            .args(["--no-colour", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        write!(stdin, "Hello\r\nWorld\r\n\r\n* an item\r\n").unwrap();
+        drop(stdin);
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "Hello World\n\n\u{2022} an item\n"
+        );
+    }
+
+    #[test]
+    fn warn_once_about_invalid_utf8() {
+        let mut child = cargo_mdcat()
+            .args(["--no-colour", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(b"Hello \xff World\n").unwrap();
+        drop(stdin);
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "Hello \u{fffd} World\n"
+        );
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            "Warning: -: Invalid UTF-8, replacing invalid bytes\n"
+        );
+    }
+
+    #[test]
+    fn input_exceeds_size_limit() {
+        let output = run_cargo_mdcat([
+            "--max-input-size",
+            "100",
+            "sample/common-mark.md",
+            "README.md",
+        ]);
+        let stderr = std::str::from_utf8(&output.stderr).unwrap();
+        assert!(!output.status.success());
+        assert!(
+            stderr.contains("Error: sample/common-mark.md: Input exceeds size limit of 100 bytes"),
+            "Stderr: {stderr}",
+        );
+    }
+
+    #[test]
+    fn pager_option_overrides_pager_variables() {
+        let output = cargo_mdcat()
+            .env("MDCAT_PAGER", "false")
+            .env("PAGER", "false")
+            .args(["--paginate", "--pager", "cat", "--no-colour", "README.md"])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        assert!(String::from_utf8_lossy(&output.stdout).contains("mdcat"));
+    }
+
+    #[test]
+    fn empty_pager_option() {
+        let output = run_cargo_mdcat(["--paginate", "--pager", "", "README.md"]);
+        assert_eq!(output.status.code(), Some(2));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Empty pager command"), "{stderr}");
+        assert!(output.stdout.is_empty());
+    }
+
+    fn render_stdin_with_config(config: &str, name: &str, args: &[&str], input: &str) -> Output {
+        let config_file = std::env::temp_dir().join(format!("mdcat-test-{name}.toml"));
+        std::fs::write(&config_file, config).unwrap();
+        let mut child = cargo_mdcat()
+            .env("MDCAT_CONFIG", &config_file)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        write!(stdin, "{input}").unwrap();
+        drop(stdin);
+        let output = child.wait_with_output().unwrap();
+        std::fs::remove_file(&config_file).unwrap();
+        output
+    }
+
+    #[test]
+    fn warn_unknown_languages() {
+        let input = "```rsut\nfn main() {}\n```\n\n```rust\nfn main() {}\n```\n\n```pyhton\npass\n```\n\n```rsut\n```\n";
+        let output = render_stdin_with_config("", "no-warn-languages", &["-"], input);
+        assert!(output.status.success(), "{output:?}");
+        assert!(output.stderr.is_empty(), "{output:?}");
+        let output = render_stdin_with_config(
+            "",
+            "warn-languages",
+            &["--warn-unknown-languages", "-"],
+            input,
+        );
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            "Unknown languages of code blocks: rsut, pyhton\n"
+        );
+    }
+
+    #[test]
+    fn hostname_for_file_links() {
+        let output = render_stdin_with_config(
+            "",
+            "hostname",
+            &["--ansi", "--hostname", "client.example.com", "-"],
+            "[link](./foo.md)\n",
+        );
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            stdout.contains("\x1b]8;;file://client.example.com/"),
+            "{stdout:?}"
+        );
+    }
+
+    #[test]
+    fn render_with_indent() {
+        let input = "# Heading\n\nSome text which is long enough to wrap at thirty columns.\n\n* Item\n  > Quote\n\n```\ncode\n```\n\nA [link](https://example.com).\n";
+        let output = render_stdin_with_config(
+            "",
+            "indent",
+            &["--indent", "4", "--columns", "30", "-"],
+            input,
+        );
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "    ┄Heading\n\n    Some text which is long\n    enough to wrap at thirty\n    columns.\n\n    • Item\n          Quote\n\n    ────────────────────\n    code\n    ────────────────────\n\n    A link[1].\n\n    [1]: https://example.com\n"
+        );
+    }
+
+    #[test]
+    fn highlight_with_syntax_theme() {
+        let input = "```rust\nfn main() {}\n```\n";
+        let output = render_stdin_with_config("", "syntax-theme", &["--ansi", "-"], input);
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(!stdout.contains("\x1b[38;2;"), "{stdout:?}");
+
+        let output = render_stdin_with_config(
+            "",
+            "syntax-theme",
+            &["--ansi", "--syntax-theme", "InspiredGitHub", "-"],
+            input,
+        );
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("\x1b[38;2;"), "{stdout:?}");
+    }
+
+    #[test]
+    fn unknown_syntax_theme() {
+        let output = run_cargo_mdcat(["--syntax-theme", "Solarised"]);
+        assert_eq!(output.status.code(), Some(2));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("Unknown syntax theme Solarised"),
+            "{stderr}"
+        );
+        assert!(stderr.contains("Solarized (dark)"), "{stderr}");
+    }
+
+    #[test]
+    fn tmux_passthrough() {
+        let render = |args: &[&str]| {
+            let mut child = cargo_mdcat()
+                .env("TMUX", "/tmp/tmux-1000/default,1234,0")
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            let mut stdin = child.stdin.take().unwrap();
+            writeln!(stdin, "[link](https://example.com)").unwrap();
+            drop(stdin);
+            let output = child.wait_with_output().unwrap();
+            assert!(output.status.success(), "{output:?}");
+            String::from_utf8(output.stdout).unwrap()
+        };
+        similar_asserts::assert_eq!(
+            render(&["--ansi", "-"]),
+            "\x1bPtmux;\x1b\x1b]8;;https://example.com/\x1b\x1b\\\x1b\\\x1b[34mlink\x1b[0m\x1bPtmux;\x1b\x1b]8;;\x1b\x1b\\\x1b\\\n\x1b[0m"
+        );
+        similar_asserts::assert_eq!(
+            render(&["--ansi", "--no-tmux-passthrough", "-"]),
+            "\x1b]8;;https://example.com/\x1b\\\x1b[34mlink\x1b[0m\x1b]8;;\x1b\\\n\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn preserve_line_breaks() {
+        let input = "One sentence.\nAnother sentence.\n";
+        let output = render_stdin_with_config("", "reflow", &["-"], input);
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "One sentence. Another sentence.\n"
+        );
+        let output =
+            render_stdin_with_config("", "preserve-breaks", &["--preserve-breaks", "-"], input);
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(String::from_utf8_lossy(&output.stdout), input);
+    }
+
+    #[test]
+    fn plain_if_stdout_is_not_a_terminal() {
+        let output = render_stdin_with_config("", "piped", &["-"], "**bold**\n");
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(String::from_utf8_lossy(&output.stdout), "bold\n");
+    }
+
+    #[test]
+    fn force_colour_if_stdout_is_not_a_terminal() {
+        let output =
+            render_stdin_with_config("", "force-colour", &["--force-colour", "-"], "**bold**\n");
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("\x1b[1mbold"), "{stdout:?}");
+    }
+
+    #[test]
+    fn strip_osc_keeps_colours() {
+        let output = render_stdin_with_config(
+            "",
+            "strip-osc",
+            &["--ansi", "--strip-osc", "-"],
+            "A **[link](https://example.com)**\n",
+        );
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(!stdout.contains("\x1b]"), "{stdout:?}");
+        assert!(stdout.contains("\x1b[1m"), "{stdout:?}");
+        assert!(stdout.contains("link"), "{stdout:?}");
+    }
+
+    #[test]
+    fn invalid_hostname() {
+        let output = run_cargo_mdcat(["--hostname", "foo bar"]);
+        assert_eq!(output.status.code(), Some(2));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Invalid hostname"), "{stderr}");
+    }
+
+    #[test]
+    fn render_line_range() {
+        let input = "# Heading\n\nFirst paragraph\n\n```\ncode\nmore code\n```\n";
+        let output =
+            render_stdin_with_config("", "lines", &["--no-colour", "--lines", "3-6", "-"], input);
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "First paragraph\n\n────────────────────\ncode\n────────────────────\n"
+        );
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            "Warning: -: Lines 3-6 cut a fenced code block\n"
+        );
+    }
+
+    #[test]
+    fn config_file_sets_defaults() {
+        let config = "columns = 10\n[markdown]\nstrikethrough = false\n";
+        let output = render_stdin_with_config(
+            config,
+            "defaults",
+            &["--no-colour", "-"],
+            "Hello ~~World~~ and all\n",
+        );
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "Hello\n~~World~~\nand all\n"
+        );
+    }
+
+    #[test]
+    fn arguments_override_config_file() {
+        let output = render_stdin_with_config(
+            "columns = 10\n",
+            "override",
+            &["--no-colour", "--columns", "40", "-"],
+            "Hello World and all\n",
+        );
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "Hello World and all\n"
+        );
+    }
+
+    #[test]
+    fn arguments_turn_off_config_file_options() {
+        let config = "local = true\n[markdown]\nmath = true\n";
+        let output = render_stdin_with_config(
+            config,
+            "turn-off-math",
+            &["--no-colour", "--no-math", "-"],
+            "Area: $\\pi r^2$\n",
+        );
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "Area: $\\pi r^2$\n"
+        );
+        let input = "![Image](https://example.com/image.png)\n";
+        let output =
+            render_stdin_with_config(config, "local-config", &["--list-resources", "-"], input);
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "denied https://example.com/image.png\n"
+        );
+        let output = render_stdin_with_config(
+            config,
+            "remote-argument",
+            &["--remote", "--list-resources", "-"],
+            input,
+        );
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "allowed https://example.com/image.png\n"
+        );
+    }
+
+    #[test]
+    fn syntax_theme_from_config_file() {
+        let input = "```rust\nfn main() {}\n```\n";
+        let output = render_stdin_with_config(
+            "syntax-theme = \"InspiredGitHub\"\n",
+            "config-theme",
+            &["--ansi", "-"],
+            input,
+        );
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("\x1b[38;2;"), "{stdout:?}");
+
+        let output = render_stdin_with_config(
+            "syntax-theme = \"Solarised\"\n",
+            "config-unknown-theme",
+            &["--ansi", "-"],
+            input,
+        );
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("Unknown syntax theme Solarised"),
+            "{stderr}"
+        );
+    }
+
+    #[test]
+    fn math_flag_enables_math() {
+        let input = "Area: $\\pi r^2$\n";
+        let output = render_stdin_with_config("", "no-math", &["--no-colour", "-"], input);
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "Area: $\\pi r^2$\n"
+        );
+        let output = render_stdin_with_config("", "math", &["--no-colour", "--math", "-"], input);
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(String::from_utf8_lossy(&output.stdout), "Area: \\pi r^2\n");
+    }
+
+    #[test]
+    fn autolinks_flag_turns_urls_into_links() {
+        let input = "Visit https://example.com/foo.\n";
+        let output = render_stdin_with_config("", "no-autolinks", &["--ansi", "-"], input);
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(!stdout.contains("\x1b]8;;"), "{stdout:?}");
+        let output =
+            render_stdin_with_config("", "autolinks", &["--ansi", "--autolinks", "-"], input);
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            stdout.contains("\x1b]8;;https://example.com/foo\x1b\\"),
+            "{stdout:?}"
+        );
+        // Autolinks never get a link reference
+        let output = render_stdin_with_config(
+            "",
+            "autolinks-dumb",
+            &["--no-colour", "--autolinks", "-"],
+            input,
+        );
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "Visit https://example.com/foo.\n"
+        );
+    }
+
+    #[test]
+    fn front_matter_flags() {
+        let input = "---\ntitle: A document\n---\n\nSome text\n";
+        let output = render_stdin_with_config(
+            "",
+            "front-matter",
+            &["--no-colour", "--front-matter", "-"],
+            input,
+        );
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(String::from_utf8_lossy(&output.stdout), "Some text\n");
+        let output = render_stdin_with_config(
+            "[markdown]\nfront-matter = true\n",
+            "front-matter-config",
+            &["--no-colour", "-"],
+            input,
+        );
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(String::from_utf8_lossy(&output.stdout), "Some text\n");
+        let output = render_stdin_with_config(
+            "",
+            "front-matter-title",
+            &["--no-colour", "--front-matter-title", "-"],
+            input,
+        );
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "\u{2504}A document\n\nSome text\n"
+        );
+    }
+
+    #[test]
+    fn diagram_languages() {
+        let input = "```mermaid\ngraph TD\n```\n";
+        let output = render_stdin_with_config(
+            "",
+            "diagram-languages",
+            &["--no-colour", "--diagram-languages=plantuml,mermaid", "-"],
+            input,
+        );
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "────────────────────\n\u{2301} mermaid diagram (not rendered)\ngraph TD\n────────────────────\n"
+        );
+    }
+
+    #[test]
+    fn link_style() {
+        let input = "A [link](https://example.com) and <https://example.org>.\n";
+        let inline = render_stdin_with_config(
+            "",
+            "link-style-inline",
+            &["--no-colour", "--link-style", "inline", "-"],
+            input,
+        );
+        assert!(inline.status.success(), "{inline:?}");
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&inline.stdout),
+            "A link (https://example.com) and https://example.org.\n"
+        );
+        let hidden = render_stdin_with_config(
+            "",
+            "link-style-hidden",
+            &["--no-colour", "--link-style=hidden", "-"],
+            input,
+        );
+        assert!(hidden.status.success(), "{hidden:?}");
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&hidden.stdout),
+            "A link and https://example.org.\n"
+        );
+    }
+
+    #[test]
+    fn list_resources() {
+        let input = "![Image](https://example.com/image.png) [link](http://example.com) <jane@example.com>\n\n[Unsupported](gopher://example.com)\n";
+        let output = render_stdin_with_config(
+            "",
+            "list-resources",
+            &["--https-only", "--list-resources", "-"],
+            input,
+        );
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "allowed https://example.com/image.png\ndenied http://example.com/\ndenied mailto:jane@example.com\ndenied gopher://example.com\n"
+        );
+        let output = render_stdin_with_config(
+            "",
+            "list-resources-local",
+            &["--local", "--list-resources", "-"],
+            "![Image](image.png)\n",
+        );
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.starts_with("allowed file://"), "{stdout}");
+        assert!(stdout.ends_with("/image.png\n"), "{stdout}");
+    }
+
+    #[test]
+    fn render_dumped_events() {
+        let input = "# Heading\n\nSome *text*.\n";
+        let dumped = render_stdin_with_config("", "dump-events", &["--dump-events", "-"], input);
+        assert!(dumped.status.success(), "{dumped:?}");
+        let output = render_stdin_with_config(
+            "",
+            "events-json",
+            &["--no-colour", "--events-json", "-"],
+            &String::from_utf8(dumped.stdout).unwrap(),
+        );
+        assert!(output.status.success(), "{output:?}");
+        let rendered = render_stdin_with_config("", "render", &["--no-colour", "-"], input);
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&rendered.stdout)
+        );
+    }
+
+    #[test]
+    fn reject_impossible_events() {
+        for (name, events) in [
+            ("text", r#"[{"Text":"hi"}]"#),
+            ("end", r#"[{"End":"Paragraph"}]"#),
+            ("start", r#"[{"Start":"Paragraph"}]"#),
+            ("mismatch", r#"[{"Start":"Paragraph"},{"End":"Emphasis"}]"#),
+        ] {
+            let output = render_stdin_with_config(
+                "",
+                &format!("impossible-events-{name}"),
+                &["--events-json", "-"],
+                events,
+            );
+            assert_eq!(output.status.code(), Some(1), "{output:?}");
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(stderr.contains("Error"), "{stderr}");
+            assert!(!stderr.contains("panicked"), "{stderr}");
+        }
+    }
+
+    #[test]
+    fn render_table_of_contents() {
+        let input = "# One\n\nText\n\n## Two\n\n* Item\n\n## Three\n";
+        let output = render_stdin_with_config("", "toc", &["--toc", "-"], input);
+        assert!(output.status.success(), "{output:?}");
+        similar_asserts::assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "• One\n  • Two\n  • Three\n"
+        );
+    }
+
+    #[test]
+    fn iterm2_links_to_broken_images() {
+        let mut child = cargo_mdcat()
+            .env_remove("TERM")
+            .env_remove("KONSOLE_VERSION")
+            .env_remove("TERMINOLOGY")
+            .env("TERM_PROGRAM", "iTerm.app")
+            .args(["--force-colour", "--hostname", "example.com", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        writeln!(stdin, "![Broken](does-not-exist.png)").unwrap();
+        drop(stdin);
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        // The image fails to render, so mdcat must link to it with OSC 8 instead of writing a
+        // link reference.
+        assert!(
+            stdout.starts_with("\x1b]8;;file://example.com/"),
+            "{stdout:?}"
+        );
+        assert!(
+            stdout.contains("/does-not-exist.png\x1b\\\x1b[35mBroken\x1b[0m\x1b]8;;\x1b\\"),
+            "{stdout:?}"
+        );
+        assert!(!stdout.contains("[1]"), "{stdout:?}");
+    }
+
+    #[test]
+    fn quiet_about_expected_image_fallbacks() {
+        let mut child = cargo_mdcat()
+            .env("MDCAT_LOG", "info")
+            .arg("-")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        writeln!(stdin, "![Logo](logo.png)\n\n![Broken](http://[invalid)").unwrap();
+        drop(stdin);
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // Without colours there's no image support, so the fallback for the logo is expected,
+        // but the broken URL is not.
+        assert!(!stderr.contains("logo.png"), "{stderr}");
+        assert!(
+            stderr.contains("Rendering image http://[invalid"),
+            "{stderr}"
+        );
+    }
+
+    #[test]
+    fn invalid_config_file() {
+        let output = render_stdin_with_config("colums = 10\n", "invalid", &["-"], "Hello\n");
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("Error: Failed to parse configuration") && stderr.contains("colums"),
+            "{stderr}"
+        );
+        assert!(output.stdout.is_empty());
+    }
+
     #[test]
     fn ignore_broken_pipe() {
         let mut child = cargo_mdcat()